@@ -0,0 +1,29 @@
+// Server di rendezvous e relay per p2pchat, vedi relay/relay_server.rs
+use std::error::Error;
+use std::net::{Ipv6Addr, SocketAddr};
+
+use clap::Parser;
+use p2pchat::relay::relay_server::serve;
+use p2pchat::relay::state_relay::DEFAULT_RELAY_PORT;
+use tokio::net::TcpListener;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "P2P Chat rendezvous and relay server", long_about = None)]
+struct Cli {
+    #[arg(short = 'p', long = "port", default_value_t = DEFAULT_RELAY_PORT)]
+    port: u16,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let args = Cli::parse();
+
+    let listener = match TcpListener::bind(SocketAddr::from((Ipv6Addr::UNSPECIFIED, args.port))).await {
+        Ok(l) => l,
+        Err(_) => TcpListener::bind(SocketAddr::from(([0, 0, 0, 0], args.port))).await?,
+    };
+    println!("Relay listening on {}", listener.local_addr()?);
+
+    serve(listener).await?;
+    Ok(())
+}
@@ -0,0 +1,24 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::crypto::identity::challenge_payload;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Prova di conoscere la password della stanza senza mandarla: HMAC sul nonce scelto da chi accetta,
+// quindi una risposta intercettata non serve su un'altra connessione
+pub fn password_proof(password: &str, nonce: &str, id: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(password.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(&challenge_payload(nonce, id));
+    hex::encode(mac.finalize().into_bytes())
+}
+
+pub fn check_password_proof(password: &str, nonce: &str, id: &str, proof: &str) -> bool {
+    let Ok(proof) = hex::decode(proof) else {
+        return false;
+    };
+
+    let mut mac = HmacSha256::new_from_slice(password.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(&challenge_payload(nonce, id));
+    mac.verify_slice(&proof).is_ok()
+}
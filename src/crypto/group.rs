@@ -0,0 +1,29 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use chacha20poly1305::aead::{Aead, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce};
+use rand::random;
+
+// ChaCha20-Poly1305 con nonce casuale, che viaggia in testa al ciphertext
+pub fn seal(key: &[u8; 32], plaintext: &[u8], aad: &[u8]) -> String {
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    let nonce = random::<[u8; 12]>();
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: plaintext, aad })
+        .expect("Encryption failed");
+
+    STANDARD.encode([nonce.as_slice(), &ciphertext].concat())
+}
+
+pub fn open(key: &[u8; 32], data: &str, aad: &[u8]) -> Option<Vec<u8>> {
+    let data = STANDARD.decode(data).ok()?;
+    if data.len() < 12 {
+        return None;
+    }
+
+    let (nonce, ciphertext) = data.split_at(12);
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    cipher
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad })
+        .ok()
+}
@@ -0,0 +1,103 @@
+use std::path::Path;
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand::random;
+use sha2::{Digest, Sha256};
+
+// Chiave ed25519 del nodo: la pubblica viaggia in Member.pubkey, la privata resta qui
+pub struct Identity {
+    signing_key: SigningKey,
+}
+
+impl Identity {
+    pub fn generate() -> Self {
+        Self {
+            signing_key: SigningKey::from_bytes(&random::<[u8; 32]>()),
+        }
+    }
+
+    // Con --key-file la chiave sopravvive ai riavvii, così gli inviti già condivisi restano validi
+    pub fn load_or_create(path: &Path) -> std::io::Result<Self> {
+        if path.exists() {
+            let hex_key = std::fs::read_to_string(path)?;
+            let bytes: [u8; 32] = hex::decode(hex_key.trim())
+                .ok()
+                .and_then(|b| b.try_into().ok())
+                .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid key file"))?;
+
+            return Ok(Self {
+                signing_key: SigningKey::from_bytes(&bytes),
+            });
+        }
+
+        let identity = Self::generate();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, hex::encode(identity.signing_key.to_bytes()))?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+        }
+
+        Ok(identity)
+    }
+
+    pub fn public_key(&self) -> String {
+        hex::encode(self.signing_key.verifying_key().to_bytes())
+    }
+
+    pub fn sign(&self, data: &[u8]) -> String {
+        hex::encode(self.signing_key.sign(data).to_bytes())
+    }
+
+    // X25519 con le stesse chiavi ed25519 convertite in forma Montgomery: la chiave
+    // derivata è uguale dai due lati e serve a cifrare le chiavi di gruppo per un solo membro
+    pub fn shared_key(&self, public_key: &str) -> Option<[u8; 32]> {
+        let remote = hex::decode(public_key)
+            .ok()
+            .and_then(|b| <[u8; 32]>::try_from(b).ok())
+            .and_then(|b| VerifyingKey::from_bytes(&b).ok())
+            .filter(|k| !k.is_weak())?; // con un punto di ordine piccolo il segreto sarebbe prevedibile
+
+        let shared = remote.to_montgomery().mul_clamped(self.signing_key.to_scalar_bytes());
+
+        let mut hasher = Sha256::new();
+        hasher.update(b"p2pchat-group-key-wrap");
+        hasher.update(shared.as_bytes());
+        Some(hasher.finalize().into())
+    }
+}
+
+pub fn verify(public_key: &str, data: &[u8], signature: &str) -> bool {
+    let Some(key) = hex::decode(public_key)
+        .ok()
+        .and_then(|b| <[u8; 32]>::try_from(b).ok())
+        .and_then(|b| VerifyingKey::from_bytes(&b).ok())
+    else {
+        return false;
+    };
+
+    let Some(signature) = hex::decode(signature)
+        .ok()
+        .and_then(|b| <[u8; 64]>::try_from(b).ok())
+        .map(|b| Signature::from_bytes(&b))
+    else {
+        return false;
+    };
+
+    key.verify(data, &signature).is_ok()
+}
+
+// Impronta corta della chiave pubblica, da confrontare a occhio o da mettere negli inviti
+pub fn fingerprint(public_key: &str) -> String {
+    let digest = Sha256::digest(public_key.as_bytes());
+    hex::encode(&digest[..16])
+}
+
+// Quello che l'host firma per dimostrare di possedere la chiave dell'invito
+pub fn challenge_payload(nonce: &str, id: &str) -> Vec<u8> {
+    format!("p2pchat-challenge:{}:{}", nonce, id).into_bytes()
+}
@@ -0,0 +1,87 @@
+use std::path::Path;
+
+use serde_json::{Value, json};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
+
+use crate::daemon::rpc::{MemberInfo, Notification, Request, Response};
+use crate::state::state_events::ChatEvent;
+use crate::ui::handle_format::{sanitize, sanitize_line};
+use crate::ui::handle_output::say;
+
+// Client minimo del daemon: mostra gli eventi della stanza e manda quello che si scrive.
+// /members, /connect host:porta e /quit sono gestiti qui, il resto diventa un "send"
+pub async fn attach_main(socket: &Path) -> Result<(), String> {
+    let stream = UnixStream::connect(socket)
+        .await
+        .map_err(|e| format!("cannot reach the daemon on {}: {}", socket.display(), e))?;
+    let (reader, mut writer) = stream.into_split();
+
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(reader).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            print_line(&line);
+        }
+        say!("Daemon closed the connection");
+        std::process::exit(0);
+    });
+
+    say!("Attached to {}, /quit to detach", socket.display());
+
+    let mut next_id = 1;
+    let mut request = |method: &str, params: Value| {
+        let line = serde_json::to_string(&Request::new(next_id, method, params)).expect("Failed to serialize");
+        next_id += 1;
+        line + "\n"
+    };
+
+    let mut pending = vec![request("subscribe", Value::Null)];
+    let mut stdin = BufReader::new(tokio::io::stdin()).lines();
+
+    loop {
+        for line in pending.drain(..) {
+            writer.write_all(line.as_bytes()).await.map_err(|e| e.to_string())?;
+        }
+
+        let Ok(Some(line)) = stdin.next_line().await else {
+            return Ok(());
+        };
+
+        let mut parts = line.splitn(2, ' ');
+        match (parts.next().unwrap_or(""), parts.next()) {
+            ("/quit", _) => return Ok(()),
+            ("/members", _) => pending.push(request("members", Value::Null)),
+            ("/connect", Some(address)) => pending.push(request("connect", json!({ "address": address.trim() }))),
+            (command, _) if command.starts_with('/') => say!("Attached commands: /members, /connect host:port, /quit"),
+            _ if line.trim().is_empty() => {}
+            _ => pending.push(request("send", json!({ "text": line }))),
+        }
+    }
+}
+
+fn print_line(line: &str) {
+    if let Ok(notification) = serde_json::from_str::<Notification>(line) {
+        print_event(&notification.params);
+        return;
+    }
+
+    let Ok(response) = serde_json::from_str::<Response>(line) else {
+        return;
+    };
+    if let Some(error) = response.error {
+        say!("Error: {}", sanitize_line(&error.message));
+    } else if let Some(members) = response.result.and_then(|r| serde_json::from_value::<Vec<MemberInfo>>(r).ok()) {
+        for m in members {
+            let you = if m.you { " (you)" } else { "" };
+            say!("  {}{} - {}", sanitize_line(&m.username), you, m.status);
+        }
+    }
+}
+
+fn print_event(event: &ChatEvent) {
+    match event {
+        ChatEvent::Message { sender, text, .. } => say!("[{}]: {}", sanitize_line(sender), sanitize(text)),
+        ChatEvent::MemberJoined { username, .. } => say!("* {} joined", sanitize_line(username)),
+        ChatEvent::MemberLeft { username, .. } => say!("* {} left", sanitize_line(username)),
+    }
+}
@@ -0,0 +1,88 @@
+use std::collections::VecDeque;
+use std::path::Path;
+
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::UnixStream;
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+
+use crate::daemon::rpc::{Notification, Request, Response};
+use crate::state::state_events::ChatEvent;
+
+// Una richiesta alla volta; gli eventi che arrivano mentre si aspetta una risposta restano in coda
+pub struct RpcClient {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+    next_id: u64,
+    events: VecDeque<ChatEvent>,
+}
+
+impl RpcClient {
+    pub async fn connect(socket: &Path) -> Result<Self, String> {
+        let stream = UnixStream::connect(socket)
+            .await
+            .map_err(|e| format!("cannot reach the daemon on {}: {}", socket.display(), e))?;
+        let (reader, writer) = stream.into_split();
+
+        Ok(Self {
+            lines: BufReader::new(reader).lines(),
+            writer,
+            next_id: 1,
+            events: VecDeque::new(),
+        })
+    }
+
+    pub async fn call(&mut self, method: &str, params: Value) -> Result<Value, String> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let line = serde_json::to_string(&Request::new(id, method, params)).expect("Failed to serialize") + "\n";
+        self.writer
+            .write_all(line.as_bytes())
+            .await
+            .map_err(|e| format!("daemon connection lost: {}", e))?;
+
+        loop {
+            let line = self.read_line().await?;
+            if let Ok(notification) = serde_json::from_str::<Notification>(&line) {
+                self.events.push_back(notification.params);
+                continue;
+            }
+
+            let Ok(response) = serde_json::from_str::<Response>(&line) else {
+                continue;
+            };
+            if response.id != id {
+                continue;
+            }
+
+            return match (response.result, response.error) {
+                (_, Some(error)) => Err(error.message),
+                (Some(result), None) => Ok(result),
+                (None, None) => Ok(Value::Null),
+            };
+        }
+    }
+
+    // None quando il daemon chiude la connessione
+    pub async fn next_event(&mut self) -> Option<ChatEvent> {
+        if let Some(event) = self.events.pop_front() {
+            return Some(event);
+        }
+
+        loop {
+            let line = self.read_line().await.ok()?;
+            if let Ok(notification) = serde_json::from_str::<Notification>(&line) {
+                return Some(notification.params);
+            }
+        }
+    }
+
+    async fn read_line(&mut self) -> Result<String, String> {
+        match self.lines.next_line().await {
+            Ok(Some(line)) => Ok(line),
+            Ok(None) => Err("the daemon closed the connection".to_string()),
+            Err(e) => Err(format!("daemon connection lost: {}", e)),
+        }
+    }
+}
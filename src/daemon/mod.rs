@@ -0,0 +1,4 @@
+pub mod attach;
+pub mod client;
+pub mod rpc;
+pub mod server;
@@ -0,0 +1,144 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::state::state_events::ChatEvent;
+
+// Codici di errore di JSON-RPC 2.0, più uno per le operazioni fallite (vedi docs/daemon-protocol.md)
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const FAILED: i64 = -32000;
+
+// Senza --socket: $XDG_RUNTIME_DIR/p2pchat/<stanza>.sock, o p2pchat-<utente>/ nella cartella
+// temporanea. La cartella la crea il daemon, accessibile solo a noi
+pub fn default_socket(room: &str) -> PathBuf {
+    let dir = match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(runtime) => PathBuf::from(runtime).join("p2pchat"),
+        None => {
+            let user = std::env::var("USER").unwrap_or_else(|_| "user".to_string());
+            std::env::temp_dir().join(format!("p2pchat-{}", safe_name(&user)))
+        }
+    };
+    dir.join(format!("{}.sock", safe_name(room)))
+}
+
+fn safe_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect()
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Request {
+    pub jsonrpc: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<Value>, // senza id è una notifica: nessuna risposta
+    pub method: String,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub params: Value,
+}
+
+impl Request {
+    pub fn new(id: u64, method: &str, params: Value) -> Self {
+        Self {
+            jsonrpc: "2.0".to_string(),
+            id: Some(Value::from(id)),
+            method: method.to_string(),
+            params,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Response {
+    pub jsonrpc: String,
+    pub id: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+impl Response {
+    pub fn ok(id: Value, result: Value) -> Self {
+        Self {
+            jsonrpc: "2.0".to_string(),
+            id,
+            result: Some(result),
+            error: None,
+        }
+    }
+
+    pub fn err(id: Value, code: i64, message: impl Into<String>) -> Self {
+        Self {
+            jsonrpc: "2.0".to_string(),
+            id,
+            result: None,
+            error: Some(RpcError {
+                code,
+                message: message.into(),
+            }),
+        }
+    }
+}
+
+// Dopo "subscribe" il daemon manda gli eventi della stanza come notifiche "event"
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Notification {
+    pub jsonrpc: String,
+    pub method: String,
+    pub params: ChatEvent,
+}
+
+impl Notification {
+    pub fn event(event: ChatEvent) -> Self {
+        Self {
+            jsonrpc: "2.0".to_string(),
+            method: "event".to_string(),
+            params: event,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SendParams {
+    pub text: String,
+    #[serde(default)]
+    pub reply_to: Option<String>,
+}
+
+// O un indirizzo host:porta o un invito, come -c e -j
+#[derive(Deserialize, Debug)]
+pub struct ConnectParams {
+    #[serde(default)]
+    pub address: Option<String>,
+    #[serde(default)]
+    pub invite: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct HistoryParams {
+    #[serde(default)]
+    pub count: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MemberInfo {
+    pub id: String,
+    pub username: String,
+    pub fingerprint: String,
+    pub status: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status_message: Option<String>,
+    pub owner: bool,
+    pub you: bool,
+}
@@ -0,0 +1,279 @@
+use std::fs::DirBuilder;
+use std::io;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde_json::{Value, json};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream, lookup_host};
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{Mutex, mpsc};
+
+use crate::crypto::identity::fingerprint;
+use crate::daemon::rpc::{
+    ConnectParams, FAILED, HistoryParams, INVALID_PARAMS, INVALID_REQUEST, METHOD_NOT_FOUND, MemberInfo, Notification,
+    PARSE_ERROR, Request, Response, SendParams,
+};
+use crate::network::connection::{connection_main, join_invite};
+use crate::state::state_chat::{Chat, Connections, Member};
+use crate::state::state_events::ChatEvent;
+use crate::state::state_invite::Invite;
+use crate::state::state_node::Node;
+use crate::ui::handle_input::send_message;
+use crate::ui::handle_output::say;
+
+const DEFAULT_HISTORY: usize = 50;
+
+// Tutto quello che serve per rispondere alle richieste, clonato per ogni client
+#[derive(Clone)]
+struct Daemon {
+    chat: Arc<Mutex<Chat>>,
+    myself: Arc<Member>,
+    node: Node,
+    connections: Connections,
+}
+
+// Il nodo gira senza terminale: resta nella stanza finché non riceve SIGTERM o ctrl-c,
+// e si comanda dal socket (p2pchat attach, script...)
+pub async fn daemon_main(
+    socket: PathBuf,
+    chat: Arc<Mutex<Chat>>,
+    myself: Arc<Member>,
+    node: Node,
+    connections: Connections,
+) -> io::Result<()> {
+    let listener = bind_socket(&socket).await?;
+    say!("Control socket: {}", socket.display());
+
+    // chiudere il terminale da cui è partito non deve far uscire il nodo dalla stanza
+    let mut hangup = signal(SignalKind::hangup())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+
+    let daemon = Daemon {
+        chat,
+        myself,
+        node,
+        connections,
+    };
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => {
+                    tokio::spawn(serve_client(stream, daemon.clone()));
+                }
+                Err(e) => say!("Control socket error: {}", e),
+            },
+            _ = hangup.recv() => {}
+            _ = terminate.recv() => break,
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+
+    let _ = std::fs::remove_file(&socket);
+    say!("Daemon stopped");
+    Ok(())
+}
+
+// Un socket rimasto da un daemon morto si sostituisce; se risponde qualcuno invece c'è già un daemon.
+// Chi può scrivere sul socket parla a nome nostro nella stanza: sta in una cartella solo nostra,
+// così nessuno può collegarsi nell'attimo tra il bind e il chmod
+async fn bind_socket(path: &Path) -> io::Result<UnixListener> {
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        private_dir(dir)?;
+    }
+
+    if path.exists() {
+        if UnixStream::connect(path).await.is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("a daemon is already listening on {}", path.display()),
+            ));
+        }
+        std::fs::remove_file(path)?;
+    }
+
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+// Creata 0700 se manca; se c'è già non deve essere accessibile ad altri. Una cartella 0700
+// di un altro utente non si può usare comunque: il bind fallisce
+fn private_dir(dir: &Path) -> io::Result<()> {
+    DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+
+    let meta = std::fs::symlink_metadata(dir)?;
+    if !meta.is_dir() || meta.permissions().mode() & 0o077 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} must be a directory only you can access (chmod 700)", dir.display()),
+        ));
+    }
+    Ok(())
+}
+
+// Una richiesta JSON per riga, una risposta per riga; le notifiche degli eventi si mescolano alle risposte
+async fn serve_client(stream: UnixStream, daemon: Daemon) {
+    let (reader, mut writer) = stream.into_split();
+    let (out_tx, mut out_rx) = mpsc::unbounded_channel::<String>();
+
+    tokio::spawn(async move {
+        while let Some(line) = out_rx.recv().await {
+            if writer.write_all(line.as_bytes()).await.is_err() || writer.write_all(b"\n").await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut lines = BufReader::new(reader).lines();
+    let mut subscribed = false;
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let request: Request = match serde_json::from_str(&line) {
+            Ok(request) => request,
+            Err(e) => {
+                let response = Response::err(Value::Null, PARSE_ERROR, format!("invalid JSON: {}", e));
+                let _ = out_tx.send(serde_json::to_string(&response).expect("Failed to serialize"));
+                continue;
+            }
+        };
+
+        let id = request.id.clone();
+        let result = if request.jsonrpc != "2.0" {
+            Err((INVALID_REQUEST, "jsonrpc must be \"2.0\"".to_string()))
+        } else if request.method == "subscribe" {
+            if !subscribed {
+                subscribed = true;
+                tokio::spawn(forward_events(daemon.node.clone(), out_tx.clone()));
+            }
+            Ok(json!({ "subscribed": true }))
+        } else {
+            call(&request.method, request.params, &daemon).await
+        };
+
+        // senza id è una notifica JSON-RPC: si esegue e basta
+        let Some(id) = id else {
+            continue;
+        };
+        let response = match result {
+            Ok(value) => Response::ok(id, value),
+            Err((code, message)) => Response::err(id, code, message),
+        };
+        if out_tx.send(serde_json::to_string(&response).expect("Failed to serialize")).is_err() {
+            break;
+        }
+    }
+}
+
+async fn forward_events(node: Node, out_tx: mpsc::UnboundedSender<String>) {
+    let mut events = node.events.subscribe();
+
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            // client troppo lento: perde qualche evento ma resta iscritto
+            Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => break,
+        };
+
+        let line = serde_json::to_string(&Notification::event(event)).expect("Failed to serialize");
+        if out_tx.send(line).is_err() {
+            break;
+        }
+    }
+}
+
+fn params<T: serde::de::DeserializeOwned + Default>(params: Value) -> Result<T, (i64, String)> {
+    if params.is_null() {
+        return Ok(T::default());
+    }
+    serde_json::from_value(params).map_err(|e| (INVALID_PARAMS, e.to_string()))
+}
+
+async fn call(method: &str, raw: Value, daemon: &Daemon) -> Result<Value, (i64, String)> {
+    match method {
+        "send" => {
+            let p: SendParams = serde_json::from_value(raw).map_err(|e| (INVALID_PARAMS, e.to_string()))?;
+            if p.text.trim().is_empty() {
+                return Err((INVALID_PARAMS, "text is empty".to_string()));
+            }
+            if let Some(parent) = &p.reply_to
+                && !daemon.chat.lock().await.all_messages.iter().any(|m| &m.id == parent)
+            {
+                return Err((INVALID_PARAMS, format!("no message {} to reply to", parent)));
+            }
+
+            let d = daemon;
+            let id = send_message(p.text, p.reply_to, &d.chat, &d.myself, &d.node, &d.connections).await;
+            Ok(json!({ "id": id }))
+        }
+        "members" => Ok(json!(member_infos(&*daemon.chat.lock().await, &daemon.myself.id))),
+        "history" => {
+            let p: HistoryParams = params(raw)?;
+            let count = p.count.unwrap_or(DEFAULT_HISTORY);
+            Ok(json!(history(&*daemon.chat.lock().await, &daemon.node, count).await))
+        }
+        "connect" => {
+            let p: ConnectParams = serde_json::from_value(raw).map_err(|e| (INVALID_PARAMS, e.to_string()))?;
+            connect(p, daemon).await.map_err(|e| (FAILED, e))?;
+            Ok(json!({ "connected": true }))
+        }
+        _ => Err((METHOD_NOT_FOUND, format!("unknown method {}", method))),
+    }
+}
+
+pub fn member_infos(chat: &Chat, my_id: &str) -> Vec<MemberInfo> {
+    chat.members
+        .iter()
+        .map(|m| MemberInfo {
+            id: m.id.clone(),
+            username: chat.display_name(Some(&m.id), &m.username),
+            fingerprint: fingerprint(&m.pubkey),
+            status: m.status.to_string(),
+            status_message: m.status_message.clone(),
+            owner: chat.access.owner.as_ref() == Some(&m.pubkey),
+            you: m.id == my_id,
+        })
+        .collect()
+}
+
+// Gli ultimi count messaggi in chiaro, nello stesso formato degli eventi
+pub async fn history(chat: &Chat, node: &Node, count: usize) -> Vec<ChatEvent> {
+    let start = chat.all_messages.len().saturating_sub(count);
+
+    let mut events = vec![];
+    for message in &chat.all_messages[start..] {
+        let mut plain = node.keyring.reveal(message).await;
+        chat.label(&mut plain);
+        events.push(ChatEvent::message(&plain));
+    }
+    events
+}
+
+async fn connect(p: ConnectParams, d: &Daemon) -> Result<(), String> {
+    match (p.address, p.invite) {
+        (Some(address), None) => {
+            let addrs: Vec<_> = lookup_host(address.as_str())
+                .await
+                .map_err(|e| format!("cannot resolve {}: {}", address, e))?
+                .collect();
+            connection_main(addrs, d.myself.clone(), d.chat.clone(), d.node.clone(), d.connections.clone())
+                .await
+                .map_err(|e| e.to_string())
+        }
+        (None, Some(token)) => {
+            let invite = Invite::decode(&token).map_err(|e| format!("invalid invite: {}", e))?;
+            join_invite(invite, d.myself.clone(), d.chat.clone(), d.node.clone(), d.connections.clone())
+                .await
+                .map_err(|e| e.to_string())
+        }
+        _ => Err("give either address (host:port) or invite".to_string()),
+    }
+}
@@ -0,0 +1,47 @@
+use std::sync::Arc;
+use std::time::Instant;
+
+use tokio::sync::{Mutex, Notify};
+use tokio::time::sleep;
+
+use crate::discovery::multicast::MulticastSender;
+use crate::state::state_chat::{Chat, Member};
+use crate::state::state_discovery::{ANNOUNCE_INTERVAL, ANNOUNCE_MIN_GAP, Announcement, DiscoveryPacket};
+
+// Annuncia la nostra presenza ogni ANNOUNCE_INTERVAL, oppure prima se qualcuno manda una Discovery.
+// Più Discovery ravvicinate producono un solo annuncio (Notify tiene al massimo un permesso)
+// e tra due annunci passa sempre almeno ANNOUNCE_MIN_GAP
+pub async fn announce_discovery(
+    chat: Arc<Mutex<Chat>>,
+    myself: Arc<Member>,
+    announce: Arc<Notify>,
+    secret: Option<String>,
+) {
+    let sender = MulticastSender::new(secret).await;
+
+    loop {
+        let (room, username) = {
+            let chat_lock = chat.lock().await;
+            (chat_lock.room.clone(), chat_lock.me(&myself).username)
+        };
+        let packet = DiscoveryPacket::Announce(Announcement {
+            id: myself.id.clone(),
+            username,
+            room,
+            addrs: myself.addrs.clone(),
+        });
+
+        sender.send(&packet).await;
+        let last_announce = Instant::now();
+
+        tokio::select! {
+            _ = sleep(ANNOUNCE_INTERVAL) => {}
+            _ = announce.notified() => {}
+        }
+
+        let elapsed = last_announce.elapsed();
+        if elapsed < ANNOUNCE_MIN_GAP {
+            sleep(ANNOUNCE_MIN_GAP - elapsed).await;
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use std::sync::Arc;
+
+use clap::ValueEnum;
+use tokio::sync::{Mutex, mpsc};
+
+use crate::discovery::mdns_backend::MdnsBackend;
+use crate::discovery::multicast_backend::MulticastBackend;
+use crate::state::state_chat::{Chat, Member};
+use crate::state::state_discovery::{Announcement, DiscoveredPeers};
+use crate::ui::handle_output::say;
+
+// Quello che serve a un backend per annunciarsi e riempire la tabella dei peer.
+// I peer nuovi vanno anche su tx, da cui main fa l'auto-join in modalità -d
+#[derive(Clone)]
+pub struct DiscoveryContext {
+    pub chat: Arc<Mutex<Chat>>,
+    pub myself: Arc<Member>,
+    pub peers: DiscoveredPeers,
+    pub tx: mpsc::UnboundedSender<Announcement>,
+    pub secret: Option<String>,
+}
+
+pub trait DiscoveryBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // Avvia in background annunci e ascolto, resta attivo per tutta la sessione
+    fn start(&self, ctx: DiscoveryContext);
+
+    // Ricerca attiva all'avvio con -d, per chi non aspetta il prossimo annuncio periodico
+    fn probe(&self, _ctx: &DiscoveryContext) {}
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum BackendKind {
+    Multicast,
+    Mdns,
+    Both,
+}
+
+pub fn backends(kind: BackendKind) -> Vec<Box<dyn DiscoveryBackend>> {
+    let mut backends: Vec<Box<dyn DiscoveryBackend>> = vec![];
+
+    if matches!(kind, BackendKind::Multicast | BackendKind::Both) {
+        backends.push(Box::new(MulticastBackend::new()));
+    }
+
+    if matches!(kind, BackendKind::Mdns | BackendKind::Both) {
+        match MdnsBackend::new() {
+            Ok(mdns) => backends.push(Box::new(mdns)),
+            Err(e) => say!("mDNS unavailable: {}", e),
+        }
+    }
+
+    backends
+}
@@ -0,0 +1,47 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::state::state_discovery::{DiscoveryFrame, DiscoveryPacket, MAX_FRAME_AGE};
+use crate::ui::handle_input::get_timestamp;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Con un segreto condiviso ogni frame porta un HMAC-SHA256 su pacchetto e timestamp,
+// così solo chi conosce il segreto può farsi trovare o rispondere
+pub fn seal(packet: DiscoveryPacket, secret: Option<&str>) -> DiscoveryFrame {
+    let timestamp = get_timestamp();
+    let mac = secret.map(|secret| hex::encode(compute_mac(secret, &packet, timestamp).finalize().into_bytes()));
+
+    DiscoveryFrame {
+        packet,
+        timestamp,
+        mac,
+    }
+}
+
+// Senza segreto si accettano solo frame senza mac: quelli con mac appartengono a un altro gruppo
+pub fn open(frame: DiscoveryFrame, secret: Option<&str>) -> Option<DiscoveryPacket> {
+    match (secret, frame.mac) {
+        (None, None) => Some(frame.packet),
+        (Some(secret), Some(mac)) => {
+            if get_timestamp().abs_diff(frame.timestamp) > MAX_FRAME_AGE {
+                return None; // replay di un frame vecchio
+            }
+
+            let mac = hex::decode(mac).ok()?;
+            compute_mac(secret, &frame.packet, frame.timestamp)
+                .verify_slice(&mac)
+                .ok()?;
+
+            Some(frame.packet)
+        }
+        _ => None,
+    }
+}
+
+fn compute_mac(secret: &str, packet: &DiscoveryPacket, timestamp: u64) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(&serde_json::to_vec(packet).expect("Failed to serialize"));
+    mac.update(&timestamp.to_be_bytes());
+    mac
+}
@@ -1,17 +1,12 @@
+use crate::discovery::multicast::MulticastSender;
 use crate::state::state_discovery::DiscoveryPacket;
-use tokio::net::UdpSocket;
 
-pub async fn find_discovery(id: String) {
+pub async fn find_discovery(id: String, secret: Option<String>) {
     let disc_packet = DiscoveryPacket::Discovery(id);
-    let bytes = serde_json::to_vec(&disc_packet).unwrap();
-
-    let mult_addr = "239.255.42.99:9000";
-
-    let udp_socket = UdpSocket::bind("0.0.0.0:0").await.unwrap();
+    let sender = MulticastSender::new(secret).await;
 
     for _i in 0..3 {
-        udp_socket.set_broadcast(true).unwrap();
-        udp_socket.send_to(&bytes, mult_addr).await.unwrap();
+        sender.send(&disc_packet).await;
 
         tokio::time::sleep(std::time::Duration::from_secs(1)).await;
     }
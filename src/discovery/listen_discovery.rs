@@ -1,43 +1,96 @@
 use socket2::{Domain, Protocol, Socket, Type};
-use std::net::{Ipv4Addr, SocketAddr};
-use tokio::{net::UdpSocket, sync::mpsc};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use tokio::{
+    net::UdpSocket,
+    sync::{Notify, mpsc},
+};
 
+use crate::ui::handle_output::say;
 use crate::{
-    discovery::handle_packet_discovery::handle_packet_discovery,
-    state::state_discovery::DiscoveryPacket,
+    discovery::{discovery_auth::open, handle_packet_discovery::handle_packet_discovery},
+    state::state_discovery::{
+        Announcement, DISCOVERY_PORT, DiscoveredPeers, DiscoveryFrame, MULTICAST_V4, MULTICAST_V6,
+    },
 };
 
-pub async fn listen_discovery(ip: String, port: u16, tx: mpsc::UnboundedSender<(String, u16)>, id: String) {
-    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP)).unwrap();
+pub async fn listen_discovery(
+    peers: DiscoveredPeers,
+    announce: Arc<Notify>,
+    tx: mpsc::UnboundedSender<Announcement>,
+    id: String,
+    secret: Option<String>,
+) {
+    let mut loops = Vec::new();
+
+    for domain in [Domain::IPV4, Domain::IPV6] {
+        match bind_multicast(domain) {
+            Ok(udp_socket) => {
+                loops.push(tokio::spawn(discovery_loop(
+                    udp_socket,
+                    peers.clone(),
+                    announce.clone(),
+                    tx.clone(),
+                    id.clone(),
+                    secret.clone(),
+                )));
+            }
+            Err(e) => say!("Discovery unavailable on {:?}: {}", domain, e),
+        }
+    }
+
+    for l in loops {
+        let _ = l.await;
+    }
+}
+
+fn bind_multicast(domain: Domain) -> std::io::Result<UdpSocket> {
+    let socket = Socket::new(domain, Type::DGRAM, Some(Protocol::UDP))?;
 
-    socket.set_reuse_address(true).unwrap();
+    socket.set_reuse_address(true)?;
 
     #[cfg(not(windows))]
-    socket.set_reuse_port(true).unwrap();
-    
-    socket.set_nonblocking(true).unwrap();
+    socket.set_reuse_port(true)?;
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], 9000));
-    if let Err(e) = socket.bind(&addr.into()) {
-        println!("Error binding socket: {}", e);
-    }
+    socket.set_nonblocking(true)?;
+
+    if domain == Domain::IPV6 {
+        // solo v6, altrimenti la porta 9000 collide con il socket IPv4
+        socket.set_only_v6(true)?;
+
+        let addr = SocketAddr::from((Ipv6Addr::UNSPECIFIED, DISCOVERY_PORT));
+        socket.bind(&addr.into())?;
+        socket.join_multicast_v6(&MULTICAST_V6, 0)?; // 0 = interfaccia di default
+    } else {
+        let addr = SocketAddr::from((Ipv4Addr::UNSPECIFIED, DISCOVERY_PORT));
+        if let Err(e) = socket.bind(&addr.into()) {
+            say!("Error binding socket: {}", e);
+        }
 
-    let multicast_addr = Ipv4Addr::new(239, 255, 42, 99);
-    let interface = Ipv4Addr::new(0, 0, 0, 0);
-    if let Err(e) = socket.join_multicast_v4(&multicast_addr, &interface) {
-        println!("Error joining multicast: {}", e);
+        if let Err(e) = socket.join_multicast_v4(&MULTICAST_V4, &Ipv4Addr::UNSPECIFIED) {
+            say!("Error joining multicast: {}", e);
+        }
     }
 
     let std_udp: std::net::UdpSocket = socket.into();
-    let udp_socket: UdpSocket = UdpSocket::from_std(std_udp).unwrap();
+    UdpSocket::from_std(std_udp)
+}
 
-    let mut buf = [0u8; 1024];
+async fn discovery_loop(
+    udp_socket: UdpSocket,
+    peers: DiscoveredPeers,
+    announce: Arc<Notify>,
+    tx: mpsc::UnboundedSender<Announcement>,
+    id: String,
+    secret: Option<String>,
+) {
+    let mut buf = [0u8; 4096];
     loop {
-        if let Ok((len, _addr)) = udp_socket.recv_from(&mut buf).await {
-            
-            if let Ok(packet_rec) = serde_json::from_slice::<DiscoveryPacket>(&buf[..len]) {
-                handle_packet_discovery(packet_rec, ip.clone(), port, &udp_socket, tx.clone(), id.clone()).await;
-            }
+        if let Ok((len, _addr)) = udp_socket.recv_from(&mut buf).await
+            && let Ok(frame) = serde_json::from_slice::<DiscoveryFrame>(&buf[..len])
+            && let Some(packet_rec) = open(frame, secret.as_deref()) // frame di altri gruppi o non autentici scartati
+        {
+            handle_packet_discovery(packet_rec, &peers, &announce, tx.clone(), &id).await;
         }
     }
 }
@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use tokio::time::sleep;
+
+use crate::discovery::backend::{DiscoveryBackend, DiscoveryContext};
+use crate::discovery::discovery_auth::{open, seal};
+use crate::state::state_discovery::{ANNOUNCE_INTERVAL, Announcement, DiscoveryFrame, DiscoveryPacket};
+use crate::ui::handle_output::say;
+
+pub const SERVICE_TYPE: &str = "_p2pchat._tcp.local.";
+const MAX_TXT_ADDRS: usize = 200; // un valore TXT sta in 255 byte insieme alla chiave
+
+// Servizio DNS-SD standard, visibile da avahi-browse, dns-sd, Bonjour ecc.
+// Nel TXT ci sono id, username, stanza e indirizzi con la porta, tutti coperti dal mac;
+// dei record SRV/A/AAAA si usano solo gli indirizzi che compaiono anche nel TXT
+pub struct MdnsBackend {
+    daemon: ServiceDaemon,
+}
+
+impl MdnsBackend {
+    pub fn new() -> mdns_sd::Result<Self> {
+        Ok(Self {
+            daemon: ServiceDaemon::new()?,
+        })
+    }
+}
+
+impl DiscoveryBackend for MdnsBackend {
+    fn name(&self) -> &'static str {
+        "mdns"
+    }
+
+    fn start(&self, ctx: DiscoveryContext) {
+        tokio::spawn(advertise(self.daemon.clone(), ctx.clone()));
+        tokio::spawn(browse(self.daemon.clone(), ctx));
+    }
+}
+
+// Il TXT viene ripubblicato a ogni intervallo: aggiorna stanza e timestamp, e con un segreto
+// mantiene il mac dentro la finestra di validità
+async fn advertise(daemon: ServiceDaemon, ctx: DiscoveryContext) {
+    let short_id: String = ctx.myself.id.chars().take(8).collect();
+    let host = format!("p2pchat-{}.local.", short_id);
+    let ips: Vec<IpAddr> = ctx.myself.addrs.iter().map(|a| a.ip()).collect();
+    let port = ctx.myself.addrs.first().map(|a| a.port()).unwrap_or_default();
+    let addrs = txt_addrs(&ctx.myself.addrs);
+    let mut registered: Option<String> = None; // fullname pubblicato, cambia col nome dopo /nick
+
+    loop {
+        let (room, username) = {
+            let chat_lock = ctx.chat.lock().await;
+            (chat_lock.room.clone(), chat_lock.me(&ctx.myself).username)
+        };
+        let instance = format!("{}-{}", username, short_id);
+        let frame = seal(
+            DiscoveryPacket::Announce(announcement(&ctx.myself.id, &username, &room, addrs.clone())),
+            ctx.secret.as_deref(),
+        );
+
+        let mut properties = HashMap::from([
+            ("id".to_string(), ctx.myself.id.clone()),
+            ("user".to_string(), username),
+            ("room".to_string(), room),
+            ("addrs".to_string(), join_addrs(&addrs)),
+            ("ts".to_string(), frame.timestamp.to_string()),
+        ]);
+        if let Some(mac) = frame.mac {
+            properties.insert("mac".to_string(), mac);
+        }
+
+        match ServiceInfo::new(SERVICE_TYPE, &instance, &host, &ips[..], port, properties) {
+            Ok(info) => {
+                let fullname = info.get_fullname().to_string();
+                if let Some(old) = registered.take_if(|old| *old != fullname) {
+                    let _ = daemon.unregister(&old);
+                }
+
+                match daemon.register(info) {
+                    Ok(()) => registered = Some(fullname),
+                    Err(e) => say!("Error registering mDNS service: {}", e),
+                }
+            }
+            Err(e) => {
+                say!("Invalid mDNS service: {}", e);
+                return;
+            }
+        }
+
+        sleep(ANNOUNCE_INTERVAL).await;
+    }
+}
+
+async fn browse(daemon: ServiceDaemon, ctx: DiscoveryContext) {
+    let receiver = match daemon.browse(SERVICE_TYPE) {
+        Ok(r) => r,
+        Err(e) => {
+            say!("Error browsing mDNS: {}", e);
+            return;
+        }
+    };
+
+    let mut ids: HashMap<String, String> = HashMap::new(); // fullname -> id, per ServiceRemoved
+
+    while let Ok(event) = receiver.recv_async().await {
+        match event {
+            ServiceEvent::ServiceResolved(info) => {
+                let prop = |key: &str| info.get_property_val_str(key).map(|v| v.to_string());
+                let (Some(id), Some(username), Some(room), Some(addrs), Some(ts)) =
+                    (prop("id"), prop("user"), prop("room"), prop("addrs"), prop("ts"))
+                else {
+                    continue; // non è un nostro peer
+                };
+
+                // il mac copre anche gli indirizzi del TXT: chi ripete il record non può cambiarli
+                let Some(addrs) = parse_addrs(&addrs) else {
+                    continue;
+                };
+                let frame = DiscoveryFrame {
+                    packet: DiscoveryPacket::Announce(announcement(&id, &username, &room, addrs)),
+                    timestamp: ts.parse().unwrap_or_default(),
+                    mac: prop("mac"),
+                };
+                let Some(DiscoveryPacket::Announce(mut announcement)) = open(frame, ctx.secret.as_deref()) else {
+                    continue;
+                };
+
+                if announcement.id == ctx.myself.id {
+                    continue;
+                }
+
+                // dei record A/AAAA e SRV valgono solo gli indirizzi annunciati nel TXT
+                let resolved: Vec<SocketAddr> = info
+                    .get_addresses()
+                    .iter()
+                    .map(|ip| SocketAddr::new(ip.to_ip_addr(), info.get_port()))
+                    .collect();
+                announcement.addrs.retain(|a| resolved.contains(a));
+                if announcement.addrs.is_empty() {
+                    continue;
+                }
+
+                ids.insert(info.get_fullname().to_string(), id);
+                if ctx.peers.update(announcement.clone()).await {
+                    let _ = ctx.tx.send(announcement);
+                }
+            }
+            ServiceEvent::ServiceRemoved(_, fullname) => {
+                if let Some(id) = ids.remove(&fullname) {
+                    ctx.peers.remove(&id).await;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn announcement(id: &str, username: &str, room: &str, addrs: Vec<SocketAddr>) -> Announcement {
+    Announcement {
+        id: id.to_string(),
+        username: username.to_string(),
+        room: room.to_string(),
+        addrs,
+    }
+}
+
+// Quanti indirizzi stanno nel TXT, nell'ordine di preferenza
+fn txt_addrs(addrs: &[SocketAddr]) -> Vec<SocketAddr> {
+    let mut kept: Vec<SocketAddr> = vec![];
+    for addr in addrs {
+        kept.push(*addr);
+        if join_addrs(&kept).len() > MAX_TXT_ADDRS {
+            kept.pop();
+            break;
+        }
+    }
+    kept
+}
+
+fn join_addrs(addrs: &[SocketAddr]) -> String {
+    addrs.iter().map(SocketAddr::to_string).collect::<Vec<_>>().join(",")
+}
+
+// None se anche uno solo non è valido: il mac non tornerebbe comunque
+fn parse_addrs(value: &str) -> Option<Vec<SocketAddr>> {
+    value.split(',').map(|a| a.parse().ok()).collect()
+}
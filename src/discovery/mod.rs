@@ -1,3 +1,9 @@
+pub mod announce_discovery;
+pub mod backend;
+pub mod discovery_auth;
 pub mod listen_discovery;
 pub mod find_discovery;
-pub mod handle_packet_discovery;
\ No newline at end of file
+pub mod handle_packet_discovery;
+pub mod mdns_backend;
+pub mod multicast;
+pub mod multicast_backend;
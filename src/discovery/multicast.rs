@@ -0,0 +1,60 @@
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use tokio::net::UdpSocket;
+
+use crate::discovery::discovery_auth::seal;
+use crate::state::state_discovery::{DISCOVERY_PORT, DiscoveryPacket, MULTICAST_V4, MULTICAST_V6};
+use crate::ui::handle_output::say;
+
+// Socket di invio verso i gruppi multicast IPv4 e IPv6, quello che manca viene saltato
+pub struct MulticastSender {
+    udp_v4: Option<UdpSocket>,
+    udp_v6: Option<UdpSocket>,
+    secret: Option<String>,
+}
+
+impl MulticastSender {
+    pub async fn new(secret: Option<String>) -> Self {
+        let udp_v4 = match UdpSocket::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))).await {
+            Ok(s) => {
+                let _ = s.set_broadcast(true);
+                Some(s)
+            }
+            Err(e) => {
+                say!("IPv4 discovery unavailable: {}", e);
+                None
+            }
+        };
+
+        let udp_v6 = match UdpSocket::bind(SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0))).await {
+            Ok(s) => Some(s),
+            Err(e) => {
+                say!("IPv6 discovery unavailable: {}", e);
+                None
+            }
+        };
+
+        Self {
+            udp_v4,
+            udp_v6,
+            secret,
+        }
+    }
+
+    pub async fn send(&self, packet: &DiscoveryPacket) {
+        let frame = seal(packet.clone(), self.secret.as_deref());
+        let bytes = serde_json::to_vec(&frame).expect("Failed to serialize");
+
+        if let Some(udp_v4) = &self.udp_v4
+            && let Err(e) = udp_v4.send_to(&bytes, (MULTICAST_V4, DISCOVERY_PORT)).await
+        {
+            say!("Error sending IPv4 discovery: {}", e);
+        }
+
+        if let Some(udp_v6) = &self.udp_v6
+            && let Err(e) = udp_v6.send_to(&bytes, (MULTICAST_V6, DISCOVERY_PORT)).await
+        {
+            say!("Error sending IPv6 discovery: {}", e);
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use std::sync::Arc;
+
+use tokio::sync::Notify;
+
+use crate::discovery::announce_discovery::announce_discovery;
+use crate::discovery::backend::{DiscoveryBackend, DiscoveryContext};
+use crate::discovery::find_discovery::find_discovery;
+use crate::discovery::listen_discovery::listen_discovery;
+
+// Il protocollo JSON su 239.255.42.99 / ff02::4242:99
+pub struct MulticastBackend {
+    announce: Arc<Notify>,
+}
+
+impl MulticastBackend {
+    pub fn new() -> Self {
+        Self {
+            announce: Arc::new(Notify::new()),
+        }
+    }
+}
+
+impl DiscoveryBackend for MulticastBackend {
+    fn name(&self) -> &'static str {
+        "multicast"
+    }
+
+    fn start(&self, ctx: DiscoveryContext) {
+        tokio::spawn(listen_discovery(
+            ctx.peers,
+            self.announce.clone(),
+            ctx.tx,
+            ctx.myself.id.clone(),
+            ctx.secret.clone(),
+        ));
+        tokio::spawn(announce_discovery(ctx.chat, ctx.myself, self.announce.clone(), ctx.secret));
+    }
+
+    fn probe(&self, ctx: &DiscoveryContext) {
+        tokio::spawn(find_discovery(ctx.myself.id.clone(), ctx.secret.clone()));
+    }
+}
@@ -0,0 +1,71 @@
+use std::sync::Arc;
+
+use tokio::sync::Mutex;
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::state::state_chat::{Chat, Connections, Member};
+use crate::state::state_keyring::KeyShare;
+use crate::state::state_node::Node;
+use crate::state::state_packets::Packet;
+use crate::state::state_session::Session;
+use crate::ui::handle_format::sanitize_line;
+use crate::ui::handle_output::{print_notice, say};
+
+// Chi accetta un nuovo membro gli passa tutte le chiavi, così può leggere anche la storia
+pub async fn share_keys(member: &Member, myself: &Member, node: &Node, tx: &UnboundedSender<Packet>) {
+    let keys = node.keyring.all().await;
+    if keys.is_empty() {
+        return;
+    }
+
+    match KeyShare::seal(&node.identity, &myself.id, &member.id, &member.pubkey, &keys) {
+        Some(share) => {
+            let _ = tx.send(Packet::GroupKey(share));
+        }
+        None => say!("Cannot share the room key with {}: invalid public key", sanitize_line(&member.username)),
+    }
+}
+
+pub async fn receive_keys(share: KeyShare, myself: &Member, node: &Node, session: &mut Session) {
+    // la chiave arriva direttamente da chi l'ha cifrata, quindi la sua pubkey è quella della sessione
+    if share.recipient != myself.id || session.remote_id.as_ref() != Some(&share.issuer) {
+        return;
+    }
+    let Some(issuer_key) = &session.remote_key else {
+        return;
+    };
+
+    let Some(keys) = share.open(&node.identity, issuer_key) else {
+        say!("Received a room key that cannot be decrypted");
+        return;
+    };
+
+    if session.joining {
+        session.joining = false;
+        node.keyring.adopt(keys).await;
+    } else {
+        node.keyring.merge(keys).await;
+    }
+}
+
+// Dopo un'uscita o un kick la chiave va cambiata, così chi se ne è andato non legge i messaggi nuovi.
+// Lo fa solo il membro rimasto con l'id minore, per non avere rotazioni in parallelo
+pub async fn rotate_if_leader(chat: &Arc<Mutex<Chat>>, myself: &Member, node: &Node, connections: &Connections) {
+    let members: Vec<Arc<Member>> = {
+        let chat_lock = chat.lock().await;
+        if chat_lock.members.iter().any(|m| m.id < myself.id) {
+            return;
+        }
+        chat_lock.members.iter().filter(|m| m.id != myself.id).cloned().collect()
+    };
+
+    let key = node.keyring.rotate(&myself.id).await;
+
+    for m in members {
+        if let Some(share) = KeyShare::seal(&node.identity, &myself.id, &m.id, &m.pubkey, std::slice::from_ref(&key)) {
+            connections.send_to(&m.id, Packet::GroupKey(share)).await;
+        }
+    }
+
+    print_notice(&format!("Room key rotated (epoch {})", key.epoch));
+}
@@ -0,0 +1,199 @@
+use std::sync::Arc;
+
+use tokio::sync::Mutex;
+
+use crate::crypto::identity::verify;
+use crate::handler::handle_keys::rotate_if_leader;
+use crate::state::state_access::{ModAction, Moderation};
+use crate::state::state_chat::{Chat, Connections, Member};
+use crate::state::state_events::ChatEvent;
+use crate::state::state_node::Node;
+use crate::ui::handle_input::get_timestamp;
+use crate::ui::handle_output::{print_notice, say};
+
+// Applica un /kick o /ban: arriva dall'owner oppure l'ha appena firmato questo nodo (check = false)
+pub async fn apply_moderation(
+    moderation: Moderation,
+    chat: &Arc<Mutex<Chat>>,
+    myself: &Member,
+    node: &Node,
+    connections: &Connections,
+    check: bool,
+) {
+    let username;
+    {
+        let mut chat_lock = chat.lock().await;
+
+        if check {
+            let Some(owner) = &chat_lock.access.owner else {
+                return;
+            };
+
+            if moderation.room != chat_lock.room || !verify(owner, &moderation.payload(), &moderation.signature) {
+                say!("Ignoring a {} with an invalid signature", moderation.action);
+                return;
+            }
+
+            if !moderation.is_fresh(get_timestamp()) {
+                say!("Ignoring an outdated moderation ({} {})", moderation.action, moderation.target);
+                return;
+            }
+        }
+
+        if !node.access.first_time(&moderation).await {
+            return;
+        }
+
+        username = chat_lock
+            .members
+            .iter()
+            .find(|m| m.id == moderation.target)
+            .map(|m| m.username.clone())
+            .unwrap_or_else(|| moderation.target.clone());
+
+        if moderation.action == ModAction::Ban {
+            chat_lock.access.ban(&moderation.target_key);
+        }
+
+        if moderation.target != myself.id {
+            if let Some(m) = chat_lock.member(&moderation.target) {
+                node.events.publish(ChatEvent::left(m));
+            }
+            chat_lock.members.retain(|m| m.id != moderation.target);
+        }
+    }
+
+    if moderation.target == myself.id {
+        print_notice(&format!("You were {} from room {} by the owner", moderation.action, moderation.room));
+        return;
+    }
+
+    node.access.kick(&moderation.target).await;
+    connections
+        .drop_member(&moderation.target, &format!("you were {} from this room", moderation.action))
+        .await;
+
+    print_notice(&format!("{} was {} from the room", username, moderation.action));
+    rotate_if_leader(chat, myself, node, connections).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::identity::{Identity, fingerprint};
+    use crate::plugin::api::Plugins;
+    use crate::state::state_access::MODERATION_MAX_AGE;
+
+    struct Room {
+        chat: Arc<Mutex<Chat>>,
+        myself: Member,
+        target: Member,
+        node: Node,
+        owner: Identity,
+    }
+
+    // Questo nodo è un membro qualunque; l'owner non è connesso, arrivano solo i suoi pacchetti
+    fn room() -> Room {
+        let owner = Identity::generate();
+        let member = |name: &str, id: &str| Member::new(name.to_string(), vec![], id.to_string(), Identity::generate().public_key());
+        let (myself, target) = (member("me", "id-me"), member("eve", "id-eve"));
+
+        let mut chat = Chat::new("general".to_string());
+        chat.access.owner = Some(owner.public_key());
+        chat.add_member(myself.clone());
+        chat.add_member(target.clone());
+
+        let node = Node::new(Identity::generate(), None, Arc::new(vec![]), Plugins::new(vec![]), false);
+        Room {
+            chat: Arc::new(Mutex::new(chat)),
+            myself,
+            target,
+            node,
+            owner,
+        }
+    }
+
+    fn signed(action: ModAction, room: &Room, signer: &Identity, timestamp: u64) -> Moderation {
+        let mut moderation = Moderation {
+            action,
+            room: "general".to_string(),
+            target: room.target.id.clone(),
+            target_key: fingerprint(&room.target.pubkey),
+            timestamp,
+            signature: String::new(),
+        };
+        moderation.signature = signer.sign(&moderation.payload());
+        moderation
+    }
+
+    async fn receive(moderation: Moderation, room: &Room) {
+        apply_moderation(moderation, &room.chat, &room.myself, &room.node, &Connections::new(), true).await;
+    }
+
+    async fn is_member(room: &Room) -> bool {
+        room.chat.lock().await.member(&room.target.id).is_some()
+    }
+
+    #[tokio::test]
+    async fn owner_kick_removes_the_member() {
+        let room = room();
+        receive(signed(ModAction::Kick, &room, &room.owner, get_timestamp()), &room).await;
+
+        assert!(!is_member(&room).await);
+        assert!(room.node.access.is_kicked(&room.target.id).await);
+        assert!(!room.chat.lock().await.access.is_banned(&fingerprint(&room.target.pubkey)));
+    }
+
+    #[tokio::test]
+    async fn owner_ban_bans_the_key() {
+        let room = room();
+        receive(signed(ModAction::Ban, &room, &room.owner, get_timestamp()), &room).await;
+
+        assert!(!is_member(&room).await);
+        assert!(room.chat.lock().await.access.is_banned(&fingerprint(&room.target.pubkey)));
+    }
+
+    #[tokio::test]
+    async fn kick_not_signed_by_the_owner_is_ignored() {
+        let room = room();
+        let someone = Identity::generate();
+        receive(signed(ModAction::Kick, &room, &someone, get_timestamp()), &room).await;
+
+        assert!(is_member(&room).await);
+        assert!(!room.node.access.is_kicked(&room.target.id).await);
+    }
+
+    #[tokio::test]
+    async fn kick_for_another_room_is_ignored() {
+        let room = room();
+        let mut moderation = signed(ModAction::Kick, &room, &room.owner, get_timestamp());
+        moderation.room = "other".to_string();
+        moderation.signature = room.owner.sign(&moderation.payload());
+        receive(moderation, &room).await;
+
+        assert!(is_member(&room).await);
+    }
+
+    #[tokio::test]
+    async fn old_kick_is_ignored() {
+        let room = room();
+        let old = get_timestamp() - MODERATION_MAX_AGE - 1;
+        receive(signed(ModAction::Kick, &room, &room.owner, old), &room).await;
+
+        assert!(is_member(&room).await);
+        assert!(!room.node.access.is_kicked(&room.target.id).await);
+    }
+
+    #[tokio::test]
+    async fn replayed_kick_is_applied_once() {
+        let room = room();
+        let kick = signed(ModAction::Kick, &room, &room.owner, get_timestamp());
+        receive(kick.clone(), &room).await;
+        assert!(!is_member(&room).await);
+
+        // rientrato da un altro membro: lo stesso pacchetto ripetuto non lo caccia di nuovo
+        room.chat.lock().await.add_member(room.target.clone());
+        receive(kick, &room).await;
+        assert!(is_member(&room).await);
+    }
+}
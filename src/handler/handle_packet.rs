@@ -1,89 +1,532 @@
 use std::collections::HashSet;
 use std::sync::Arc;
 
+use rand::random;
 use tokio::sync::Mutex;
 use tokio::sync::mpsc::UnboundedSender;
 
+use crate::crypto::access::{check_password_proof, password_proof};
+use crate::crypto::identity::{challenge_payload, fingerprint, verify};
+use crate::handler::handle_keys::{receive_keys, share_keys};
+use crate::handler::handle_moderation::apply_moderation;
+use crate::handler::handle_presence::receive_presence;
+use crate::handler::handle_rules::receive_rules;
 use crate::network::connect_to::connect_to;
 use crate::network::listen::listen_main;
 use crate::network::send::send;
-use crate::state::state_chat::{Connections, Member};
+use crate::state::state_chat::{Connections, Member, validate_username};
+use crate::state::state_events::ChatEvent;
+use crate::state::state_node::Node;
+use crate::state::state_presence::MAX_STATUS_LEN;
+use crate::state::state_reactions::valid_reaction;
 use crate::state::state_packets::Packet;
+use crate::state::state_session::Session;
 use crate::state_chat::{self, Chat};
+use crate::ui::handle_input::get_timestamp;
+use crate::ui::handle_format::sanitize_line;
 use crate::ui::handle_output;
-use crate::ui::handle_output::print_message;
+use crate::ui::handle_output::{print_message, print_notice, say, thread_views, view};
 
 pub async fn handle_packet(
     packet: Packet,
     chat: &Arc<Mutex<Chat>>,
     myself: &Member,
+    node: &Node,
+    session: &mut Session,
     tx: UnboundedSender<Packet>,
     connections: Connections,
 ) {
+    // un membro cacciato non può continuare a scrivere sulla connessione che aveva
+    if let Some(id) = &session.remote_id
+        && node.access.is_kicked(id).await
+    {
+        session.close = true;
+        return;
+    }
+
+    // prima dei controlli della stanza si accetta solo l'handshake
+    if session.incoming && !session.authorized {
+        match packet {
+            Packet::Identity(..) | Packet::Challenge(_) | Packet::InviteAuth(_) | Packet::AccessResponse { .. } => {}
+            Packet::InitSyncRequest => {
+                // ancora in attesa dell'invito: il joiner lo manda prima di chiedere la chat
+                if session.pending_member.is_some() && session.access_challenge.is_none() {
+                    deny(&tx, session, "this room requires a valid invite");
+                    return;
+                }
+
+                session.pending_sync = true;
+                return;
+            }
+            _ => return,
+        }
+    }
+
+    // finché il peer non ha firmato il nostro nonce il suo id non vale come mittente
+    if !session.verified
+        && matches!(
+            packet,
+            Packet::UserMessage(_)
+                | Packet::Presence(_)
+                | Packet::Ack { .. }
+                | Packet::React { .. }
+                | Packet::Typing
+                | Packet::Nick(_)
+                | Packet::GroupKey(_)
+        )
+    {
+        return;
+    }
+
     match packet {
         Packet::UserMessage(message) => {
+            // ognuno manda i propri messaggi direttamente: il mittente è chi sta dietro alla connessione
+            if message.sender_id.is_none() || message.sender_id != session.remote_id {
+                say!("Ignoring a message sent on behalf of another member");
+                return;
+            }
+
+            // a scomparsa e già scaduto: arrivato troppo tardi, non si mostra
+            if message.expires.is_some_and(|e| e <= get_timestamp()) {
+                return;
+            }
+
             let mut chat_lock = chat.lock().await;
 
-            print_message(&message);
+            if let Some(id) = &message.sender_id {
+                node.typing.stopped(id).await;
+            }
+
+            let shown = view(&message, &chat_lock, node, &myself.id).await;
+            print_message(&shown);
+
+            let mut plain = node.keyring.reveal(&message).await;
+            chat_lock.label(&mut plain);
+            node.events.publish(ChatEvent::message(&plain));
+
+            if shown.mentioned {
+                let title = format!("{} mentioned you in {}", shown.sender, chat_lock.room);
+                for notifier in node.notifiers.iter() {
+                    notifier.notify(&title, &shown.text);
+                }
+            }
+
+            // ricevuto subito, letto quando l'utente torna a scrivere
+            if !message.id.is_empty()
+                && let Some(id) = &session.remote_id
+            {
+                let _ = tx.send(Packet::Ack {
+                    message: message.id.clone(),
+                    read: false,
+                });
+                node.unread.push(&message.id, id).await;
+            }
+
             chat_lock.add_message(message);
+            chat_lock.prune(get_timestamp());
         }
         Packet::Sync(chat_received) => {
+            // niente chat finché l'host non ha dimostrato di avere la chiave (quella dell'invito, se c'è)
+            if !session.verified {
+                say!("Received Sync before the host key was verified, disconnecting");
+                session.close = true;
+                return;
+            }
+
             let diff: Vec<state_chat::Member>;
+            let me: Member;
             {
                 let mut chat_lock = chat.lock().await;
-                chat_lock.set_all_messages(chat_received.all_messages.clone());
-                handle_output::print_all_messages(chat_received.all_messages);
+                if chat_lock.room != chat_received.room {
+                    say!("\r\x1b[2KJoined room {}", sanitize_line(&chat_received.room));
+                    chat_lock.room = chat_received.room.clone();
+                }
+                // entrando si accetta l'owner dell'invito o, senza invito, quello della stanza;
+                // dopo resta quello: regole non firmate da lui non sostituiscono le nostre
+                let owner = if session.sync_requested && chat_lock.access.owner.as_ref() == Some(&myself.pubkey) {
+                    session.expected_owner.clone()
+                } else {
+                    chat_lock.access.owner.as_deref().map(fingerprint)
+                };
+                let previous = chat_lock.retention.clone();
+                let accepted = match &chat_received.rules {
+                    Some(signed) => chat_lock.accept_rules(
+                        &chat_received.access,
+                        &chat_received.retention,
+                        signed,
+                        owner.as_deref(),
+                    ),
+                    None => Err("unsigned room rules".to_string()),
+                };
+                match accepted {
+                    Ok(()) if previous != chat_lock.retention && !chat_lock.retention.is_unlimited() => {
+                        say!("\r\x1b[2KRoom retention: {}", chat_lock.retention);
+                    }
+                    Ok(()) => {}
+                    Err(reason) => say!("\r\x1b[2KIgnoring {}", reason),
+                }
+                session.sync_requested = false;
+                let dropped = chat_lock.set_all_messages(chat_received.all_messages.clone(), &chat_received.members);
+                if dropped > 0 {
+                    say!("\r\x1b[2KDropped {} messages with an invalid edit", dropped);
+                }
+                chat_lock.prune(get_timestamp());
 
                 diff = get_members_diff(&chat_lock.members, &chat_received.members);
 
                 for m in diff.clone() {
+                    node.events.publish(ChatEvent::joined(&m));
                     chat_lock.add_member(m.clone());
                 }
+
+                let views = thread_views(&chat_lock.all_messages, &chat_lock, node, &myself.id).await;
+                handle_output::print_all_messages(views);
+                me = chat_lock.me(myself);
             }
+            node.synced.notify_one();
 
             for m in diff.clone() {
                 let chat_clone = Arc::clone(chat);
                 let myself_clone = Arc::new(myself.clone());
                 let conns_clone = connections.clone();
 
-                let packet = Packet::Identity(myself.clone(), false);
+                let packet = Packet::Identity(me.clone(), false);
 
-                conn(m, chat_clone, myself_clone, conns_clone, packet);
+                conn(m, chat_clone, myself_clone, node.clone(), conns_clone, packet);
             }
         }
         Packet::InitSyncRequest => {
             let chat_lock = chat.lock().await;
-            let packet = Packet::Sync(chat_lock.clone());
+            let packet = Packet::Sync(chat_lock.for_sync());
 
             if let Err(e) = tx.send(packet) {
-                println!("Connection error in InitSyncRequest: {}", e);
+                say!("Connection error in InitSyncRequest: {}", e);
+            }
+        }
+        Packet::Identity(mut new_member, idback) => {
+            if let Err(reason) = validate_username(&new_member.username) {
+                deny(&tx, session, &format!("invalid username: {}", reason));
                 return;
             }
+
+            // stesso limite di Presence: lo stato troppo lungo non entra, il membro sì
+            if new_member.status_message.as_ref().is_some_and(|m| m.chars().count() > MAX_STATUS_LEN) {
+                new_member.status_message = None;
+            }
+
+            let packet = Packet::Identity(chat.lock().await.me(myself), false);
+
+            if idback && let Err(e) = tx.send(packet) {
+                say!("Connection error in Identity: {}", e);
+            }
+
+            if session.incoming && !session.authorized {
+                // abbiamo emesso inviti con segreto: chi non è già nella chat aspetta InviteAuth
+                if !session.invited && node.invites.required() && !chat.lock().await.knows(&new_member) {
+                    session.pending_member = Some(new_member);
+                    return;
+                }
+
+                authorize(new_member, chat, session, &tx).await;
+                return;
+            }
+
+            if !key_matches(&*chat.lock().await, &new_member) {
+                say!("WARNING: the peer claims the id of another member, disconnecting");
+                session.close = true;
+                return;
+            }
+
+            // connessione aperta da noi: nella chat solo dopo che ha firmato il nonce
+            if !session.verified {
+                session.pending_member = Some(new_member);
+                return;
+            }
+
+            admit(new_member, chat, node).await;
         }
-        Packet::Identity(new_member, idback) => {
+        Packet::Challenge(nonce) => {
+            let signature = node.identity.sign(&challenge_payload(&nonce, &myself.id));
+
+            if let Err(e) = tx.send(Packet::ChallengeRes(signature)) {
+                say!("Connection error in Challenge: {}", e);
+            }
+        }
+        Packet::ChallengeRes(signature) => {
+            let (Some(nonce), Some(remote_id), Some(remote_key)) =
+                (session.challenge.take(), &session.remote_id, &session.remote_key)
+            else {
+                return; // non abbiamo chiesto niente
+            };
+
+            if !verify(remote_key, &challenge_payload(&nonce, remote_id), &signature) {
+                say!("WARNING: the peer did not prove its key, disconnecting");
+                session.close = true;
+                return;
+            }
+
+            if let Some(expected) = &session.expected_fingerprint {
+                if fingerprint(remote_key) != *expected {
+                    say!("WARNING: the host key does not match the invite, disconnecting");
+                    session.close = true;
+                    return;
+                }
+                say!("Host key verified ({})", expected);
+            }
+            session.verified = true;
+
+            if !session.incoming
+                && let Some(member) = session.pending_member.take()
             {
-                let mut chat_lock = chat.lock().await;
+                admit(member, chat, node).await;
+            }
 
-                if chat_lock.members.iter().any(|m| m.id == new_member.id) {
-                    println!("Ricevuto membro già presente in members: {:?}", new_member);
-                } else {
-                    chat_lock.add_member(new_member);
+            // l'host è quello dell'invito: ora si può mostrare il segreto e chiedere la chat
+            if let Some(secret) = session.invite_secret.take() {
+                let _ = tx.send(Packet::InviteAuth(secret));
+                let _ = tx.send(Packet::InitSyncRequest);
+            }
+        }
+        Packet::InviteAuth(secret) => {
+            if let Err(reason) = node.invites.redeem(&secret).await {
+                deny(&tx, session, &reason);
+                return;
+            }
+
+            session.invited = true;
+            if session.incoming
+                && !session.authorized
+                && session.access_challenge.is_none()
+                && let Some(member) = session.pending_member.take()
+            {
+                authorize(member, chat, session, &tx).await;
+            }
+        }
+        Packet::Denied(reason) => {
+            say!("Access denied: {}", sanitize_line(&reason));
+            session.close = true;
+        }
+        Packet::AccessChallenge { nonce, password } => {
+            let signature = node.identity.sign(&challenge_payload(&nonce, &myself.id));
+
+            let proof = match (password, &node.access.password) {
+                (false, _) => None,
+                (true, Some(pw)) => Some(password_proof(pw, &nonce, &myself.id)),
+                (true, None) => {
+                    say!("This room requires a password, restart with --password");
+                    None
                 }
+            };
+
+            if let Err(e) = tx.send(Packet::AccessResponse {
+                signature,
+                password: proof,
+            }) {
+                say!("Connection error in AccessChallenge: {}", e);
             }
+        }
+        Packet::AccessResponse { signature, password } => {
+            let (Some(nonce), Some(member)) = (session.access_challenge.take(), session.pending_member.take())
+            else {
+                return; // non abbiamo chiesto niente
+            };
+
+            let access = chat.lock().await.access.clone();
+            let key = fingerprint(&member.pubkey);
 
-            let packet = Packet::Identity(myself.clone(), false);
+            if !verify(&member.pubkey, &challenge_payload(&nonce, &member.id), &signature) {
+                deny(&tx, session, "invalid key signature");
+                return;
+            }
+
+            if access.owner.as_ref() != Some(&member.pubkey) && !access.is_allowed(&key) {
+                deny(&tx, session, "your key is not on the room allow-list");
+                return;
+            }
 
-            if idback == true {
-                if let Err(e) = tx.send(packet) {
-                    println!("Connection error in Identity: {}", e);
+            if access.password {
+                let ok = match (&node.access.password, &password) {
+                    (Some(pw), Some(proof)) => check_password_proof(pw, &nonce, &member.id, proof),
+                    _ => false,
+                };
+
+                if !ok {
+                    deny(&tx, session, "wrong room password");
                     return;
                 }
             }
+
+            session.verified = true;
+            admit_authorized(member, chat, myself, node, session, &tx).await;
+        }
+        Packet::Moderation(moderation) => {
+            apply_moderation(moderation, chat, myself, node, &connections, true).await;
+        }
+        Packet::GroupKey(share) => {
+            receive_keys(share, myself, node, session).await;
+        }
+        Packet::Presence(presence) => {
+            let Some(id) = &session.remote_id else {
+                return;
+            };
+
+            if presence.message.as_ref().is_some_and(|m| m.chars().count() > MAX_STATUS_LEN) {
+                return;
+            }
+
+            receive_presence(presence, id, chat).await;
+        }
+        Packet::Ack { message, read } => {
+            let Some(id) = &session.remote_id else {
+                return;
+            };
+
+            chat.lock().await.acknowledge(&myself.id, &message, id, read);
+        }
+        Packet::Amend(amendment) => {
+            let mut chat_lock = chat.lock().await;
+            let Some(author) = chat_lock.member(&amendment.author) else {
+                return;
+            };
+
+            if !verify(&author.pubkey, &amendment.payload(), &amendment.signature) {
+                say!("Ignoring an edit with an invalid signature");
+                return;
+            }
+
+            let id = amendment.message.clone();
+            if chat_lock.amend(amendment).is_ok()
+                && let Some(message) = chat_lock.all_messages.iter().find(|m| m.id == id)
+            {
+                print_message(&view(message, &chat_lock, node, &myself.id).await);
+            }
+        }
+        Packet::Rules { access, retention, signed } => {
+            receive_rules(access, retention, signed, chat).await;
+        }
+        Packet::React { message, emoji, add } => {
+            let Some(id) = &session.remote_id else {
+                return;
+            };
+
+            if !valid_reaction(&emoji) {
+                return;
+            }
+
+            let mut chat_lock = chat.lock().await;
+            if chat_lock.react(&message, id, &emoji, add) && add {
+                let handle = node.handles.assign(&message).await;
+                let name = chat_lock.display_name(Some(id), "someone");
+                print_notice(&format!("{} reacted {} to #{}", name, emoji, handle));
+            }
+        }
+        Packet::Typing => {
+            let Some(id) = &session.remote_id else {
+                return;
+            };
+
+            if node.typing.started(id).await {
+                let name = chat.lock().await.display_name(Some(id), "someone");
+                print_notice(&format!("{} is typing…", name));
+            }
+        }
+        Packet::Nick(username) => {
+            let Some(id) = &session.remote_id else {
+                return;
+            };
+
+            if validate_username(&username).is_err() {
+                return;
+            }
+
+            let mut chat_lock = chat.lock().await;
+            if let Some(old) = chat_lock.rename(id, &username) {
+                let shown = chat_lock.display_name(Some(id), &username);
+                print_notice(&format!("{} is now known as {}", old, shown));
+            }
+        }
+    }
+}
+
+// Controlli della stanza per una connessione in ingresso: ban, poi firma della chiave (e password).
+// La firma serve anche nelle stanze aperte: senza, chiunque potrebbe presentarsi con l'id di un altro
+async fn authorize(new_member: Member, chat: &Arc<Mutex<Chat>>, session: &mut Session, tx: &UnboundedSender<Packet>) {
+    let (access, taken) = {
+        let chat_lock = chat.lock().await;
+        (chat_lock.access.clone(), !key_matches(&chat_lock, &new_member))
+    };
+
+    if access.is_banned(&fingerprint(&new_member.pubkey)) {
+        deny(tx, session, "you are banned from this room");
+        return;
+    }
+
+    if taken {
+        deny(tx, session, "this id belongs to another key");
+        return;
+    }
+
+    // il peer deve firmare un nonce con la sua chiave (e con la password); si entra con AccessResponse
+    let nonce = hex::encode(random::<[u8; 16]>());
+    session.access_challenge = Some(nonce.clone());
+    session.pending_member = Some(new_member);
+
+    if let Err(e) = tx.send(Packet::AccessChallenge {
+        nonce,
+        password: access.password,
+    }) {
+        say!("Connection error in Identity: {}", e);
+    }
+}
+
+// Superati i controlli: chiavi, ingresso nella chat e la Sync chiesta nel frattempo
+async fn admit_authorized(
+    member: Member,
+    chat: &Arc<Mutex<Chat>>,
+    myself: &Member,
+    node: &Node,
+    session: &mut Session,
+    tx: &UnboundedSender<Packet>,
+) {
+    session.authorized = true;
+    share_keys(&member, myself, node, tx).await;
+    admit(member, chat, node).await;
+
+    if session.pending_sync {
+        session.pending_sync = false;
+        let packet = Packet::Sync(chat.lock().await.for_sync());
+
+        if let Err(e) = tx.send(packet) {
+            say!("Connection error in Sync: {}", e);
         }
     }
 }
 
-fn get_members_diff(m_loc: &Vec<Arc<Member>>, m_rec: &Vec<Arc<Member>>) -> Vec<state_chat::Member> {
+// se lo conosciamo già (es. da un Sync) aggiorniamo gli indirizzi con quelli osservati
+async fn admit(new_member: Member, chat: &Arc<Mutex<Chat>>, node: &Node) {
+    let mut chat_lock = chat.lock().await;
+
+    if let Some(m) = chat_lock.members.iter_mut().find(|m| m.id == new_member.id) {
+        let m = Arc::make_mut(m);
+        m.addrs = new_member.addrs;
+        m.username = new_member.username;
+    } else {
+        node.events.publish(ChatEvent::joined(&new_member));
+        chat_lock.add_member(new_member);
+    }
+}
+
+// Un id già nella chat resta legato alla sua chiave: firmare con un'altra non basta per prenderlo
+fn key_matches(chat: &Chat, member: &Member) -> bool {
+    chat.member(&member.id).is_none_or(|m| m.pubkey == member.pubkey)
+}
+
+fn deny(tx: &UnboundedSender<Packet>, session: &mut Session, reason: &str) {
+    let _ = tx.send(Packet::Denied(reason.to_string()));
+    session.close = true;
+}
+
+fn get_members_diff(m_loc: &[Arc<Member>], m_rec: &[Arc<Member>]) -> Vec<state_chat::Member> {
     let loc_members: HashSet<_> = m_loc.iter().map(|m| &m.id).collect();
 
     m_rec
@@ -97,22 +540,237 @@ fn conn(
     m: Member,
     chat_clone: Arc<Mutex<Chat>>,
     myself_clone: Arc<Member>,
+    node: Node,
     conns_clone: Connections,
     packet: Packet,
 ) {
     tokio::spawn(async move {
-        match connect_to(&m.ip, m.port).await {
+        match connect_to(&m.addrs).await {
             Ok(stream) => {
+                let peer_addr = stream.peer_addr().ok();
+                if let Some(addr) = peer_addr {
+                    chat_clone.lock().await.promote_addr(&m.id, addr);
+                }
+
                 let (reader, mut writer) = stream.into_split();
                 if let Err(e) = send(&mut writer, &packet).await {
-                    println!("Error sending identity: {}", e);
+                    say!("Error sending identity: {}", e);
+                }
+
+                // id e chiave dal Sync: il membro deve dimostrare che la chiave è sua
+                let mut session = Session::new(peer_addr);
+                session.remote_id = Some(m.id.clone());
+                session.remote_key = Some(m.pubkey.clone());
+                let nonce = session.new_challenge();
+                if let Err(e) = send(&mut writer, &Packet::Challenge(nonce)).await {
+                    say!("Error sending challenge: {}", e);
                 }
-                listen_main(chat_clone, myself_clone, reader, writer, conns_clone).await;
+                listen_main(chat_clone, myself_clone, node, reader, writer, conns_clone, session).await;
             }
             Err(e) => {
-                println!("Problem connect_to in Sync: {}", e);
-                return;
+                say!("Problem connect_to in Sync: {}", e);
             }
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::identity::Identity;
+    use crate::plugin::api::Plugins;
+    use crate::state::state_access::{RoomAccess, RulesSignature, rules_payload};
+    use crate::state::state_chat::Message;
+    use crate::state::state_presence::{Presence, Status};
+    use crate::state::state_retention::Retention;
+    use tokio::sync::mpsc;
+
+    struct Peer {
+        chat: Arc<Mutex<Chat>>,
+        myself: Member,
+        node: Node,
+        session: Session,
+    }
+
+    // Questo nodo ha aperto la connessione verso bob, che ha già firmato il nonce
+    fn peer() -> Peer {
+        let member = |name: &str, id: &str| Member::new(name.to_string(), vec![], id.to_string(), Identity::generate().public_key());
+        let (myself, bob) = (member("me", "id-me"), member("bob", "id-bob"));
+
+        let mut chat = Chat::new("general".to_string());
+        chat.add_member(myself.clone());
+        chat.add_member(bob.clone());
+
+        let mut session = Session::new(None);
+        session.remote_id = Some(bob.id.clone());
+        session.remote_key = Some(bob.pubkey.clone());
+        session.verified = true;
+
+        Peer {
+            chat: Arc::new(Mutex::new(chat)),
+            myself,
+            node: Node::new(Identity::generate(), None, Arc::new(vec![]), Plugins::new(vec![]), false),
+            session,
+        }
+    }
+
+    async fn receive(packet: Packet, peer: &mut Peer) {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        handle_packet(packet, &peer.chat, &peer.myself, &peer.node, &mut peer.session, tx, Connections::new()).await;
+    }
+
+    async fn status(peer: &Peer, id: &str) -> (Status, Option<String>) {
+        let chat_lock = peer.chat.lock().await;
+        let m = chat_lock.member(id).unwrap();
+        (m.status, m.status_message.clone())
+    }
+
+    fn presence(status: Status, message: Option<&str>) -> Packet {
+        Packet::Presence(Presence {
+            status,
+            message: message.map(str::to_string),
+        })
+    }
+
+    #[tokio::test]
+    async fn presence_updates_the_member_behind_the_session() {
+        let mut peer = peer();
+        receive(presence(Status::Busy, Some("in a meeting")), &mut peer).await;
+
+        assert_eq!(status(&peer, "id-bob").await, (Status::Busy, Some("in a meeting".to_string())));
+        assert_eq!(status(&peer, "id-me").await, (Status::Online, None));
+    }
+
+    #[tokio::test]
+    async fn presence_needs_a_verified_session() {
+        let mut peer = peer();
+        peer.session.verified = false;
+        receive(presence(Status::Away, None), &mut peer).await;
+
+        assert_eq!(status(&peer, "id-bob").await, (Status::Online, None));
+    }
+
+    #[tokio::test]
+    async fn presence_with_a_long_status_is_ignored() {
+        let mut peer = peer();
+        let long = "a".repeat(MAX_STATUS_LEN + 1);
+        receive(presence(Status::Away, Some(&long)), &mut peer).await;
+        assert_eq!(status(&peer, "id-bob").await, (Status::Online, None));
+
+        let longest = "é".repeat(MAX_STATUS_LEN);
+        receive(presence(Status::Away, Some(&longest)), &mut peer).await;
+        assert_eq!(status(&peer, "id-bob").await, (Status::Away, Some(longest)));
+    }
+
+    #[tokio::test]
+    async fn identity_drops_a_long_status() {
+        let mut peer = peer();
+        let carol_key = Identity::generate();
+        let mut carol = Member::new("carol".to_string(), vec![], "id-carol".to_string(), carol_key.public_key());
+        carol.status = Status::Busy;
+        carol.status_message = Some("x".repeat(MAX_STATUS_LEN + 1));
+
+        peer.session.remote_id = Some(carol.id.clone());
+        peer.session.remote_key = Some(carol.pubkey.clone());
+        receive(Packet::Identity(carol, false), &mut peer).await;
+
+        assert_eq!(status(&peer, "id-carol").await, (Status::Busy, None));
+    }
+
+    // Regole della stanza con solo max_count, firmate da signer come farebbe l'owner
+    fn rules(owner: &Identity, signer: &Identity, max_count: usize, timestamp: u64) -> Packet {
+        let access = RoomAccess {
+            owner: Some(owner.public_key()),
+            ..RoomAccess::default()
+        };
+        let retention = Retention {
+            max_count: Some(max_count),
+            ..Retention::default()
+        };
+        let signature = signer.sign(&rules_payload("general", &access, &retention, timestamp));
+
+        Packet::Rules {
+            access,
+            retention,
+            signed: RulesSignature { timestamp, signature },
+        }
+    }
+
+    async fn room_with_owner(owner: &Identity) -> Peer {
+        let peer = peer();
+        {
+            let mut chat_lock = peer.chat.lock().await;
+            chat_lock.access.owner = Some(owner.public_key());
+            let bob = chat_lock.member("id-bob").unwrap().clone();
+            let now = get_timestamp();
+            for (i, text) in ["one", "two", "three"].iter().enumerate() {
+                chat_lock.add_message(Message::new(&bob, text.to_string(), now + i as u64));
+            }
+        }
+        peer
+    }
+
+    #[tokio::test]
+    async fn owner_rules_apply_and_prune() {
+        let owner = Identity::generate();
+        let mut peer = room_with_owner(&owner).await;
+
+        receive(rules(&owner, &owner, 2, 100), &mut peer).await;
+
+        let chat_lock = peer.chat.lock().await;
+        assert_eq!(chat_lock.retention.max_count, Some(2));
+        let texts: Vec<&str> = chat_lock.all_messages.iter().map(|m| m.text.as_str()).collect();
+        assert_eq!(texts, vec!["two", "three"]);
+    }
+
+    #[tokio::test]
+    async fn rules_need_the_owner_signature_and_must_be_newer() {
+        let owner = Identity::generate();
+        let mut peer = room_with_owner(&owner).await;
+
+        // firmate da bob, o con un owner diverso da quello che conosciamo
+        let bob = Identity::generate();
+        receive(rules(&owner, &bob, 1, 100), &mut peer).await;
+        receive(rules(&bob, &bob, 1, 100), &mut peer).await;
+        assert_eq!(peer.chat.lock().await.retention, Retention::default());
+        assert_eq!(peer.chat.lock().await.all_messages.len(), 3);
+
+        // una firma vecchia dell'owner non torna indietro sulle regole nuove
+        receive(rules(&owner, &owner, 2, 200), &mut peer).await;
+        receive(rules(&owner, &owner, 1, 100), &mut peer).await;
+        assert_eq!(peer.chat.lock().await.retention.max_count, Some(2));
+        assert_eq!(peer.chat.lock().await.all_messages.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn message_expiry_is_authenticated() {
+        let mut peer = peer();
+        let bob = peer.chat.lock().await.member("id-bob").unwrap().clone();
+        peer.node.keyring.rotate("id-bob").await;
+
+        let sealed = |expires: Option<u64>| {
+            let mut message = Message::new(&bob, "self-destructing".to_string(), get_timestamp());
+            message.expires = expires;
+            message
+        };
+
+        // già scaduto: non si mostra né si tiene
+        let mut expired = sealed(Some(get_timestamp() - 1));
+        peer.node.keyring.seal(&mut expired).await;
+        receive(Packet::UserMessage(expired), &mut peer).await;
+        assert!(peer.chat.lock().await.all_messages.is_empty());
+
+        // scadenza tolta per strada: il messaggio resta ma il testo non si decifra più
+        let mut stripped = sealed(Some(get_timestamp() + 60));
+        peer.node.keyring.seal(&mut stripped).await;
+        stripped.expires = None;
+        receive(Packet::UserMessage(stripped), &mut peer).await;
+
+        let message = peer.chat.lock().await.all_messages[0].clone();
+        assert_eq!(peer.node.keyring.plaintext(&message).await, None);
+
+        let mut intact = sealed(Some(get_timestamp() + 60));
+        peer.node.keyring.seal(&mut intact).await;
+        assert_eq!(peer.node.keyring.plaintext(&intact).await.as_deref(), Some("self-destructing"));
+    }
+}
@@ -0,0 +1,35 @@
+use std::sync::Arc;
+
+use tokio::sync::Mutex;
+
+use crate::state::state_chat::{Chat, Connections, Member};
+use crate::state::state_packets::Packet;
+use crate::state::state_presence::Presence;
+use crate::ui::handle_output::print_notice;
+
+// Aggiorna la nostra voce nella chat e la manda a tutti; i peer che arrivano dopo la trovano nell'Identity
+pub async fn set_presence(presence: Presence, chat: &Arc<Mutex<Chat>>, myself: &Member, connections: &Connections) {
+    chat.lock().await.set_presence(&myself.id, presence.clone());
+
+    let conns = connections.connections.lock().await;
+    for c in conns.iter() {
+        let _ = c.send(Packet::Presence(presence.clone()));
+    }
+}
+
+pub async fn receive_presence(presence: Presence, id: &str, chat: &Arc<Mutex<Chat>>) {
+    let mut chat_lock = chat.lock().await;
+    let changed = chat_lock
+        .member(id)
+        .is_some_and(|m| m.status != presence.status || m.status_message != presence.message);
+
+    if !changed || !chat_lock.set_presence(id, presence.clone()) {
+        return;
+    }
+
+    let name = chat_lock.display_name(Some(id), "");
+    match presence.message {
+        Some(message) => print_notice(&format!("{} is {}: {}", name, presence.status, message)),
+        None => print_notice(&format!("{} is {}", name, presence.status)),
+    }
+}
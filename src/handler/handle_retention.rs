@@ -0,0 +1,31 @@
+use std::sync::Arc;
+
+use tokio::sync::Mutex;
+
+use crate::handler::handle_rules::share_rules;
+use crate::state::state_chat::{Chat, Connections};
+use crate::state::state_node::Node;
+use crate::state::state_retention::{RETENTION_CHECK, Retention};
+use crate::ui::handle_input::get_timestamp;
+use crate::ui::handle_output::print_notice;
+
+// Ogni membro pota la propria copia della storia, così scadenze e limiti valgono ovunque
+// senza che nessuno debba mandare cancellazioni
+pub async fn enforce_retention(chat: Arc<Mutex<Chat>>) {
+    loop {
+        tokio::time::sleep(RETENTION_CHECK).await;
+        chat.lock().await.prune(get_timestamp());
+    }
+}
+
+// Solo l'owner: nuova regola applicata subito e mandata a tutti, firmata insieme alle altre regole
+pub async fn set_retention(retention: Retention, chat: &Arc<Mutex<Chat>>, node: &Node, connections: &Connections) {
+    {
+        let mut chat_lock = chat.lock().await;
+        chat_lock.retention = retention.clone();
+        chat_lock.prune(get_timestamp());
+    }
+
+    share_rules(chat, node, connections).await;
+    print_notice(&format!("Retention: {}", retention));
+}
@@ -0,0 +1,52 @@
+use std::sync::Arc;
+
+use tokio::sync::Mutex;
+
+use crate::crypto::identity::fingerprint;
+use crate::state::state_access::{RoomAccess, RulesSignature};
+use crate::state::state_chat::{Chat, Connections};
+use crate::state::state_node::Node;
+use crate::state::state_packets::Packet;
+use crate::state::state_retention::Retention;
+use crate::ui::handle_input::get_timestamp;
+use crate::ui::handle_output::{print_notice, say};
+
+// Solo l'owner: firma di nuovo le regole appena cambiate e le manda a tutti, così anche
+// il Sync di chi le ha ricevute porta una firma valida
+pub async fn share_rules(chat: &Arc<Mutex<Chat>>, node: &Node, connections: &Connections) {
+    let packet = {
+        let mut chat_lock = chat.lock().await;
+        chat_lock.sign_rules(&node.identity, get_timestamp());
+
+        let Some(signed) = chat_lock.rules.clone() else {
+            return;
+        };
+        Packet::Rules {
+            access: chat_lock.access.clone(),
+            retention: chat_lock.retention.clone(),
+            signed,
+        }
+    };
+
+    let conns = connections.connections.lock().await;
+    for c in conns.iter() {
+        let _ = c.send(packet.clone());
+    }
+}
+
+// Valgono solo se le firma l'owner che conosciamo
+pub async fn receive_rules(access: RoomAccess, retention: Retention, signed: RulesSignature, chat: &Arc<Mutex<Chat>>) {
+    let mut chat_lock = chat.lock().await;
+    let owner = chat_lock.access.owner.as_deref().map(fingerprint);
+    let previous = chat_lock.retention.clone();
+
+    if let Err(reason) = chat_lock.accept_rules(&access, &retention, &signed, owner.as_deref()) {
+        say!("Ignoring {}", reason);
+        return;
+    }
+
+    if previous != retention {
+        chat_lock.prune(get_timestamp());
+        print_notice(&format!("The owner changed the room retention: {}", retention));
+    }
+}
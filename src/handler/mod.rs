@@ -1 +1,6 @@
-pub mod handle_packet;
\ No newline at end of file
+pub mod handle_keys;
+pub mod handle_moderation;
+pub mod handle_packet;
+pub mod handle_presence;
+pub mod handle_retention;
+pub mod handle_rules;
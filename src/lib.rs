@@ -0,0 +1,2 @@
+// Quello che il nodo (main.rs) e il server di relay (bin/p2pchat-relay.rs) hanno in comune
+pub mod relay;
@@ -1,124 +1,413 @@
+mod crypto;
+mod daemon;
 mod discovery;
 mod handler;
 mod network;
+mod notify;
+mod plugin;
+mod script;
 mod state;
 mod ui;
 
-use crate::discovery::find_discovery::find_discovery;
-use crate::discovery::listen_discovery::listen_discovery;
+use crate::crypto::identity::{Identity, fingerprint};
+use crate::daemon::attach::attach_main;
+use crate::daemon::rpc::default_socket;
+use crate::daemon::server::daemon_main;
+use crate::discovery::backend::{BackendKind, DiscoveryContext, backends};
+use crate::handler::handle_retention::enforce_retention;
+use crate::network::bind::bind_listener;
+use crate::network::connection::{connection_main, join_invite};
+use crate::network::interfaces::{format_addrs, local_addrs};
 use crate::network::listen::listen_main;
-use crate::state::state_chat::{self, Chat, Connections, Member};
-use crate::state::state_packets::Packet;
-
-use crate::network::connect_to::connect_to;
-use crate::network::send::send;
-use crate::ui::handle_input::handle_input;
-
-use clap::Parser;
-use local_ip_address::local_ip;
+use crate::network::relay_client::relay_main;
+use crate::script::local::{self, ScriptContext};
+use crate::script::output::JsonOut;
+use crate::script::{EXIT_USAGE, remote};
+use crate::notify::notifier::{NotifierKind, notifiers};
+use crate::plugin::api::plugins;
+use crate::plugin::plugin_host::plugin_main;
+use crate::state::state_chat::{self, Chat, Connections, Member, validate_username};
+use crate::state::state_discovery::{Announcement, DiscoveredPeers};
+use crate::state::state_invite::Invite;
+use crate::state::state_node::Node;
+use crate::state::state_retention::{Retention, parse_size};
+use crate::state::state_session::Session;
+
+use crate::ui::handle_command::parse_duration;
+use crate::ui::handle_export::{ExportFormat, export_main};
+use crate::ui::handle_format::sanitize_line;
+use crate::ui::handle_input::{get_timestamp, handle_input};
+use crate::ui::handle_output::{Output, say, set_output};
+
+use clap::{Parser, Subcommand};
+use p2pchat::relay::state_relay::DEFAULT_RELAY_PORT;
 use rand::random;
 use std::error::Error;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::net::TcpListener;
+use std::time::Duration;
+use tokio::net::lookup_host;
 use tokio::sync::{Mutex, mpsc};
 use uuid::Uuid;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about = "P2P Chat", long_about = None)]
 struct Cli {
-    #[arg(short = 'c', long = "connect", num_args = 2, value_names = ["IP", "PORT"])]
+    #[arg(short = 'c', long = "connect", num_args = 2, value_names = ["IP", "PORT"], global = true)]
     ip_param: Option<Vec<String>>,
 
     #[arg(short = 'q', long = "quit")]
     quit: bool,
 
-    #[arg(short = 'u', long = "username")]
+    #[arg(short = 'u', long = "username", global = true)]
     username: Option<String>,
 
-    #[arg(short = 'd', long = "discovery")]
+    #[arg(short = 'd', long = "discovery", global = true)]
     discovery: bool,
 
     #[arg(short = 'f', long = "filesend")]
     file_send: bool,
 
-    #[arg(short = 'p', long = "port", default_value_t = 0)]
+    #[arg(short = 'p', long = "port", default_value_t = 0, global = true)]
     listening_port: u16,
+
+    #[arg(short = 'r', long = "room", default_value = "general", global = true)]
+    room: String,
+
+    #[arg(short = 's', long = "secret", global = true)]
+    secret: Option<String>,
+
+    #[arg(long = "discovery-backend", value_enum, default_value_t = BackendKind::Multicast)]
+    discovery_backend: BackendKind,
+
+    #[arg(long = "relay", value_name = "HOST[:PORT]", global = true)]
+    relay: Option<String>,
+
+    #[arg(short = 'j', long = "join", value_name = "TOKEN", conflicts_with = "ip_param", global = true)]
+    join: Option<String>,
+
+    #[arg(long = "key-file", global = true)]
+    key_file: Option<PathBuf>,
+
+    #[arg(long = "password", global = true)]
+    password: Option<String>,
+
+    #[arg(long = "allow", value_name = "FINGERPRINT")]
+    allow: Vec<String>,
+
+    #[arg(long = "notify", value_enum, default_value_t = NotifierKind::Bell)]
+    notify: NotifierKind,
+
+    #[arg(long = "notify-command", value_name = "COMMAND")]
+    notify_command: Option<String>,
+
+    #[arg(long = "format")]
+    format: bool,
+
+    #[arg(long = "plugin", value_name = "NAME|COMMAND")]
+    plugin: Vec<String>,
+
+    #[arg(long = "max-age", value_name = "DURATION")]
+    max_age: Option<String>,
+
+    #[arg(long = "max-messages")]
+    max_messages: Option<usize>,
+
+    #[arg(long = "max-bytes", value_name = "SIZE")]
+    max_bytes: Option<String>,
+
+    #[arg(long = "ttl", value_name = "DURATION")]
+    ttl: Option<String>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Connect to a room, wait for its history and write it to a file
+    Export {
+        #[arg(long = "format", value_enum, default_value_t = ExportFormat::Json)]
+        format: ExportFormat,
+
+        #[arg(short = 'o', long = "output")]
+        output: Option<PathBuf>,
+
+        #[arg(long = "wait", default_value = "15s")]
+        wait: String,
+    },
+    /// Keep the node running without a terminal, controlled through a Unix socket
+    Daemon {
+        #[arg(long = "socket")]
+        socket: Option<PathBuf>,
+    },
+    /// Talk to a running daemon
+    Attach {
+        #[arg(long = "socket")]
+        socket: Option<PathBuf>,
+    },
+    /// Send a message and exit; prints {"id", ...} as JSON
+    Send {
+        /// Message text; read from stdin when missing or "-"
+        text: Vec<String>,
+
+        #[arg(long = "reply-to", value_name = "MESSAGE_ID")]
+        reply_to: Option<String>,
+
+        #[arg(long = "wait", default_value = "15s")]
+        wait: String,
+
+        #[arg(long = "socket")]
+        socket: Option<PathBuf>,
+    },
+    /// Print the last messages as JSON lines, and new events with --follow
+    Tail {
+        #[arg(short = 'n', long = "lines", default_value_t = 10)]
+        lines: usize,
+
+        #[arg(short = 'f', long = "follow")]
+        follow: bool,
+
+        #[arg(long = "wait", default_value = "15s")]
+        wait: String,
+
+        #[arg(long = "socket")]
+        socket: Option<PathBuf>,
+    },
+    /// Print the room members as a JSON array
+    Members {
+        #[arg(long = "wait", default_value = "15s")]
+        wait: String,
+
+        #[arg(long = "socket")]
+        socket: Option<PathBuf>,
+    },
+    /// Print the peers found on the LAN as a JSON array
+    Discover {
+        #[arg(long = "timeout", default_value = "3s")]
+        timeout: String,
+    },
+}
+
+impl Command {
+    // I sottocomandi per gli script scrivono solo JSON su stdout
+    fn is_script(&self) -> bool {
+        matches!(
+            self,
+            Command::Send { .. } | Command::Tail { .. } | Command::Members { .. } | Command::Discover { .. }
+        )
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     let args = Cli::parse();
 
+    // il daemon può perdere il terminale da cui è partito: niente prompt e niente panic su stdout.
+    // Neanche attach ha un prompt da ridisegnare
+    if let Some(Command::Daemon { .. } | Command::Attach { .. }) = &args.command {
+        set_output(Output::Log);
+    }
+
     if args.quit {
-        println!("DISCONNECTED");
+        say!("DISCONNECTED");
+        return Ok(());
+    }
+
+    // attach non avvia un nodo: parla solo con quello del daemon
+    if let Some(Command::Attach { socket }) = &args.command {
+        let socket = socket.clone().unwrap_or_else(|| default_socket(&args.room));
+        if let Err(e) = attach_main(&socket).await {
+            say!("{}", e);
+            std::process::exit(1);
+        }
         return Ok(());
     }
 
+    let mut json_out = None;
+    if let Some(command) = &args.command
+        && command.is_script()
+    {
+        set_output(Output::Stderr);
+        json_out = Some(JsonOut::new());
+
+        // errori d'uso prima di avviare il nodo
+        let duration = match command {
+            Command::Send { wait, .. } | Command::Tail { wait, .. } | Command::Members { wait, .. } => script_duration("--wait", wait),
+            Command::Discover { timeout } => script_duration("--timeout", timeout),
+            _ => Ok(Duration::ZERO),
+        };
+        if let Err(e) = duration {
+            say!("{}", e);
+            std::process::exit(EXIT_USAGE);
+        }
+    }
+
+    // senza -c/-j/--relay/-d i sottocomandi per script passano dal daemon della stanza
+    let joins_room = args.ip_param.is_some() || args.join.is_some() || args.relay.is_some() || args.discovery;
+    if let (Some(out), false) = (json_out.as_mut(), joins_room)
+        && let Some(code) = run_remote(out, &args).await
+    {
+        std::process::exit(code);
+    }
+
+    // senza un peer da cui farsi mandare la storia non c'è niente da esportare
+    if matches!(args.command, Some(Command::Export { .. }))
+        && args.ip_param.is_none()
+        && args.join.is_none()
+        && args.relay.is_none()
+        && !args.discovery
+    {
+        say!("export needs a room to read from: use -c, -j, --relay or -d");
+        std::process::exit(2);
+    }
+
+    // l'invito si controlla subito, prima di aprire socket
+    let invite: Option<Invite> = match &args.join {
+        Some(token) => match Invite::decode(token) {
+            Ok(invite) => Some(invite),
+            Err(e) => {
+                say!("Invalid invite: {}", e);
+                return Ok(());
+            }
+        },
+        None => None,
+    };
+    let room = invite.as_ref().map_or(args.room.clone(), |invite| invite.room.clone());
+
+    // regole di retention per la stanza che si crea; entrando in un'altra arrivano col Sync
+    let retention = match room_retention(&args) {
+        Ok(retention) => retention,
+        Err(e) => {
+            say!("{}", e);
+            return Ok(());
+        }
+    };
+
+    let identity = match &args.key_file {
+        Some(path) => Identity::load_or_create(path)?,
+        None => Identity::generate(),
+    };
+    let node = Node::new(
+        identity,
+        args.password.clone(),
+        notifiers(args.notify, args.notify_command.clone()),
+        plugins(if json_out.is_some() { &[] } else { &args.plugin }),
+        args.format,
+    );
+    for notifier in node.notifiers.iter() {
+        say!("Mention notifications: {}", notifier.name());
+    }
+
     let selected_port: u16 = args.listening_port;
     let connections: Connections = Connections::new();
-    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), selected_port);
-    let listener = TcpListener::bind(&addr).await.expect("Failed to bind");
-    let used_port = listener.local_addr()?.port();
-    let my_ip: String = local_ip()
-        .map(|ip| ip.to_string())
-        .unwrap_or_else(|_| "127.0.0.1".to_string());
+    let listener = bind_listener(selected_port).expect("Failed to bind");
+    let listen_addr = listener.local_addr()?;
+    let used_port = listen_addr.port();
+    let my_addrs: Vec<SocketAddr> = local_addrs(used_port, listen_addr.is_ipv6());
 
-    println!("Your local ip: {}", my_ip);
-    println!("Your port: {}", used_port);
-    let username: String = args.username.unwrap_or_else(|| rand_username());
+    for addr in &my_addrs {
+        say!("Your local ip: {}", addr.ip());
+    }
+    say!("Your port: {}", used_port);
+    let username: String = args.username.unwrap_or_else(rand_username);
+    if let Err(reason) = validate_username(&username) {
+        say!("Invalid username: {}", reason);
+        return Ok(());
+    }
 
     let myself = Arc::new(Member::new(
         username.clone(),
-        my_ip.clone(),
-        used_port,
+        my_addrs.clone(),
         Uuid::new_v4().to_string(),
+        node.identity.public_key(),
     ));
-    let chat: Arc<Mutex<Chat>> = Arc::new(Mutex::new(Chat::new()));
+    say!("Your key fingerprint: {}", fingerprint(&myself.pubkey));
+    node.keyring.rotate(&myself.id).await; // prima chiave di gruppo, sostituita da quella della stanza se ci si unisce
+    let chat: Arc<Mutex<Chat>> = Arc::new(Mutex::new(Chat::new(args.room.clone())));
+    say!("Room: {}", args.room);
 
     {
         let mut chat_lock = chat.lock().await;
         chat_lock.add_member((*myself).clone());
+
+        // chi crea la stanza ne è l'owner; entrando in un'altra stanza le regole arrivano col Sync
+        chat_lock.access.owner = Some(myself.pubkey.clone());
+        chat_lock.access.password = args.password.is_some();
+        chat_lock.access.allow = args.allow.clone();
+        chat_lock.retention = retention;
+        chat_lock.sign_rules(&node.identity, get_timestamp());
     }
+    tokio::spawn(enforce_retention(Arc::clone(&chat)));
+
+    let (tx, mut rx) = mpsc::unbounded_channel::<Announcement>();
+    let discovered: DiscoveredPeers = DiscoveredPeers::new();
+    let discovery_ctx = DiscoveryContext {
+        chat: Arc::clone(&chat),
+        myself: Arc::clone(&myself),
+        peers: discovered.clone(),
+        tx,
+        secret: args.secret.clone(),
+    };
+    let discovery_backends = backends(args.discovery_backend);
 
-    let (tx, mut rx) = mpsc::unbounded_channel::<(String, u16)>();
+    for backend in &discovery_backends {
+        say!("Discovery backend: {}", backend.name());
+        backend.start(discovery_ctx.clone());
+    }
 
-    tokio::spawn(listen_discovery(
-        my_ip.clone(),
-        used_port,
-        tx,
-        (*myself.id).to_string(),
-    ));
+    if matches!(args.command, Some(Command::Discover { .. })) {
+        for backend in &discovery_backends {
+            backend.probe(&discovery_ctx);
+        }
+    }
 
     if args.discovery {
-        println!("Searching for other peers...");
-        tokio::spawn(find_discovery((*myself.id).to_string()));
+        say!("Searching for other peers...");
+        for backend in &discovery_backends {
+            backend.probe(&discovery_ctx);
+        }
 
         let myself_dis = Arc::clone(&myself);
         let chat_dis = Arc::clone(&chat);
+        let node_dis = node.clone();
         let conn_dis = connections.clone();
 
+        // ci si connette a ogni peer nuovo della nostra stanza, salvo quelli già arrivati tramite Sync.
+        // Gli altri restano in /discover e si possono raggiungere con /join
         tokio::spawn(async move {
-            while let Some((ip, port)) = rx.recv().await {
+            while let Some(peer) = rx.recv().await {
+                {
+                    let chat_lock = chat_dis.lock().await;
+                    if chat_lock.members.iter().any(|m| m.id == peer.id) {
+                        continue;
+                    }
+
+                    if peer.room != chat_lock.room {
+                        say!(
+                            "Found {} in room {}, use /join to enter it",
+                            sanitize_line(&peer.username),
+                            sanitize_line(&peer.room)
+                        );
+                        continue;
+                    }
+                }
+
                 match connection_main(
-                    ip.clone(),
-                    port,
+                    peer.addrs.clone(),
                     myself_dis.clone(),
                     chat_dis.clone(),
+                    node_dis.clone(),
                     conn_dis.clone(),
                 )
                 .await
                 {
                     Ok(_) => {
-                        println!("Connected to {}:{}", ip, port);
-                        break; // Se si connette correttamente esco dal ciclo, altrimenti continuo a provare
+                        say!("Connected to {} ({})", sanitize_line(&peer.username), format_addrs(&peer.addrs));
                     }
                     Err(_) => {
-                        println!(
-                            "Failed to connect to {}:{}. Trying with next peer...",
-                            ip, port
-                        );
-                        continue;
+                        say!("Failed to connect to {} ({})", sanitize_line(&peer.username), format_addrs(&peer.addrs));
                     }
                 }
             }
@@ -131,19 +420,29 @@ async fn main() -> Result<(), Box<dyn Error>> {
         let port_to_connect: u16 = params[1].parse().expect("Port must be a number");
         let myself_connect = Arc::clone(&myself);
         let chat = chat.clone();
+        let node = node.clone();
         let conn_clone = connections.clone();
 
         tokio::spawn(async move {
+            // lookup_host accetta sia letterali IPv4/IPv6 che hostname
+            let addrs: Vec<SocketAddr> = match lookup_host((ip_to_connect.as_str(), port_to_connect)).await {
+                Ok(addrs) => addrs.collect(),
+                Err(e) => {
+                    say!("Cannot resolve {}: {}", ip_to_connect, e);
+                    return;
+                }
+            };
+
             if let Err(e) = connection_main(
-                ip_to_connect.clone(),
-                port_to_connect,
+                addrs,
                 myself_connect,
                 chat,
+                node,
                 conn_clone,
             )
             .await
             {
-                println!(
+                say!(
                     "Error connecting to {}:{}: {}",
                     ip_to_connect, port_to_connect, e
                 );
@@ -151,67 +450,213 @@ async fn main() -> Result<(), Box<dyn Error>> {
         });
     }
 
+    // JOIN
+    if let Some(invite) = invite {
+        let myself_join = Arc::clone(&myself);
+        let chat = chat.clone();
+        let node = node.clone();
+        let conn_clone = connections.clone();
+
+        say!("Joining room {} (host key {})...", sanitize_line(&invite.room), sanitize_line(&invite.fingerprint));
+        tokio::spawn(async move {
+            if let Err(e) = join_invite(invite, myself_join, chat, node, conn_clone).await {
+                say!("Error joining with the invite: {}", e);
+            }
+        });
+    }
+
+    // RELAY
+    if let Some(relay) = args.relay {
+        let chat = chat.clone();
+        let myself_relay = Arc::clone(&myself);
+        let node = node.clone();
+        let conn_clone = connections.clone();
+
+        tokio::spawn(async move {
+            // senza porta si usa quella di default del relay
+            let resolved = match lookup_host(relay.as_str()).await {
+                Ok(mut addrs) => Ok(addrs.next()),
+                Err(_) => lookup_host((relay.as_str(), DEFAULT_RELAY_PORT)).await.map(|mut a| a.next()),
+            };
+
+            match resolved {
+                Ok(Some(relay_addr)) => relay_main(relay_addr, chat, myself_relay, node, conn_clone).await,
+                Ok(None) | Err(_) => say!("Cannot resolve relay {}", relay),
+            }
+        });
+    }
+
     // LISTEN
     let chat_clone: Arc<Mutex<Chat>> = Arc::clone(&chat);
     let conn_clone: Connections = connections.clone();
     let myself_clone: Arc<Member> = Arc::clone(&myself);
+    let node_clone: Node = node.clone();
 
     tokio::spawn(async move {
         loop {
-            let (stream, _) = listener.accept().await.expect("Failed to accept");
+            let (stream, peer_addr) = listener.accept().await.expect("Failed to accept");
 
             let stream = stream;
 
             let chat_clone = Arc::clone(&chat_clone);
             let myself_in = Arc::clone(&myself_clone);
+            let node_in = node_clone.clone();
             let conn_clone = conn_clone.clone();
 
             let (reader, writer) = stream.into_split();
             tokio::spawn(listen_main(
-                chat_clone, myself_in, reader, writer, conn_clone,
+                chat_clone,
+                myself_in,
+                node_in,
+                reader,
+                writer,
+                conn_clone,
+                Session::incoming(Some(peer_addr)),
             ));
         }
     });
 
+    tokio::spawn(plugin_main(
+        Arc::clone(&chat),
+        Arc::clone(&myself),
+        node.clone(),
+        connections.clone(),
+    ));
+
     let chat_clone = Arc::clone(&chat);
-    handle_input(chat_clone, myself, connections.clone()).await;
+    match args.command {
+        Some(Command::Export { format, output, wait }) => {
+            let Some(wait) = parse_duration(&wait) else {
+                say!("Invalid --wait {}, use e.g. 30s or 2m", wait);
+                return Ok(());
+            };
+
+            if let Err(e) = export_main(format, output, Duration::from_secs(wait), chat_clone, node).await {
+                say!("Export failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Daemon { socket }) => {
+            let socket = socket.unwrap_or_else(|| default_socket(&args.room));
+            if let Err(e) = daemon_main(socket, chat_clone, myself, node, connections.clone()).await {
+                say!("Daemon error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Attach { .. }) => {}
+        Some(command) => {
+            let out = json_out.as_mut().expect("Script output not set up");
+            let ctx = ScriptContext {
+                chat: chat_clone,
+                myself,
+                node,
+                connections: connections.clone(),
+                room,
+            };
+            std::process::exit(run_local(command, out, &ctx, &discovered).await);
+        }
+        None => handle_input(chat_clone, myself, node, connections.clone(), discovered).await,
+    }
 
     Ok(())
 }
 
-fn rand_username() -> String {
-    (0..4)
-        .map(|_| (0x20u8 + (random::<f32>() * 96.0) as u8) as char)
-        .collect()
+// Testo da mandare: gli argomenti, oppure tutto stdin (anche su più righe) se mancano o sono "-"
+fn message_text(text: &[String]) -> Result<String, String> {
+    let text = if text.is_empty() || text == ["-"] {
+        std::io::read_to_string(std::io::stdin()).map_err(|e| format!("cannot read stdin: {}", e))?
+    } else {
+        text.join(" ")
+    };
+
+    let text = text.trim_end_matches('\n').to_string();
+    if text.trim().is_empty() {
+        return Err("nothing to send".to_string());
+    }
+    Ok(text)
 }
 
-async fn connection_main(
-    ip_to_connect: String,
-    port_to_connect: u16,
-    myself: Arc<Member>,
-    chat: Arc<Mutex<Chat>>,
-    conn_clone: Connections,
-) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let stream = connect_to(&ip_to_connect, port_to_connect).await?;
-
-    let (reader, mut writer) = stream.into_split();
-
-    let packet_id = Packet::Identity((*myself).clone(), true);
-    if let Err(e) = send(&mut writer, &packet_id).await {
-        println!("Error sending identity: {}", e);
-    }
+fn script_duration(flag: &str, value: &str) -> Result<Duration, String> {
+    parse_duration(value)
+        .map(Duration::from_secs)
+        .ok_or(format!("Invalid {} {}, use e.g. 30s or 2m", flag, value))
+}
 
-    let packet_init = Packet::InitSyncRequest;
-    if let Err(e) = send(&mut writer, &packet_init).await {
-        println!("Error sending init: {}", e);
-    }
+// Senza nodo, attraverso il socket del daemon; None se il sottocomando ha bisogno di un nodo (discover)
+async fn run_remote(out: &mut JsonOut, args: &Cli) -> Option<i32> {
+    let socket = |s: &Option<PathBuf>| s.clone().unwrap_or_else(|| default_socket(&args.room));
+
+    let code = match args.command.as_ref()? {
+        Command::Send {
+            text, reply_to, socket: s, ..
+        } => match message_text(text) {
+            Ok(text) => remote::send(out, &socket(s), text, reply_to.clone()).await,
+            Err(e) => {
+                say!("{}", e);
+                EXIT_USAGE
+            }
+        },
+        Command::Tail {
+            lines, follow, socket: s, ..
+        } => remote::tail(out, &socket(s), *lines, *follow).await,
+        Command::Members { socket: s, .. } => remote::members(out, &socket(s)).await,
+        _ => return None,
+    };
+    Some(code)
+}
 
-    let chat_clone = Arc::clone(&chat);
-    let myself_in = Arc::clone(&myself);
+// Con un nodo usa e getta già avviato e in collegamento con la stanza
+async fn run_local(command: Command, out: &mut JsonOut, ctx: &ScriptContext, discovered: &DiscoveredPeers) -> i32 {
+    let code = match command {
+        Command::Send { text, reply_to, wait, .. } => match (message_text(&text), script_duration("--wait", &wait)) {
+            (Ok(text), Ok(wait)) => Ok(local::send(out, text, reply_to, wait, ctx).await),
+            (Err(e), _) | (_, Err(e)) => Err(e),
+        },
+        Command::Tail {
+            lines, follow, wait, ..
+        } => match script_duration("--wait", &wait) {
+            Ok(wait) => Ok(local::tail(out, lines, follow, wait, ctx).await),
+            Err(e) => Err(e),
+        },
+        Command::Members { wait, .. } => match script_duration("--wait", &wait) {
+            Ok(wait) => Ok(local::members(out, wait, ctx).await),
+            Err(e) => Err(e),
+        },
+        Command::Discover { timeout } => match script_duration("--timeout", &timeout) {
+            Ok(timeout) => Ok(local::discover(out, timeout, discovered).await),
+            Err(e) => Err(e),
+        },
+        _ => unreachable!("not a script subcommand"),
+    };
+
+    code.unwrap_or_else(|e| {
+        say!("{}", e);
+        EXIT_USAGE
+    })
+}
 
-    tokio::spawn(listen_main(
-        chat_clone, myself_in, reader, writer, conn_clone,
-    ));
+fn room_retention(args: &Cli) -> Result<Retention, String> {
+    let duration = |flag: &str, value: &Option<String>| match value {
+        Some(v) => parse_duration(v)
+            .filter(|secs| *secs > 0)
+            .map(Some)
+            .ok_or(format!("Invalid {} {}, use e.g. 30s, 10m, 7d", flag, v)),
+        None => Ok(None),
+    };
+
+    let max_bytes = match &args.max_bytes {
+        Some(v) => Some(parse_size(v).ok_or(format!("Invalid --max-bytes {}, use e.g. 512k or 2M", v))?),
+        None => None,
+    };
+
+    Ok(Retention {
+        max_age: duration("--max-age", &args.max_age)?,
+        max_count: args.max_messages,
+        max_bytes,
+        ttl: duration("--ttl", &args.ttl)?,
+    })
+}
 
-    Ok(())
+fn rand_username() -> String {
+    format!("guest-{:04x}", random::<u16>())
 }
@@ -0,0 +1,34 @@
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use tokio::net::TcpListener;
+
+use crate::ui::handle_output::say;
+
+// Prova prima un socket dual-stack [::] che accetta sia IPv6 che IPv4 (mappati),
+// se IPv6 non è disponibile ripiega su 0.0.0.0
+pub fn bind_listener(port: u16) -> std::io::Result<TcpListener> {
+    match bind_on(SocketAddr::from((Ipv6Addr::UNSPECIFIED, port))) {
+        Ok(listener) => Ok(listener),
+        Err(e) => {
+            say!("IPv6 not available ({}), listening on IPv4 only", e);
+            bind_on(SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)))
+        }
+    }
+}
+
+fn bind_on(addr: SocketAddr) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+
+    if addr.is_ipv6() {
+        socket.set_only_v6(false)?;
+    }
+
+    #[cfg(not(windows))]
+    socket.set_reuse_address(true)?;
+
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+
+    TcpListener::from_std(socket.into())
+}
@@ -1,13 +1,82 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
 use tokio::net::TcpStream;
+use tokio::task::JoinSet;
+use tokio::time::{sleep, timeout};
+
+use crate::ui::handle_output::say;
+
+const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+const ATTEMPT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Happy eyeballs (RFC 8305 semplificato): i tentativi partono scaglionati di 250ms
+// alternando IPv6 e IPv4, il primo che si connette vince e gli altri vengono annullati
+pub async fn connect_to(addrs: &[SocketAddr]) -> tokio::io::Result<TcpStream> {
+    let mut candidates = interleave_families(addrs).into_iter();
+    let mut attempts: JoinSet<(SocketAddr, tokio::io::Result<TcpStream>)> = JoinSet::new();
+    let mut last_err = std::io::Error::new(std::io::ErrorKind::InvalidInput, "no address to connect to");
+
+    match candidates.next() {
+        Some(first) => {
+            attempts.spawn(attempt(first));
+        }
+        None => return Err(last_err),
+    }
 
-pub async fn connect_to(ip: &str, port: u16) -> tokio::io::Result<TcpStream> {
-    let address = format!("{}:{}", ip, port);
+    loop {
+        tokio::select! {
+            Some(res) = attempts.join_next() => {
+                match res {
+                    Ok((_, Ok(stream))) => return Ok(stream), // il drop del JoinSet annulla gli altri tentativi
+                    Ok((address, Err(e))) => {
+                        say!("Failed to connect to {}: {}", address, e);
+                        last_err = e;
+                    }
+                    Err(e) => last_err = std::io::Error::other(e),
+                }
 
-    match TcpStream::connect(&address).await {
-        Ok(stream) => Ok(stream),
-        Err(e) => {
-            println!("Failed to connect to {}: {}", address, e);
-            Err(e)
+                // un tentativo fallito fa partire subito il successivo
+                if let Some(next) = candidates.next() {
+                    attempts.spawn(attempt(next));
+                } else if attempts.is_empty() {
+                    return Err(last_err);
+                }
+            }
+            _ = sleep(ATTEMPT_DELAY), if candidates.len() > 0 => {
+                if let Some(next) = candidates.next() {
+                    attempts.spawn(attempt(next));
+                }
+            }
         }
     }
-}
\ No newline at end of file
+}
+
+async fn attempt(address: SocketAddr) -> (SocketAddr, tokio::io::Result<TcpStream>) {
+    let res = match timeout(ATTEMPT_TIMEOUT, TcpStream::connect(address)).await {
+        Ok(res) => res,
+        Err(_) => Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "connection timed out")),
+    };
+
+    (address, res)
+}
+
+// Mantiene l'ordine di preferenza ma alterna le famiglie partendo da quella del primo indirizzo
+fn interleave_families(addrs: &[SocketAddr]) -> Vec<SocketAddr> {
+    let first_v6 = addrs.first().is_some_and(|a| a.is_ipv6());
+    let (mut primary, mut secondary): (Vec<SocketAddr>, Vec<SocketAddr>) =
+        addrs.iter().partition(|a| a.is_ipv6() == first_v6);
+    primary.reverse();
+    secondary.reverse();
+
+    let mut out = Vec::with_capacity(addrs.len());
+    while let Some(a) = primary.pop() {
+        out.push(a);
+        if let Some(b) = secondary.pop() {
+            out.push(b);
+        }
+    }
+    out.extend(secondary.into_iter().rev());
+
+    out
+}
@@ -0,0 +1,96 @@
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::Mutex;
+
+use crate::network::connect_to::connect_to;
+use crate::network::listen::listen_main;
+use crate::network::send::send;
+use crate::state::state_chat::{Chat, Connections, Member};
+use crate::state::state_invite::Invite;
+use crate::state::state_node::Node;
+use crate::state::state_packets::Packet;
+use crate::state::state_session::Session;
+use crate::ui::handle_output::say;
+
+pub async fn connection_main(
+    addrs: Vec<SocketAddr>,
+    myself: Arc<Member>,
+    chat: Arc<Mutex<Chat>>,
+    node: Node,
+    conn_clone: Connections,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let stream = connect_to(&addrs).await?;
+    let peer_addr = stream.peer_addr().ok();
+
+    start_session(stream, Session::new(peer_addr), myself, chat, node, conn_clone, None).await;
+
+    Ok(())
+}
+
+pub async fn join_invite(
+    invite: Invite,
+    myself: Arc<Member>,
+    chat: Arc<Mutex<Chat>>,
+    node: Node,
+    conn_clone: Connections,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let stream = connect_to(&invite.addrs).await?;
+    let peer_addr = stream.peer_addr().ok();
+
+    start_session(stream, Session::new(peer_addr), myself, chat, node, conn_clone, Some(invite)).await;
+
+    Ok(())
+}
+
+// Lato di chi si connette: presentazione, richiesta della chat e poi ascolto come per le connessioni in ingresso.
+// All'host si chiede sempre di firmare un nonce; con un invito la sua chiave deve essere quella scritta lì
+// e il segreto dell'invito (con la richiesta della chat) parte solo dopo la firma, così non lo riceve
+// chi si è messo al posto dell'host.
+// session.peer_addr è None quando lo stream passa da un relay e l'indirizzo remoto non è quello del peer
+pub async fn start_session<S>(
+    stream: S,
+    mut session: Session,
+    myself: Arc<Member>,
+    chat: Arc<Mutex<Chat>>,
+    node: Node,
+    conn_clone: Connections,
+    invite: Option<Invite>,
+) where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let (reader, mut writer) = tokio::io::split(stream);
+
+    let packet_id = Packet::Identity(chat.lock().await.me(&myself), true);
+    if let Err(e) = send(&mut writer, &packet_id).await {
+        say!("Error sending identity: {}", e);
+    }
+
+    let nonce = session.new_challenge();
+    if let Err(e) = send(&mut writer, &Packet::Challenge(nonce)).await {
+        say!("Error sending challenge: {}", e);
+    }
+
+    if let Some(invite) = invite {
+        session.expected_fingerprint = Some(invite.fingerprint);
+        session.expected_owner = invite.owner;
+        session.invite_secret = invite.secret;
+    }
+
+    session.joining = true;
+    session.sync_requested = true;
+    if session.invite_secret.is_none()
+        && let Err(e) = send(&mut writer, &Packet::InitSyncRequest).await
+    {
+        say!("Error sending init: {}", e);
+    }
+
+    let chat_clone = Arc::clone(&chat);
+    let myself_in = Arc::clone(&myself);
+
+    tokio::spawn(listen_main(
+        chat_clone, myself_in, node, reader, writer, conn_clone, session,
+    ));
+}
@@ -0,0 +1,60 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use p2pchat::relay::state_relay::PunchStart;
+use tokio::io::DuplexStream;
+use tokio::net::{TcpSocket, TcpStream};
+use tokio::time::{sleep, timeout};
+
+use crate::network::udp_stream::UdpMux;
+use crate::ui::handle_output::say;
+
+const TCP_PUNCH_TIMEOUT: Duration = Duration::from_secs(3);
+const TCP_RETRY: Duration = Duration::from_millis(200);
+
+pub enum Punched {
+    Tcp(TcpStream),
+    Udp(DuplexStream),
+}
+
+// Prima un'apertura simultanea TCP dalla porta locale della connessione di controllo verso quella
+// osservata dal relay per l'altro peer (funziona con i NAT che mantengono la porta), poi UDP
+pub async fn punch(start: &PunchStart, local_tcp: SocketAddr, mux: &UdpMux) -> std::io::Result<Punched> {
+    match tcp_punch(local_tcp, start.tcp).await {
+        Ok(stream) => return Ok(Punched::Tcp(stream)),
+        Err(e) => say!("TCP hole punching to {} failed ({}), trying UDP", start.tcp, e),
+    }
+
+    mux.punch(start.udp, &start.session).await.map(Punched::Udp)
+}
+
+// Socket con SO_REUSEADDR/SO_REUSEPORT, per poter condividere la porta locale con la connessione al relay
+pub fn reusable_socket(addr: SocketAddr) -> std::io::Result<TcpSocket> {
+    let socket = if addr.is_ipv4() { TcpSocket::new_v4()? } else { TcpSocket::new_v6()? };
+
+    socket.set_reuseaddr(true)?;
+    #[cfg(unix)]
+    socket.set_reuseport(true)?;
+
+    Ok(socket)
+}
+
+async fn tcp_punch(local: SocketAddr, peer: SocketAddr) -> std::io::Result<TcpStream> {
+    let attempts = async {
+        loop {
+            let socket = reusable_socket(local)?;
+            socket.bind(local)?;
+
+            // finché il NAT dell'altro lato non ha la mappatura i SYN vengono rifiutati: si riprova
+            match socket.connect(peer).await {
+                Ok(stream) => return Ok(stream),
+                Err(_) => sleep(TCP_RETRY).await,
+            }
+        }
+    };
+
+    match timeout(TCP_PUNCH_TIMEOUT, attempts).await {
+        Ok(res) => res,
+        Err(_) => Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "TCP hole punching timed out")),
+    }
+}
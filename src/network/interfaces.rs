@@ -0,0 +1,50 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use local_ip_address::{list_afinet_netifas, local_ip};
+
+use crate::ui::handle_output::say;
+
+// Tutti gli indirizzi delle interfacce (Docker, VPN, più schede...) con la porta di ascolto.
+// Loopback e link-local IPv6 sono esclusi: da un altro host non sono raggiungibili
+pub fn local_addrs(port: u16, ipv6: bool) -> Vec<SocketAddr> {
+    let mut addrs: Vec<SocketAddr> = vec![];
+
+    // l'interfaccia della route di default va per prima, è quella che funziona più spesso
+    if let Ok(ip) = local_ip() {
+        addrs.push(SocketAddr::new(ip, port));
+    }
+
+    match list_afinet_netifas() {
+        Ok(ifas) => {
+            for (_name, ip) in ifas {
+                let addr = SocketAddr::new(ip, port);
+
+                if is_advertisable(&ip) && (ipv6 || ip.is_ipv4()) && !addrs.contains(&addr) {
+                    addrs.push(addr);
+                }
+            }
+        }
+        Err(e) => say!("Cannot list network interfaces: {}", e),
+    }
+
+    if addrs.is_empty() {
+        addrs.push(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port));
+    }
+
+    addrs
+}
+
+fn is_advertisable(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => !v4.is_loopback() && !v4.is_unspecified() && !v4.is_link_local(),
+        IpAddr::V6(v6) => !v6.is_loopback() && !v6.is_unspecified() && !v6.is_unicast_link_local(),
+    }
+}
+
+pub fn format_addrs(addrs: &[SocketAddr]) -> String {
+    addrs
+        .iter()
+        .map(|a| a.to_string())
+        .collect::<Vec<String>>()
+        .join(", ")
+}
@@ -1,20 +1,23 @@
 use std::sync::Arc;
 
+use crate::ui::handle_output::say;
 use crate::{
-    handler::handle_packet::handle_packet,
+    handler::{handle_keys::rotate_if_leader, handle_packet::handle_packet},
     network::send::send,
     state::{
         state_chat::{Chat, Connections, Member},
+        state_events::ChatEvent,
+        state_node::Node,
         state_packets::Packet,
+        state_session::Session,
     },
 };
 use tokio::{
-    io::{AsyncBufReadExt, BufReader},
-    net::tcp::{OwnedReadHalf, OwnedWriteHalf},
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, BufReader},
     sync::{Mutex, mpsc},
 };
 
-pub async fn get_packet(reader: &mut BufReader<OwnedReadHalf>) -> Option<Packet> {
+pub async fn get_packet<R: AsyncRead + Unpin>(reader: &mut BufReader<R>) -> Option<Packet> {
     let mut line: String = String::new();
 
     match reader.read_line(&mut line).await {
@@ -23,62 +26,119 @@ pub async fn get_packet(reader: &mut BufReader<OwnedReadHalf>) -> Option<Packet>
         Ok(_) => match serde_json::from_str::<Packet>(line.trim()) {
             Ok(packet) => Some(packet),
             Err(e) => {
-                eprintln!("Errore deserializzando pacchetto: {}", e);
-                eprintln!("Contenuto ricevuto: {:?}", line);
+                say!("Errore deserializzando pacchetto: {}", e);
+                say!("Contenuto ricevuto: {:?}", line);
                 None
             }
         },
         Err(e) => {
-            eprintln!("Stream error: {}", e);
+            say!("Stream error: {}", e);
             None
         }
     }
 }
 
-pub async fn listen_main(
+// Generico sul trasporto: metà di un TcpStream, di uno stream passato dal relay o di uno su UDP
+pub async fn listen_main<R, W>(
     chat: Arc<Mutex<Chat>>,
     myself: Arc<Member>,
-    reader: OwnedReadHalf,
-    mut writer: OwnedWriteHalf,
+    node: Node,
+    reader: R,
+    mut writer: W,
     connections: Connections,
-) {
+    mut session: Session,
+) where
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
     let (tx, mut rx) = mpsc::unbounded_channel::<Packet>();
 
-    {
-        let mut c_lock = connections.connections.lock().await;
-        c_lock.push(tx.clone());
+    // i messaggi della chat partono verso il peer solo quando è autorizzato
+    let mut registered = false;
+    let mut tagged = false;
+    if session.authorized {
+        connections.add(tx.clone()).await;
+        registered = true;
     }
 
     tokio::spawn(async move {
         while let Some(packet) = rx.recv().await {
             // appena riceve il mess sul rx lo invia a tutti i membri della chat
-            if let Err(_) = send(&mut writer, &packet).await {
+            if send(&mut writer, &packet).await.is_err() {
                 break;
             }
         }
     });
 
-    let mut remote_id: Option<String> = None; // per sapere chi devo rimuovere quando qualcuno si disconnette
     let mut buf_reader = BufReader::new(reader);
     loop {
         match get_packet(&mut buf_reader).await {
-            Some(packet) => {
-                if let Packet::Identity(member, _) = &packet {
-                    remote_id = Some(member.id.clone());
+            Some(mut packet) => {
+                if let Packet::Identity(member, _) = &mut packet {
+                    // l'id resta quello della prima Identity: chi prova a cambiarlo viene scollegato
+                    if session.remote_id.as_ref().is_some_and(|id| *id != member.id)
+                        || session.remote_key.as_ref().is_some_and(|key| *key != member.pubkey)
+                    {
+                        say!("Peer changed identity, disconnecting");
+                        break;
+                    }
+                    session.remote_id = Some(member.id.clone());
+                    session.remote_key = Some(member.pubkey.clone());
+
+                    if let Some(observed) = session.peer_addr {
+                        member.add_observed(observed.ip());
+                    }
                 }
 
-                handle_packet(packet, &chat, &*myself, tx.clone(), connections.clone()).await;
-            }
-            None => {
-                println!("Peer disconnected");
+                handle_packet(packet, &chat, &myself, &node, &mut session, tx.clone(), connections.clone()).await;
 
-                if let Some(remote_id) = remote_id {
-                    let mut chat_lock = chat.lock().await;
-                    chat_lock.members.retain(|m| m.id != remote_id);
+                if session.close {
+                    say!("Closing connection");
+                    break;
                 }
 
+                if session.authorized && !registered {
+                    connections.add(tx.clone()).await;
+                    registered = true;
+                }
+
+                if registered
+                    && !tagged
+                    && session.verified
+                    && let Some(id) = &session.remote_id
+                {
+                    connections.tag(id, &tx).await;
+                    tagged = true;
+                }
+            }
+            None => {
+                say!("Peer disconnected");
                 break;
             }
         }
     }
+
+    // se non era autorizzato non l'abbiamo mai aggiunto, e senza firma l'id potrebbe essere quello di un altro
+    if registered
+        && session.verified
+        && let Some(remote_id) = session.remote_id
+    {
+        let left = {
+            let mut chat_lock = chat.lock().await;
+            let gone = chat_lock.member(&remote_id).cloned();
+            chat_lock.members.retain(|m| m.id != remote_id);
+
+            if let Some(m) = &gone {
+                node.events.publish(ChatEvent::left(m));
+            }
+            gone.is_some()
+        };
+
+        if left {
+            rotate_if_leader(&chat, &myself, &node, &connections).await;
+        }
+    }
+
+    // senza più sender il task di scrittura finisce e chiude il socket (dopo aver mandato quello in coda)
+    connections.remove(&tx).await;
 }
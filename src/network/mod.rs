@@ -1,3 +1,9 @@
+pub mod bind;
 pub mod send;
 pub mod connect_to;
-pub mod listen;
\ No newline at end of file
+pub mod connection;
+pub mod hole_punch;
+pub mod interfaces;
+pub mod listen;
+pub mod relay_client;
+pub mod udp_stream;
@@ -0,0 +1,315 @@
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use p2pchat::relay::state_relay::{PunchStart, RelayPeer, RelayRequest, RelayResponse};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::{Mutex, mpsc, oneshot};
+use tokio::time::{interval, sleep, timeout};
+
+use crate::network::connection::{connection_main, start_session};
+use crate::network::hole_punch::{Punched, punch, reusable_socket};
+use crate::network::listen::listen_main;
+use crate::network::udp_stream::UdpMux;
+use crate::state::state_chat::{Chat, Connections, Member};
+use crate::state::state_node::Node;
+use crate::state::state_session::Session;
+use crate::ui::handle_format::sanitize_line;
+use crate::ui::handle_output::say;
+
+const LOOKUP_INTERVAL: Duration = Duration::from_secs(10);
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+const START_TIMEOUT: Duration = Duration::from_secs(5); // per PunchStart e Outgoing
+
+// Quello che serve ai task che aprono connessioni tramite il relay
+#[derive(Clone)]
+struct RelayLink {
+    relay: SocketAddr,
+    control: mpsc::UnboundedSender<RelayRequest>,
+    local_tcp: SocketAddr, // porta locale della connessione di controllo, riusata per il punching TCP
+    mux: Option<UdpMux>,
+    punches: Arc<Mutex<HashMap<String, oneshot::Sender<PunchStart>>>>,
+    splices: Arc<Mutex<HashMap<String, oneshot::Sender<String>>>>, // id del peer -> sessione di Connect
+}
+
+// Connessione di controllo verso il relay: ci registra nella stanza, chiede periodicamente
+// chi c'è e si collega a chi non è ancora nella chat: direttamente, con l'hole punching
+// o, come ultima possibilità, passando dal relay
+pub async fn relay_main(
+    relay: SocketAddr,
+    chat: Arc<Mutex<Chat>>,
+    myself: Arc<Member>,
+    node: Node,
+    connections: Connections,
+) {
+    let dialing: Arc<Mutex<HashSet<String>>> = Arc::new(Mutex::new(HashSet::new()));
+
+    let mux = match UdpMux::bind(relay).await {
+        Ok(mux) => Some(mux),
+        Err(e) => {
+            say!("UDP hole punching unavailable: {}", e);
+            None
+        }
+    };
+
+    loop {
+        if let Err(e) = relay_session(relay, &mux, &chat, &myself, &node, &connections, &dialing).await {
+            say!("Relay {} unreachable: {}", relay, e);
+        } else {
+            say!("Relay {} closed the connection", relay);
+        }
+
+        sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn relay_session(
+    relay: SocketAddr,
+    mux: &Option<UdpMux>,
+    chat: &Arc<Mutex<Chat>>,
+    myself: &Arc<Member>,
+    node: &Node,
+    connections: &Connections,
+    dialing: &Arc<Mutex<HashSet<String>>>,
+) -> std::io::Result<()> {
+    let stream = reusable_socket(relay)?.connect(relay).await?;
+    let local_tcp = stream.local_addr()?;
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    let mut ticker = interval(LOOKUP_INTERVAL);
+
+    let (control, mut control_rx) = mpsc::unbounded_channel::<RelayRequest>();
+    let link = RelayLink {
+        relay,
+        control,
+        local_tcp,
+        mux: mux.clone(),
+        punches: Arc::new(Mutex::new(HashMap::new())),
+        splices: Arc::new(Mutex::new(HashMap::new())),
+    };
+
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                // si ripete la registrazione perché la stanza può cambiare dopo un Sync e il nome dopo /nick
+                let (room, username) = {
+                    let chat_lock = chat.lock().await;
+                    (chat_lock.room.clone(), chat_lock.me(myself).username)
+                };
+                let register = RelayRequest::Register {
+                    id: myself.id.clone(),
+                    username,
+                    room: room.clone(),
+                    addrs: myself.addrs.clone(),
+                };
+
+                write_request(&mut writer, &register).await?;
+                write_request(&mut writer, &RelayRequest::Lookup(room)).await?;
+            }
+            Some(request) = control_rx.recv() => {
+                write_request(&mut writer, &request).await?;
+            }
+            line = lines.next_line() => {
+                let Some(line) = line? else {
+                    return Ok(());
+                };
+
+                match serde_json::from_str::<RelayResponse>(line.trim()) {
+                    Ok(RelayResponse::Members(_, members)) => {
+                        for peer in members {
+                            dial_peer(&link, peer, chat, myself, node, connections, dialing).await;
+                        }
+                    }
+                    Ok(RelayResponse::Incoming { from, session }) => {
+                        let chat = Arc::clone(chat);
+                        let myself = Arc::clone(myself);
+                        let node = node.clone();
+                        let connections = connections.clone();
+
+                        tokio::spawn(async move {
+                            match relay_accept(relay, session).await {
+                                Ok(stream) => {
+                                    let (reader, writer) = stream.into_split();
+                                    listen_main(chat, myself, node, reader, writer, connections, Session::incoming(None)).await;
+                                }
+                                Err(e) => say!("Relayed connection from {} failed: {}", sanitize_line(&from), e),
+                            }
+                        });
+                    }
+                    Ok(RelayResponse::Outgoing { to, session }) => {
+                        if let Some(waiting) = link.splices.lock().await.remove(&to) {
+                            let _ = waiting.send(session);
+                        }
+                    }
+                    Ok(RelayResponse::PunchStart(start)) if start.initiator => {
+                        if let Some(waiting) = link.punches.lock().await.remove(&start.peer) {
+                            let _ = waiting.send(start);
+                        }
+                    }
+                    Ok(RelayResponse::PunchStart(start)) => {
+                        let link = link.clone();
+                        let chat = Arc::clone(chat);
+                        let myself = Arc::clone(myself);
+                        let node = node.clone();
+                        let connections = connections.clone();
+
+                        // lato che riceve: se il punching riesce si comporta come per una connessione in ingresso
+                        tokio::spawn(async move {
+                            let Some(mux) = &link.mux else {
+                                return;
+                            };
+
+                            match punch(&start, link.local_tcp, mux).await {
+                                Ok(Punched::Tcp(stream)) => {
+                                    let (reader, writer) = stream.into_split();
+                                    listen_main(chat, myself, node, reader, writer, connections, Session::incoming(None)).await;
+                                }
+                                Ok(Punched::Udp(stream)) => {
+                                    let (reader, writer) = tokio::io::split(stream);
+                                    listen_main(chat, myself, node, reader, writer, connections, Session::incoming(None)).await;
+                                }
+                                Err(e) => say!("Hole punching from {} failed: {}", sanitize_line(&start.peer), e),
+                            }
+                        });
+                    }
+                    Ok(RelayResponse::Registered(_, token)) => {
+                        if let Some(mux) = &link.mux {
+                            mux.set_hello(myself.id.clone(), token).await;
+                        }
+                    }
+                    Ok(RelayResponse::Error(e)) => say!("Relay error: {}", sanitize_line(&e)),
+                    Ok(RelayResponse::Spliced) => {}
+                    Err(e) => say!("Invalid relay response: {}", e),
+                }
+            }
+        }
+    }
+}
+
+// Solo l'id minore chiama, altrimenti i due peer aprirebbero due connessioni in parallelo
+async fn dial_peer(
+    link: &RelayLink,
+    peer: RelayPeer,
+    chat: &Arc<Mutex<Chat>>,
+    myself: &Arc<Member>,
+    node: &Node,
+    connections: &Connections,
+    dialing: &Arc<Mutex<HashSet<String>>>,
+) {
+    if peer.id <= myself.id || chat.lock().await.members.iter().any(|m| m.id == peer.id) {
+        return;
+    }
+
+    if !dialing.lock().await.insert(peer.id.clone()) {
+        return; // tentativo già in corso
+    }
+
+    let link = link.clone();
+    let chat = Arc::clone(chat);
+    let myself = Arc::clone(myself);
+    let node = node.clone();
+    let connections = connections.clone();
+    let dialing = Arc::clone(dialing);
+
+    tokio::spawn(async move {
+        if connection_main(peer.addrs.clone(), myself.clone(), chat.clone(), node.clone(), connections.clone())
+            .await
+            .is_ok()
+        {
+            dialing.lock().await.remove(&peer.id);
+            return;
+        }
+
+        say!("Direct connection to {} failed, trying hole punching", sanitize_line(&peer.username));
+        match punch_peer(&link, &peer.id).await {
+            Ok(Punched::Tcp(stream)) => start_session(stream, Session::new(None), myself, chat, node, connections, None).await,
+            Ok(Punched::Udp(stream)) => start_session(stream, Session::new(None), myself, chat, node, connections, None).await,
+            Err(e) => {
+                say!("Hole punching to {} failed ({}), using the relay", sanitize_line(&peer.username), e);
+
+                match relay_connect(&link, &peer.id).await {
+                    Ok(stream) => start_session(stream, Session::new(None), myself, chat, node, connections, None).await,
+                    Err(e) => say!("Relayed connection to {} failed: {}", sanitize_line(&peer.username), e),
+                }
+            }
+        }
+
+        dialing.lock().await.remove(&peer.id);
+    });
+}
+
+async fn punch_peer(link: &RelayLink, to: &str) -> std::io::Result<Punched> {
+    let Some(mux) = &link.mux else {
+        return Err(std::io::Error::other("no UDP socket"));
+    };
+
+    let (tx, rx) = oneshot::channel();
+    link.punches.lock().await.insert(to.to_string(), tx);
+    let _ = link.control.send(RelayRequest::Punch { to: to.to_string() });
+
+    let start = match timeout(START_TIMEOUT, rx).await {
+        Ok(Ok(start)) => start,
+        _ => {
+            link.punches.lock().await.remove(to);
+            return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "relay did not start punching"));
+        }
+    };
+
+    punch(&start, link.local_tcp, mux).await
+}
+
+// Il relay manda la sessione su Outgoing, poi si entra come l'altro peer con Accept
+async fn relay_connect(link: &RelayLink, to: &str) -> std::io::Result<TcpStream> {
+    let (tx, rx) = oneshot::channel();
+    link.splices.lock().await.insert(to.to_string(), tx);
+    let _ = link.control.send(RelayRequest::Connect { to: to.to_string() });
+
+    let session = match timeout(START_TIMEOUT, rx).await {
+        Ok(Ok(session)) => session,
+        _ => {
+            link.splices.lock().await.remove(to);
+            return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "relay did not open a session"));
+        }
+    };
+
+    relay_accept(link.relay, session).await
+}
+
+async fn relay_accept(relay: SocketAddr, session: String) -> std::io::Result<TcpStream> {
+    let mut stream = TcpStream::connect(relay).await?;
+
+    write_request(&mut stream, &RelayRequest::Accept(session)).await?;
+    wait_spliced(&mut stream).await?;
+
+    Ok(stream)
+}
+
+// Si legge un byte alla volta: subito dopo Spliced arrivano già i Packet dell'altro peer,
+// che non devono finire in un buffer che poi andrebbe perso
+async fn wait_spliced(stream: &mut TcpStream) -> std::io::Result<()> {
+    let mut line: Vec<u8> = vec![];
+
+    loop {
+        let byte = stream.read_u8().await?;
+        if byte == b'\n' {
+            break;
+        }
+        line.push(byte);
+    }
+
+    match serde_json::from_slice::<RelayResponse>(&line) {
+        Ok(RelayResponse::Spliced) => Ok(()),
+        Ok(RelayResponse::Error(e)) => Err(std::io::Error::other(e)),
+        Ok(other) => Err(std::io::Error::other(format!("unexpected relay response {:?}", other))),
+        Err(e) => Err(std::io::Error::other(e)),
+    }
+}
+
+async fn write_request<W: AsyncWrite + Unpin>(writer: &mut W, request: &RelayRequest) -> std::io::Result<()> {
+    let mut data = serde_json::to_vec(request).expect("Failed to serialize");
+    data.push(b'\n');
+    writer.write_all(&data).await?;
+    writer.flush().await
+}
@@ -1,8 +1,8 @@
-use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 
 use crate::state::state_packets::Packet;
 
-pub async fn send(stream: &mut tokio::net::tcp::OwnedWriteHalf, packet: &Packet) -> tokio::io::Result<()> {
+pub async fn send<W: AsyncWrite + Unpin>(stream: &mut W, packet: &Packet) -> tokio::io::Result<()> {
     let mut data = serde_json::to_vec(packet).expect("Failed to serialize");
 
     data.push(b'\n'); // framing
@@ -0,0 +1,545 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use p2pchat::relay::state_relay::{PunchMessage, UDP_CONTROL};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+use tokio::net::UdpSocket;
+use tokio::sync::{Mutex, mpsc};
+use tokio::time::{interval, sleep, timeout};
+
+use crate::ui::handle_output::say;
+
+// Datagrammi dello stream affidabile: [tipo][seq o ack u32 big endian][payload]
+const DATA: u8 = 1;
+const ACK: u8 = 2;
+const FIN: u8 = 3;
+
+const MSS: usize = 1200; // sta in un datagramma senza frammentazione anche con IPv6 e tunnel
+const WINDOW: usize = 64;
+const RTO: Duration = Duration::from_millis(250);
+const TICK: Duration = Duration::from_millis(50);
+const KEEPALIVE: Duration = Duration::from_secs(5); // tiene aperta la mappatura del NAT
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+const HELLO_INTERVAL: Duration = Duration::from_secs(10);
+const PUNCH_INTERVAL: Duration = Duration::from_millis(200);
+const PUNCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Un solo socket UDP per nodo: serve per farsi vedere dal relay, per il punching e per tutti gli
+// stream verso i peer. I datagrammi vengono smistati in base all'indirizzo sorgente
+#[derive(Clone)]
+pub struct UdpMux {
+    socket: Arc<UdpSocket>,
+    routes: Arc<Mutex<HashMap<SocketAddr, mpsc::UnboundedSender<Vec<u8>>>>>,
+    hello: Arc<Mutex<Option<PunchMessage>>>, // arriva con Registered, prima non c'è niente da mandare
+    relay: SocketAddr,
+}
+
+impl UdpMux {
+    // Stessa famiglia del relay, così le mappature che ci comunica sono direttamente usabili
+    pub async fn bind(relay: SocketAddr) -> std::io::Result<Self> {
+        let local = if relay.is_ipv4() {
+            SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))
+        } else {
+            SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0))
+        };
+
+        let mux = Self {
+            socket: Arc::new(UdpSocket::bind(local).await?),
+            routes: Arc::new(Mutex::new(HashMap::new())),
+            hello: Arc::new(Mutex::new(None)),
+            relay,
+        };
+
+        tokio::spawn(mux.clone().recv_loop(relay));
+        tokio::spawn(mux.clone().hello_loop());
+
+        Ok(mux)
+    }
+
+    // Il token cambia a ogni connessione di controllo: il primo Hello parte subito
+    pub async fn set_hello(&self, id: String, token: String) {
+        let hello = PunchMessage::Hello(id, token);
+        self.send_control(self.relay, &hello).await;
+        *self.hello.lock().await = Some(hello);
+    }
+
+    async fn hello_loop(self) {
+        loop {
+            let hello = self.hello.lock().await.clone();
+            if let Some(hello) = hello {
+                self.send_control(self.relay, &hello).await;
+            }
+            sleep(HELLO_INTERVAL).await;
+        }
+    }
+
+    async fn recv_loop(self, relay: SocketAddr) {
+        let mut buf = [0u8; 2048];
+        let mut mapping: Option<SocketAddr> = None;
+
+        loop {
+            let Ok((len, src)) = self.socket.recv_from(&mut buf).await else {
+                continue;
+            };
+            let src = SocketAddr::new(src.ip().to_canonical(), src.port());
+
+            if let Some(route) = self.routes.lock().await.get(&src) {
+                let _ = route.send(buf[..len].to_vec());
+                continue;
+            }
+
+            if src == relay
+                && len > 0
+                && buf[0] == UDP_CONTROL
+                && let Ok(PunchMessage::Mapping(addr)) = serde_json::from_slice(&buf[1..len])
+                && mapping != Some(addr)
+            {
+                say!("Public UDP mapping: {}", addr);
+                mapping = Some(addr);
+            }
+        }
+    }
+
+    async fn send_control(&self, to: SocketAddr, message: &PunchMessage) {
+        let mut data = vec![UDP_CONTROL];
+        data.extend(serde_json::to_vec(message).expect("Failed to serialize"));
+        let _ = self.socket.send_to(&data, to).await;
+    }
+
+    // Entrambi i peer mandano Punch all'indirizzo pubblico dell'altro: i primi pacchetti aprono
+    // la mappatura nel proprio NAT, quelli dopo passano. Lo stream è pronto quando dall'altro lato
+    // arriva un Punch con la sessione decisa dal relay; qualunque altra cosa viene scartata
+    pub async fn punch(&self, peer: SocketAddr, session: &str) -> std::io::Result<DuplexStream> {
+        let (tx, mut rx) = mpsc::unbounded_channel::<Vec<u8>>();
+        self.routes.lock().await.insert(peer, tx);
+
+        let punched = timeout(PUNCH_TIMEOUT, async {
+            let mut ticker = interval(PUNCH_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => {
+                        self.send_control(peer, &PunchMessage::Punch(session.to_string())).await;
+                    }
+                    Some(datagram) = rx.recv() => {
+                        if is_punch(&datagram, session) {
+                            break;
+                        }
+                    }
+                }
+            }
+        })
+        .await;
+
+        if punched.is_err() {
+            self.routes.lock().await.remove(&peer);
+            return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "UDP hole punching timed out"));
+        }
+
+        // un ultimo Punch nel caso l'altro lato non abbia ancora ricevuto niente da noi
+        self.send_control(peer, &PunchMessage::Punch(session.to_string())).await;
+
+        let (app, transport) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(self.clone().drive(peer, session.to_string(), rx, transport));
+
+        Ok(app)
+    }
+
+    // ARQ a finestra fissa: ack cumulativi, chi riceve tiene da parte i segmenti fuori ordine
+    // e chi manda ritrasmette tutto quello non confermato dopo RTO. Basta per i Packet della chat,
+    // non è un TCP completo (niente controllo di congestione)
+    async fn drive(self, peer: SocketAddr, session: String, mut rx: mpsc::UnboundedReceiver<Vec<u8>>, transport: DuplexStream) {
+        let (mut app_reader, mut app_writer) = tokio::io::split(transport);
+        let mut buf = vec![0u8; MSS];
+
+        let mut next_seq: u32 = 0;
+        let mut unacked: VecDeque<(u32, Vec<u8>)> = VecDeque::new();
+        let mut last_retransmit = Instant::now();
+
+        let mut expected: u32 = 0;
+        let mut out_of_order: BTreeMap<u32, Vec<u8>> = BTreeMap::new();
+
+        let mut fin_sent = false;
+        let mut fin_received = false;
+        let mut last_heard = Instant::now();
+        let mut last_sent = Instant::now();
+        let mut ticker = interval(TICK);
+
+        // un ACK vuoto dice all'altro lato che siamo già sullo stream
+        let mut peer_streaming = false;
+        let _ = self.socket.send_to(&frame(ACK, 0, &[]), peer).await;
+
+        loop {
+            tokio::select! {
+                n = app_reader.read(&mut buf), if !fin_sent && unacked.len() < WINDOW => {
+                    let segment = match n {
+                        Ok(0) | Err(_) => {
+                            fin_sent = true;
+                            frame(FIN, next_seq, &[])
+                        }
+                        Ok(n) => frame(DATA, next_seq, &buf[..n]),
+                    };
+
+                    if unacked.is_empty() {
+                        last_retransmit = Instant::now();
+                    }
+                    let _ = self.socket.send_to(&segment, peer).await;
+                    last_sent = Instant::now();
+                    unacked.push_back((next_seq, segment));
+                    next_seq += 1;
+                }
+                datagram = rx.recv() => {
+                    let Some(datagram) = datagram else {
+                        break;
+                    };
+                    last_heard = Instant::now();
+
+                    // l'altro lato sta ancora facendo il punching: il nostro ultimo Punch si è perso
+                    if is_punch(&datagram, &session) {
+                        if !peer_streaming {
+                            self.send_control(peer, &PunchMessage::Punch(session.clone())).await;
+                        }
+                        continue;
+                    }
+                    if datagram.len() < 5 || datagram[0] == UDP_CONTROL {
+                        continue;
+                    }
+                    peer_streaming = true;
+                    let seq = u32::from_be_bytes([datagram[1], datagram[2], datagram[3], datagram[4]]);
+
+                    match datagram[0] {
+                        ACK => {
+                            while unacked.front().is_some_and(|(s, _)| *s < seq) {
+                                unacked.pop_front();
+                            }
+                            last_retransmit = Instant::now();
+                        }
+                        DATA | FIN => {
+                            if seq >= expected && seq < expected + 2 * WINDOW as u32 {
+                                out_of_order.insert(seq, datagram);
+                            }
+
+                            while let Some(segment) = out_of_order.remove(&expected) {
+                                expected += 1;
+                                if segment[0] == FIN {
+                                    fin_received = true;
+                                    let _ = app_writer.shutdown().await;
+                                } else if app_writer.write_all(&segment[5..]).await.is_err() {
+                                    fin_sent = true; // l'applicazione ha chiuso
+                                }
+                            }
+
+                            let _ = self.socket.send_to(&frame(ACK, expected, &[]), peer).await;
+                            last_sent = Instant::now();
+                        }
+                        _ => {}
+                    }
+                }
+                _ = ticker.tick() => {
+                    if !unacked.is_empty() && last_retransmit.elapsed() >= RTO {
+                        for (_, segment) in &unacked {
+                            let _ = self.socket.send_to(segment, peer).await;
+                        }
+                        last_retransmit = Instant::now();
+                        last_sent = Instant::now();
+                    }
+
+                    if last_sent.elapsed() >= KEEPALIVE {
+                        let _ = self.socket.send_to(&frame(ACK, expected, &[]), peer).await;
+                        last_sent = Instant::now();
+                    }
+
+                    if last_heard.elapsed() >= IDLE_TIMEOUT || (fin_sent && fin_received && unacked.is_empty()) {
+                        break;
+                    }
+                }
+            }
+        }
+
+        self.routes.lock().await.remove(&peer);
+    }
+}
+
+fn is_punch(datagram: &[u8], session: &str) -> bool {
+    datagram.first() == Some(&UDP_CONTROL)
+        && matches!(serde_json::from_slice(&datagram[1..]), Ok(PunchMessage::Punch(s)) if s == session)
+}
+
+fn frame(kind: u8, seq: u32, payload: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(5 + payload.len());
+    data.push(kind);
+    data.extend(seq.to_be_bytes());
+    data.extend(payload);
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use p2pchat::relay::relay_server::serve;
+    use p2pchat::relay::state_relay::{PunchStart, RelayRequest, RelayResponse};
+    use rand::{random, random_range};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use tokio::io::{AsyncBufReadExt, BufReader, Lines};
+    use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+    use tokio::net::{TcpListener, TcpStream};
+
+    const TEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+    fn local(socket: &UdpSocket) -> SocketAddr {
+        SocketAddr::from((Ipv4Addr::LOCALHOST, socket.local_addr().unwrap().port()))
+    }
+
+    // Il "relay" di un mux senza relay: riceve gli Hello e non risponde mai
+    async fn mux() -> (UdpMux, UdpSocket) {
+        let relay = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mux = UdpMux::bind(local(&relay)).await.unwrap();
+        (mux, relay)
+    }
+
+    // Rete simulata tra due mux: ognuno parla con la sua estremità, che inoltra all'altro lato.
+    // Ogni datagramma si perde con probabilità `loss` o viene ritardato con probabilità `delay`,
+    // così arriva dopo quelli partiti dopo di lui
+    async fn link(a: SocketAddr, b: SocketAddr, loss: f64, delay: f64) -> (SocketAddr, SocketAddr) {
+        let facing_a = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let facing_b = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let ends = (local(&facing_a), local(&facing_b));
+
+        for (from, to, dest) in [(facing_a.clone(), facing_b.clone(), b), (facing_b, facing_a, a)] {
+            tokio::spawn(async move {
+                let mut buf = [0u8; 2048];
+                while let Ok((len, _)) = from.recv_from(&mut buf).await {
+                    let datagram = buf[..len].to_vec();
+
+                    if random::<f64>() < loss {
+                        continue;
+                    }
+                    if random::<f64>() < delay {
+                        let to = to.clone();
+                        tokio::spawn(async move {
+                            sleep(Duration::from_millis(random_range(5..40))).await;
+                            let _ = to.send_to(&datagram, dest).await;
+                        });
+                        continue;
+                    }
+                    let _ = to.send_to(&datagram, dest).await;
+                }
+            });
+        }
+
+        ends
+    }
+
+    // Un flusso più lungo della finestra in un verso, una risposta breve nell'altro, poi la chiusura
+    async fn exchange(mut a: DuplexStream, mut b: DuplexStream) {
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        let sent = data.clone();
+
+        let writer = tokio::spawn(async move {
+            a.write_all(&sent).await.unwrap();
+            let mut reply = [0u8; 5];
+            a.read_exact(&mut reply).await.unwrap();
+            assert_eq!(&reply, b"done!");
+            a.shutdown().await.unwrap();
+        });
+
+        let mut received = vec![0u8; data.len()];
+        timeout(TEST_TIMEOUT, b.read_exact(&mut received)).await.unwrap().unwrap();
+        assert!(received == data, "stream corrupted");
+
+        b.write_all(b"done!").await.unwrap();
+        let mut rest = vec![];
+        timeout(TEST_TIMEOUT, b.read_to_end(&mut rest)).await.unwrap().unwrap();
+        assert!(rest.is_empty());
+        writer.await.unwrap();
+    }
+
+    async fn punch_over(loss: f64, delay: f64) {
+        let ((a, _ra), (b, _rb)) = (mux().await, mux().await);
+        let (to_b, to_a) = link(local(&a.socket), local(&b.socket), loss, delay).await;
+
+        let (a_stream, b_stream) = tokio::join!(a.punch(to_b, "s1"), b.punch(to_a, "s1"));
+        exchange(a_stream.unwrap(), b_stream.unwrap()).await;
+    }
+
+    #[tokio::test]
+    async fn stream_over_lossy_link() {
+        punch_over(0.2, 0.0).await;
+    }
+
+    #[tokio::test]
+    async fn stream_over_reordering_link() {
+        punch_over(0.0, 0.3).await;
+    }
+
+    #[tokio::test]
+    async fn stream_over_lossy_reordering_link() {
+        punch_over(0.1, 0.2).await;
+    }
+
+    // NAT con filtro: verso l'esterno passa tutto, dall'esterno solo dopo che l'host ha già scritto
+    // a quel peer. inside è l'indirizzo a cui scrive l'host, dest la parte pubblica dell'altro NAT
+    struct Nat {
+        inside: Arc<UdpSocket>,
+        outside: Arc<UdpSocket>,
+        dropped: Arc<AtomicUsize>,
+    }
+
+    async fn nat() -> Nat {
+        Nat {
+            inside: Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap()),
+            outside: Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap()),
+            dropped: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    fn run_nat(nat: &Nat, host: SocketAddr, dest: SocketAddr) {
+        let opened = Arc::new(AtomicBool::new(false));
+
+        let (inside, outside, flag) = (nat.inside.clone(), nat.outside.clone(), opened.clone());
+        tokio::spawn(async move {
+            let mut buf = [0u8; 2048];
+            while let Ok((len, _)) = inside.recv_from(&mut buf).await {
+                flag.store(true, Ordering::SeqCst);
+                let _ = outside.send_to(&buf[..len], dest).await;
+            }
+        });
+
+        let (inside, outside, dropped) = (nat.inside.clone(), nat.outside.clone(), nat.dropped.clone());
+        tokio::spawn(async move {
+            let mut buf = [0u8; 2048];
+            while let Ok((len, _)) = outside.recv_from(&mut buf).await {
+                if !opened.load(Ordering::SeqCst) {
+                    dropped.fetch_add(1, Ordering::SeqCst);
+                    continue;
+                }
+                let _ = inside.send_to(&buf[..len], host).await;
+            }
+        });
+    }
+
+    #[tokio::test]
+    async fn punch_through_filtering_nats() {
+        let ((a, _ra), (b, _rb)) = (mux().await, mux().await);
+        let (nat_a, nat_b) = (nat().await, nat().await);
+        run_nat(&nat_a, local(&a.socket), local(&nat_b.outside));
+        run_nat(&nat_b, local(&b.socket), local(&nat_a.outside));
+
+        // a comincia da solo: i suoi Punch si fermano al NAT di b, che non ha ancora scritto niente
+        let a_punch = tokio::spawn({
+            let (a, to_b) = (a.clone(), local(&nat_a.inside));
+            async move { a.punch(to_b, "s1").await }
+        });
+        sleep(Duration::from_millis(600)).await;
+        assert!(nat_b.dropped.load(Ordering::SeqCst) > 0);
+
+        // il primo Punch di b apre il suo NAT e da lì passano anche quelli di a
+        let b_stream = b.punch(local(&nat_b.inside), "s1").await.unwrap();
+        let a_stream = a_punch.await.unwrap().unwrap();
+        exchange(a_stream, b_stream).await;
+    }
+
+    #[tokio::test]
+    async fn punch_needs_the_same_session() {
+        let ((a, _ra), (b, _rb)) = (mux().await, mux().await);
+        let (to_b, to_a) = link(local(&a.socket), local(&b.socket), 0.0, 0.0).await;
+
+        let (a_stream, b_stream) = tokio::join!(a.punch(to_b, "s1"), b.punch(to_a, "s2"));
+        assert!(a_stream.is_err() && b_stream.is_err());
+    }
+
+    #[tokio::test]
+    async fn stray_datagrams_do_not_complete_the_punch() {
+        let (a, _ra) = mux().await;
+        let stray = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let a_addr = local(&a.socket);
+
+        // dall'indirizzo atteso arriva di tutto tranne un Punch della sessione giusta
+        let sender = tokio::spawn(async move {
+            let mut wrong_session = vec![UDP_CONTROL];
+            wrong_session.extend(serde_json::to_vec(&PunchMessage::Punch("other".to_string())).unwrap());
+
+            loop {
+                let _ = stray.send_to(&frame(DATA, 0, b"hello"), a_addr).await;
+                let _ = stray.send_to(&frame(ACK, 0, &[]), a_addr).await;
+                let _ = stray.send_to(&wrong_session, a_addr).await;
+                let _ = stray.send_to(b"garbage", a_addr).await;
+                sleep(Duration::from_millis(100)).await;
+            }
+        });
+
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        assert!(a.punch(local(&peer), "s1").await.is_err()); // stray non è il peer
+        sender.abort();
+    }
+
+    // Registra id sul relay e gli collega un mux con il token ricevuto
+    async fn register(relay: SocketAddr, id: &str) -> (Lines<BufReader<OwnedReadHalf>>, OwnedWriteHalf, UdpMux) {
+        let (reader, mut writer) = TcpStream::connect(relay).await.unwrap().into_split();
+        let request = RelayRequest::Register {
+            id: id.to_string(),
+            username: id.to_string(),
+            room: "general".to_string(),
+            addrs: vec![],
+        };
+        let mut data = serde_json::to_vec(&request).unwrap();
+        data.push(b'\n');
+        writer.write_all(&data).await.unwrap();
+
+        let mut lines = BufReader::new(reader).lines();
+        let line = lines.next_line().await.unwrap().unwrap();
+        let RelayResponse::Registered(_, token) = serde_json::from_str(&line).unwrap() else {
+            panic!("expected Registered");
+        };
+
+        let mux = UdpMux::bind(relay).await.unwrap();
+        mux.set_hello(id.to_string(), token).await;
+        (lines, writer, mux)
+    }
+
+    async fn punch_start(lines: &mut Lines<BufReader<OwnedReadHalf>>) -> Option<PunchStart> {
+        let line = timeout(Duration::from_secs(5), lines.next_line()).await.unwrap().unwrap().unwrap();
+        match serde_json::from_str(&line).unwrap() {
+            RelayResponse::PunchStart(start) => Some(start),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn relay_coordinated_punch() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let relay = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener));
+
+        let (mut a_lines, mut a_control, a) = register(relay, "a").await;
+        let (mut b_lines, _b_control, b) = register(relay, "b").await;
+
+        // gli Hello UDP possono arrivare dopo la richiesta: finché manca una mappatura il relay risponde Error
+        let mut request = serde_json::to_vec(&RelayRequest::Punch { to: "b".to_string() }).unwrap();
+        request.push(b'\n');
+        let mut a_start = None;
+        for _ in 0..20 {
+            a_control.write_all(&request).await.unwrap();
+            if let Some(start) = punch_start(&mut a_lines).await {
+                a_start = Some(start);
+                break;
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+
+        let a_start = a_start.expect("relay never started the punch");
+        let b_start = punch_start(&mut b_lines).await.expect("b did not get PunchStart");
+        assert_eq!((a_start.peer.as_str(), b_start.peer.as_str()), ("b", "a"));
+        assert!(a_start.initiator && !b_start.initiator);
+        assert_eq!(a_start.session, b_start.session);
+
+        let (a_stream, b_stream) = tokio::join!(
+            a.punch(a_start.udp, &a_start.session),
+            b.punch(b_start.udp, &b_start.session)
+        );
+        exchange(a_stream.unwrap(), b_stream.unwrap()).await;
+    }
+}
@@ -0,0 +1,23 @@
+use std::io::{self, Write};
+
+use crate::notify::notifier::Notifier;
+use crate::ui::handle_output::on_terminal;
+
+pub struct BellNotifier;
+
+impl Notifier for BellNotifier {
+    fn name(&self) -> &'static str {
+        "bell"
+    }
+
+    // il daemon non ha un terminale da far suonare
+    fn notify(&self, _title: &str, _body: &str) {
+        if !on_terminal() {
+            return;
+        }
+
+        let mut stdout = io::stdout();
+        let _ = write!(stdout, "\x07");
+        let _ = stdout.flush();
+    }
+}
@@ -0,0 +1,50 @@
+use tokio::process::Command;
+
+use crate::notify::notifier::Notifier;
+use crate::plugin::process_plugin::split_command;
+use crate::ui::handle_output::say;
+
+// Lancia un programma esterno con titolo e testo come ultimi argomenti,
+// es. --notify-command notify-send oppure uno script personale
+pub struct CommandNotifier {
+    program: String,
+    args: Vec<String>,
+}
+
+impl CommandNotifier {
+    // divisa come i comandi dei plugin, es. --notify-command "notify-send -a 'p2p chat'"
+    pub fn new(command: &str) -> Result<Self, String> {
+        let mut parts = split_command(command)?.into_iter();
+        let program = parts.next().ok_or("empty command")?;
+
+        Ok(Self {
+            program,
+            args: parts.collect(),
+        })
+    }
+}
+
+impl Notifier for CommandNotifier {
+    fn name(&self) -> &'static str {
+        "command"
+    }
+
+    fn notify(&self, title: &str, body: &str) {
+        let child = Command::new(&self.program)
+            .args(&self.args)
+            .arg(title)
+            .arg(body)
+            .kill_on_drop(false)
+            .spawn();
+
+        match child {
+            // si aspetta in background solo per non lasciare processi zombie
+            Ok(mut child) => {
+                tokio::spawn(async move {
+                    let _ = child.wait().await;
+                });
+            }
+            Err(e) => say!("Notification command {} failed: {}", self.program, e),
+        }
+    }
+}
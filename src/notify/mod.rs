@@ -0,0 +1,3 @@
+pub mod bell_notifier;
+pub mod command_notifier;
+pub mod notifier;
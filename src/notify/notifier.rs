@@ -0,0 +1,43 @@
+use std::sync::Arc;
+
+use clap::ValueEnum;
+
+use crate::notify::bell_notifier::BellNotifier;
+use crate::notify::command_notifier::CommandNotifier;
+use crate::ui::handle_output::say;
+
+// Avviso quando qualcuno ci menziona: campanello del terminale, notifica desktop...
+pub trait Notifier: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn notify(&self, title: &str, body: &str);
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum NotifierKind {
+    Bell,
+    Command,
+    Both,
+    None,
+}
+
+// Il backend a comando ha senso solo con --notify-command, altrimenti si ripiega sul campanello
+pub fn notifiers(kind: NotifierKind, command: Option<String>) -> Arc<Vec<Box<dyn Notifier>>> {
+    let mut notifiers: Vec<Box<dyn Notifier>> = vec![];
+
+    let with_command = matches!(kind, NotifierKind::Command | NotifierKind::Both);
+    match (with_command, command) {
+        (true, Some(command)) => match CommandNotifier::new(&command) {
+            Ok(notifier) => notifiers.push(Box::new(notifier)),
+            Err(e) => say!("Invalid --notify-command ({}), using the terminal bell", e),
+        },
+        (true, None) => say!("--notify command needs --notify-command, using the terminal bell"),
+        (false, _) => {}
+    }
+
+    if matches!(kind, NotifierKind::Bell | NotifierKind::Both) || (with_command && notifiers.is_empty()) {
+        notifiers.push(Box::new(BellNotifier));
+    }
+
+    Arc::new(notifiers)
+}
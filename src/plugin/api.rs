@@ -0,0 +1,158 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use tokio::sync::{Mutex, mpsc, watch};
+
+use crate::plugin::dice_plugin::DicePlugin;
+use crate::plugin::process_plugin::ProcessPlugin;
+use crate::state::state_events::ChatEvent;
+use crate::ui::handle_output::say;
+
+// Bot e automatismi: ricevono gli eventi della stanza, mandano messaggi e aggiungono comandi /
+pub trait Plugin: Send + Sync {
+    fn name(&self) -> &str;
+
+    // Una volta all'avvio: il plugin registra i suoi comandi e si tiene il Bot per parlare alla stanza
+    fn start(&self, bot: Bot);
+
+    // Messaggi, ingressi e uscite; non arrivano i messaggi mandati dal plugin stesso
+    fn on_event(&self, _event: &ChatEvent) {}
+
+    // Un comando registrato con Bot::register, digitato da chi usa questo nodo
+    fn on_command(&self, _command: &str, _args: &str) {}
+}
+
+// Quello che un plugin chiede al nodo, eseguito da plugin_main
+pub enum PluginAction {
+    Register { command: String, usage: String },
+    Send { text: String, reply_to: Option<String> },
+    Notice(String),
+    Stopped,
+}
+
+// Passa tutto da un canale, così anche un plugin senza runtime async può scrivere quando vuole
+#[derive(Clone)]
+pub struct Bot {
+    pub id: String,
+    username: watch::Receiver<String>,
+    plugin: usize,
+    tx: mpsc::UnboundedSender<(usize, PluginAction)>,
+}
+
+impl Bot {
+    pub fn new(plugin: usize, id: String, username: watch::Receiver<String>, tx: mpsc::UnboundedSender<(usize, PluginAction)>) -> Self {
+        Self {
+            id,
+            username,
+            plugin,
+            tx,
+        }
+    }
+
+    // Il nome attuale del nodo, che cambia con /nick
+    pub fn username(&self) -> String {
+        self.username.borrow().clone()
+    }
+
+    // Per chi vuole sapere quando cambia (es. per avvisare un processo esterno)
+    pub fn username_changes(&self) -> watch::Receiver<String> {
+        self.username.clone()
+    }
+
+    // command senza la /, usage come lo si mostra nell'elenco dei comandi
+    pub fn register(&self, command: &str, usage: &str) {
+        self.act(PluginAction::Register {
+            command: command.trim_start_matches('/').to_string(),
+            usage: usage.to_string(),
+        });
+    }
+
+    pub fn send(&self, text: String) {
+        self.act(PluginAction::Send { text, reply_to: None });
+    }
+
+    pub fn reply(&self, id: &str, text: String) {
+        self.act(PluginAction::Send {
+            text,
+            reply_to: Some(id.to_string()),
+        });
+    }
+
+    // Solo sul terminale di questo nodo
+    pub fn notice(&self, text: String) {
+        self.act(PluginAction::Notice(text));
+    }
+
+    // Il plugin ha smesso di funzionare: i suoi comandi spariscono
+    pub fn stopped(&self) {
+        self.act(PluginAction::Stopped);
+    }
+
+    // a nodo chiuso non c'è più nessuno a cui chiedere
+    fn act(&self, action: PluginAction) {
+        let _ = self.tx.send((self.plugin, action));
+    }
+}
+
+pub struct PluginCommand {
+    pub plugin: usize,
+    pub usage: String,
+}
+
+// I plugin caricati e i comandi che hanno registrato, per handle_command
+#[derive(Clone)]
+pub struct Plugins {
+    pub list: Arc<Vec<Box<dyn Plugin>>>,
+    pub commands: Arc<Mutex<BTreeMap<String, PluginCommand>>>,
+    username: Arc<watch::Sender<String>>, // il nome con cui parlano i plugin, aggiornato da /nick
+}
+
+impl Plugins {
+    pub fn new(list: Vec<Box<dyn Plugin>>) -> Self {
+        Self {
+            list: Arc::new(list),
+            commands: Arc::new(Mutex::new(BTreeMap::new())),
+            username: Arc::new(watch::channel(String::new()).0),
+        }
+    }
+
+    pub fn set_username(&self, username: &str) {
+        self.username.send_replace(username.to_string());
+    }
+
+    pub fn username(&self) -> watch::Receiver<String> {
+        self.username.subscribe()
+    }
+
+    // false se nessun plugin ha registrato il comando
+    pub async fn run_command(&self, command: &str, args: &str) -> bool {
+        let plugin = match self.commands.lock().await.get(command.trim_start_matches('/')) {
+            Some(registered) => registered.plugin,
+            None => return false,
+        };
+
+        self.list[plugin].on_command(command.trim_start_matches('/'), args);
+        true
+    }
+
+    pub async fn usages(&self) -> Vec<String> {
+        self.commands.lock().await.values().map(|c| c.usage.clone()).collect()
+    }
+}
+
+// Un nome di plugin incluso carica quello, qualunque altra cosa è un programma da lanciare
+pub fn plugins(specs: &[String]) -> Plugins {
+    let mut list: Vec<Box<dyn Plugin>> = vec![];
+
+    for spec in specs {
+        match spec.as_str() {
+            "dice" => list.push(Box::new(DicePlugin::new())),
+            _ => match ProcessPlugin::new(spec) {
+                Ok(plugin) => list.push(Box::new(plugin)),
+                Err(e) => say!("Invalid --plugin {}: {}, ignored", spec, e),
+            },
+        }
+    }
+
+    Plugins::new(list)
+}
@@ -0,0 +1,79 @@
+use std::sync::OnceLock;
+
+use rand::random_range;
+
+use crate::plugin::api::{Bot, Plugin};
+use crate::state::state_events::ChatEvent;
+
+const MAX_DICE: u32 = 20;
+const MAX_SIDES: u32 = 1000;
+
+// /roll per chi usa questo nodo, !roll nella stanza per tutti gli altri
+pub struct DicePlugin {
+    bot: OnceLock<Bot>,
+}
+
+impl DicePlugin {
+    pub fn new() -> Self {
+        Self { bot: OnceLock::new() }
+    }
+}
+
+impl Plugin for DicePlugin {
+    fn name(&self) -> &str {
+        "dice"
+    }
+
+    fn start(&self, bot: Bot) {
+        bot.register("roll", "/roll [NdM]");
+        let _ = self.bot.set(bot);
+    }
+
+    fn on_event(&self, event: &ChatEvent) {
+        let (Some(bot), ChatEvent::Message { id, sender, text, .. }) = (self.bot.get(), event) else {
+            return;
+        };
+
+        if let Some(args) = text.strip_prefix("!roll")
+            && (args.is_empty() || args.starts_with(' '))
+        {
+            match roll(args.trim()) {
+                Some(result) => bot.reply(id, format!("{} rolled {}", sender, result)),
+                None => bot.reply(id, "Usage: !roll [NdM], e.g. !roll 2d6".to_string()),
+            }
+        }
+    }
+
+    fn on_command(&self, _command: &str, args: &str) {
+        let Some(bot) = self.bot.get() else {
+            return;
+        };
+
+        match roll(args.trim()) {
+            Some(result) => bot.send(format!("{} rolled {}", bot.username(), result)),
+            None => bot.notice("Usage: /roll [NdM], e.g. /roll 2d6".to_string()),
+        }
+    }
+}
+
+// "2d6" -> "2d6: 3 + 5 = 8"; senza argomenti un dado da sei
+fn roll(spec: &str) -> Option<String> {
+    let spec = if spec.is_empty() { "1d6" } else { spec };
+    let (dice, sides) = spec.to_lowercase().split_once('d').map(|(n, m)| (n.to_string(), m.to_string()))?;
+
+    let dice: u32 = if dice.is_empty() { 1 } else { dice.parse().ok()? };
+    let sides: u32 = sides.parse().ok()?;
+    if !(1..=MAX_DICE).contains(&dice) || !(2..=MAX_SIDES).contains(&sides) {
+        return None;
+    }
+
+    let rolls: Vec<u32> = (0..dice).map(|_| random_range(1..=sides)).collect();
+    let total: u32 = rolls.iter().sum();
+    let shown: Vec<String> = rolls.iter().map(u32::to_string).collect();
+
+    if dice == 1 {
+        Some(format!("{}d{}: {}", dice, sides, total))
+    } else {
+        Some(format!("{}d{}: {} = {}", dice, sides, shown.join(" + "), total))
+    }
+}
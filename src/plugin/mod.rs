@@ -0,0 +1,4 @@
+pub mod api;
+pub mod dice_plugin;
+pub mod plugin_host;
+pub mod process_plugin;
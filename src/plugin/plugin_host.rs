@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{Mutex, mpsc};
+
+use crate::plugin::api::{Bot, PluginAction, PluginCommand};
+use crate::state::state_chat::{Chat, Connections, Member};
+use crate::state::state_events::ChatEvent;
+use crate::state::state_node::Node;
+use crate::ui::handle_command::COMMANDS;
+use crate::ui::handle_input::send_message;
+use crate::ui::handle_output::{print_message, print_notice, say, view};
+
+// Tra i pacchetti e l'output: gli eventi del nodo vanno a ogni plugin, le loro richieste passano da qui
+pub async fn plugin_main(chat: Arc<Mutex<Chat>>, member: Arc<Member>, node: Node, connections: Connections) {
+    let plugins = node.plugins.clone();
+    if plugins.list.is_empty() {
+        return;
+    }
+
+    // iscritti prima di avviarli, così nessun evento va perso
+    let mut events = node.events.subscribe();
+    let (tx, mut rx) = mpsc::unbounded_channel::<(usize, PluginAction)>();
+
+    plugins.set_username(&chat.lock().await.me(&member).username);
+    for (i, plugin) in plugins.list.iter().enumerate() {
+        say!("Plugin: {}", plugin.name());
+        plugin.start(Bot::new(i, member.id.clone(), plugins.username(), tx.clone()));
+    }
+    drop(tx);
+
+    // id dei messaggi mandati dai plugin, per non rimandarli a chi li ha scritti
+    let mut sent: HashMap<String, usize> = HashMap::new();
+
+    loop {
+        tokio::select! {
+            action = rx.recv() => match action {
+                Some((plugin, action)) => {
+                    if let Some(id) = act(plugin, action, &chat, &member, &node, &connections).await {
+                        sent.insert(id, plugin);
+                    }
+                }
+                None => break,
+            },
+            event = events.recv() => match event {
+                Ok(event) => {
+                    let author = match &event {
+                        ChatEvent::Message { id, .. } => sent.remove(id),
+                        _ => None,
+                    };
+
+                    for (i, plugin) in plugins.list.iter().enumerate() {
+                        if author != Some(i) {
+                            plugin.on_event(&event);
+                        }
+                    }
+                }
+                Err(RecvError::Lagged(n)) => say!("Plugins missed {} events", n),
+                Err(RecvError::Closed) => break,
+            },
+        }
+    }
+}
+
+// Restituisce l'id del messaggio se il plugin ne ha mandato uno
+async fn act(
+    plugin: usize,
+    action: PluginAction,
+    chat: &Arc<Mutex<Chat>>,
+    member: &Arc<Member>,
+    node: &Node,
+    connections: &Connections,
+) -> Option<String> {
+    let plugins = &node.plugins;
+    let name = plugins.list[plugin].name();
+
+    match action {
+        PluginAction::Register { command, usage } => {
+            let valid = !command.is_empty() && command.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            if !valid || COMMANDS.contains(&format!("/{}", command).as_str()) {
+                say!("Plugin {} cannot register /{}", name, command);
+                return None;
+            }
+
+            let mut commands = plugins.commands.lock().await;
+            match commands.get(&command) {
+                Some(other) if other.plugin != plugin => {
+                    say!("Plugin {}: /{} already belongs to {}", name, command, plugins.list[other.plugin].name());
+                }
+                _ => {
+                    commands.insert(command, PluginCommand { plugin, usage });
+                }
+            }
+            None
+        }
+        PluginAction::Send { text, reply_to } => {
+            if text.trim().is_empty() {
+                return None;
+            }
+
+            let quoted = reply_to.is_some();
+            let id = send_message(text, reply_to, chat, member, node, connections).await;
+
+            // send_message ristampa solo le risposte, il resto è la riga digitata che qui non c'è
+            if !quoted {
+                let chat_lock = chat.lock().await;
+                if let Some(message) = chat_lock.all_messages.iter().find(|m| m.id == id) {
+                    print_message(&view(message, &chat_lock, node, &member.id).await);
+                }
+            }
+            Some(id)
+        }
+        PluginAction::Notice(text) => {
+            print_notice(&format!("[{}] {}", name, text));
+            None
+        }
+        PluginAction::Stopped => {
+            plugins.commands.lock().await.retain(|_, c| c.plugin != plugin);
+            None
+        }
+    }
+}
@@ -0,0 +1,296 @@
+use std::process::Stdio;
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::Command;
+use tokio::sync::mpsc;
+
+use crate::plugin::api::{Bot, Plugin};
+use crate::state::state_events::ChatEvent;
+use crate::ui::handle_output::say;
+
+// Dal nodo al plugin, una riga JSON su stdin
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum PluginInput {
+    Init { username: String, id: String },
+    Rename { username: String },
+    Event { event: ChatEvent },
+    Command { command: String, args: String },
+}
+
+// Dal plugin al nodo, una riga JSON su stdout
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum PluginOutput {
+    Register {
+        command: String,
+        #[serde(default)]
+        usage: Option<String>,
+    },
+    Send {
+        text: String,
+        #[serde(default)]
+        reply_to: Option<String>,
+    },
+    Notice {
+        text: String,
+    },
+}
+
+// Un plugin scritto in qualunque linguaggio: programma esterno che parla JSON su stdin/stdout.
+// Il suo stderr resta quello del nodo, per i log
+pub struct ProcessPlugin {
+    program: String,
+    args: Vec<String>,
+    input: OnceLock<mpsc::UnboundedSender<PluginInput>>,
+}
+
+impl ProcessPlugin {
+    // command è una riga come in una shell: "python3 'my bot.py' --name \"Dice Bot\""
+    pub fn new(command: &str) -> Result<Self, String> {
+        let mut parts = split_command(command)?.into_iter();
+        let program = parts.next().ok_or("empty command")?;
+
+        Ok(Self {
+            program,
+            args: parts.collect(),
+            input: OnceLock::new(),
+        })
+    }
+
+    // un plugin che non legge più stdin è finito, reader se ne accorge
+    fn write(&self, input: PluginInput) {
+        if let Some(tx) = self.input.get() {
+            let _ = tx.send(input);
+        }
+    }
+}
+
+// Divide come sh, senza espansioni: spazi tra gli argomenti, '...' letterale,
+// "..." con \" e \\, e \ fuori dalle virgolette per il carattere successivo.
+// Vale anche per --notify-command
+pub fn split_command(command: &str) -> Result<Vec<String>, String> {
+    let mut parts = vec![];
+    let mut current: Option<String> = None;
+    let mut chars = command.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if let Some(part) = current.take() {
+                    parts.push(part);
+                }
+            }
+            '\'' => {
+                let part = current.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => part.push(c),
+                        None => return Err("unterminated '".to_string()),
+                    }
+                }
+            }
+            '"' => {
+                let part = current.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\')) => part.push(c),
+                            Some(c) => {
+                                part.push('\\');
+                                part.push(c);
+                            }
+                            None => return Err("unterminated \"".to_string()),
+                        },
+                        Some(c) => part.push(c),
+                        None => return Err("unterminated \"".to_string()),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some(c) => current.get_or_insert_with(String::new).push(c),
+                None => return Err("trailing \\".to_string()),
+            },
+            c => current.get_or_insert_with(String::new).push(c),
+        }
+    }
+
+    parts.extend(current);
+    Ok(parts)
+}
+
+impl Plugin for ProcessPlugin {
+    fn name(&self) -> &str {
+        &self.program
+    }
+
+    fn start(&self, bot: Bot) {
+        let child = Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn();
+
+        let mut child = match child {
+            Ok(child) => child,
+            Err(e) => {
+                say!("Plugin {} failed to start: {}", self.program, e);
+                return;
+            }
+        };
+
+        let (Some(mut stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+            return;
+        };
+
+        let (tx, mut rx) = mpsc::unbounded_channel::<PluginInput>();
+        let _ = tx.send(PluginInput::Init {
+            username: bot.username(),
+            id: bot.id.clone(),
+        });
+        // dopo /nick il plugin riceve il nome nuovo; il riferimento debole non tiene aperto stdin
+        let renames = tx.downgrade();
+        let _ = self.input.set(tx);
+        let mut username = bot.username_changes();
+        username.mark_unchanged();
+        tokio::spawn(async move {
+            while username.changed().await.is_ok() {
+                let renamed = PluginInput::Rename {
+                    username: username.borrow_and_update().clone(),
+                };
+                if renames.upgrade().is_none_or(|tx| tx.send(renamed).is_err()) {
+                    break;
+                }
+            }
+        });
+
+        tokio::spawn(async move {
+            while let Some(input) = rx.recv().await {
+                let Ok(mut line) = serde_json::to_string(&input) else {
+                    continue;
+                };
+                line.push('\n');
+
+                if stdin.write_all(line.as_bytes()).await.is_err() || stdin.flush().await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let program = self.program.clone();
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stdout).lines();
+
+            while let Ok(Some(line)) = lines.next_line().await {
+                if line.trim().is_empty() {
+                    continue;
+                }
+
+                match serde_json::from_str::<PluginOutput>(&line) {
+                    Ok(PluginOutput::Register { command, usage }) => {
+                        let usage = usage.unwrap_or_else(|| format!("/{}", command.trim_start_matches('/')));
+                        bot.register(&command, &usage);
+                    }
+                    Ok(PluginOutput::Send { text, reply_to: None }) => bot.send(text),
+                    Ok(PluginOutput::Send {
+                        text,
+                        reply_to: Some(id),
+                    }) => bot.reply(&id, text),
+                    Ok(PluginOutput::Notice { text }) => bot.notice(text),
+                    Err(e) => say!("Plugin {} sent an invalid line: {}", program, e),
+                }
+            }
+
+            // stdout chiuso: il processo è uscito o sta per farlo
+            match child.wait().await {
+                Ok(status) => say!("Plugin {} exited ({})", program, status),
+                Err(e) => say!("Plugin {} exited: {}", program, e),
+            }
+            bot.stopped();
+        });
+    }
+
+    fn on_event(&self, event: &ChatEvent) {
+        self.write(PluginInput::Event { event: event.clone() });
+    }
+
+    fn on_command(&self, command: &str, args: &str) {
+        self.write(PluginInput::Command {
+            command: command.to_string(),
+            args: args.to_string(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugin::api::PluginAction;
+    use std::time::Duration;
+    use tokio::sync::watch;
+    use tokio::time::timeout;
+
+    #[test]
+    fn split_command_handles_quotes() {
+        assert_eq!(split_command("  python3  bot.py ").unwrap(), vec!["python3", "bot.py"]);
+        assert_eq!(
+            split_command(r#"python3 'my bot.py' --name "Dice \"Bot\"" a\ b ''"#).unwrap(),
+            vec!["python3", "my bot.py", "--name", "Dice \"Bot\"", "a b", ""]
+        );
+        assert!(split_command("sh -c 'echo").is_err());
+        assert!(split_command("   ").unwrap().is_empty());
+        assert!(ProcessPlugin::new("   ").is_err());
+    }
+
+    // Un plugin in sh: registra /ping, risponde ai comandi e avvisa quando cambia nome
+    const SCRIPT: &str = r#"sh -c '
+        read init
+        echo "{\"type\":\"register\",\"command\":\"ping\"}"
+        while read line; do
+            case "$line" in
+                *\"type\":\"command\"*) echo "{\"type\":\"send\",\"text\":\"pong\"}" ;;
+                *\"type\":\"rename\"*) echo "{\"type\":\"notice\",\"text\":\"renamed\"}" ;;
+            esac
+        done
+    '"#;
+
+    async fn next(rx: &mut mpsc::UnboundedReceiver<(usize, PluginAction)>) -> PluginAction {
+        let (plugin, action) = timeout(Duration::from_secs(5), rx.recv()).await.unwrap().unwrap();
+        assert_eq!(plugin, 3);
+        action
+    }
+
+    #[tokio::test]
+    async fn process_plugin_registers_commands_and_sends() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let (username, names) = watch::channel("alice".to_string());
+        let plugin = ProcessPlugin::new(SCRIPT).unwrap();
+        plugin.start(Bot::new(3, "id".to_string(), names, tx));
+
+        match next(&mut rx).await {
+            PluginAction::Register { command, usage } => assert_eq!((command.as_str(), usage.as_str()), ("ping", "/ping")),
+            _ => panic!("expected register"),
+        }
+
+        plugin.on_command("ping", "");
+        match next(&mut rx).await {
+            PluginAction::Send { text, reply_to } => assert_eq!((text.as_str(), reply_to), ("pong", None)),
+            _ => panic!("expected send"),
+        }
+
+        username.send_replace("alicia".to_string());
+        match next(&mut rx).await {
+            PluginAction::Notice(text) => assert_eq!(text, "renamed"),
+            _ => panic!("expected notice"),
+        }
+
+        // chiuso stdin il processo esce e i suoi comandi vanno tolti
+        drop(plugin);
+        assert!(matches!(next(&mut rx).await, PluginAction::Stopped));
+    }
+}
@@ -0,0 +1,2 @@
+pub mod relay_server;
+pub mod state_relay;
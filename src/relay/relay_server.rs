@@ -0,0 +1,557 @@
+// Server di rendezvous e relay per p2pchat: i nodi si registrano con id e stanza, chiedono
+// chi c'è nella stanza e, se la connessione diretta fallisce, si fanno unire due connessioni TCP qui.
+// Il relay vede solo il traffico in transito, non tiene nessuna cronologia
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::{Mutex, mpsc, oneshot};
+use tokio::time::{sleep, timeout};
+use uuid::Uuid;
+
+use crate::relay::state_relay::{PunchMessage, PunchStart, RelayPeer, RelayRequest, RelayResponse, UDP_CONTROL};
+
+const ACCEPT_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct Registration {
+    observed: SocketAddr,
+    token: String, // segreto della connessione di controllo, per gli Hello UDP
+    username: String,
+    room: String,
+    addrs: Vec<SocketAddr>,
+    control: mpsc::UnboundedSender<RelayResponse>,
+}
+
+// Una sessione di Connect: la prima connessione dati che arriva aspetta l'altra qui
+struct Splice {
+    peers: String,
+    waiting: Option<oneshot::Sender<TcpStream>>,
+}
+
+#[derive(Clone)]
+struct Relay {
+    nodes: Arc<Mutex<HashMap<String, Registration>>>,
+    pending: Arc<Mutex<HashMap<String, Splice>>>,
+    udp: Arc<Mutex<HashMap<String, SocketAddr>>>, // id -> mappatura UDP pubblica, per l'hole punching
+}
+
+// Serve le connessioni TCP del listener e, sulla stessa porta, gli Hello UDP
+pub async fn serve(listener: TcpListener) -> std::io::Result<()> {
+    let relay = Relay {
+        nodes: Arc::new(Mutex::new(HashMap::new())),
+        pending: Arc::new(Mutex::new(HashMap::new())),
+        udp: Arc::new(Mutex::new(HashMap::new())),
+    };
+
+    let udp_socket = UdpSocket::bind(listener.local_addr()?).await?;
+    tokio::spawn(udp_loop(udp_socket, relay.clone()));
+
+    loop {
+        let (stream, peer_addr) = listener.accept().await?;
+        let relay = relay.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, peer_addr, relay).await {
+                println!("Connection {} closed: {}", peer_addr, e);
+            }
+        });
+    }
+}
+
+async fn handle_connection(stream: TcpStream, peer_addr: SocketAddr, relay: Relay) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+
+    if reader.read_line(&mut line).await? == 0 {
+        return Ok(());
+    }
+
+    match serde_json::from_str::<RelayRequest>(line.trim())? {
+        RelayRequest::Register { id, username, room, addrs } => {
+            control_loop(reader, peer_addr, relay, id, username, room, addrs).await
+        }
+        RelayRequest::Accept(session) => {
+            // il client aspetta Spliced prima di scrivere altro, il buffer è vuoto
+            let mut stream = reader.into_inner();
+            let mut pending = relay.pending.lock().await;
+            let Some(splice) = pending.get_mut(&session) else {
+                drop(pending);
+                write_line(&mut stream, &RelayResponse::Error("unknown session".to_string())).await?;
+                return Ok(());
+            };
+
+            // seconda connessione: da qui se ne occupa il task della prima
+            if let Some(first) = splice.waiting.take() {
+                pending.remove(&session);
+                let _ = first.send(stream);
+                return Ok(());
+            }
+
+            let (tx, rx) = oneshot::channel();
+            splice.waiting = Some(tx);
+            let peers = splice.peers.clone();
+            drop(pending);
+
+            let other = timeout(ACCEPT_TIMEOUT, rx).await;
+            relay.pending.lock().await.remove(&session);
+
+            let Ok(Ok(mut other)) = other else {
+                write_line(&mut stream, &RelayResponse::Error("the peer did not accept".to_string())).await?;
+                return Ok(());
+            };
+
+            write_line(&mut stream, &RelayResponse::Spliced).await?;
+            write_line(&mut other, &RelayResponse::Spliced).await?;
+            println!("Relaying {}", peers);
+
+            let (up, down) = tokio::io::copy_bidirectional(&mut stream, &mut other).await?;
+            println!("Relay {} closed ({} / {} bytes)", peers, up, down);
+            Ok(())
+        }
+        RelayRequest::Lookup(_) | RelayRequest::Connect { .. } | RelayRequest::Punch { .. } => {
+            Err("request before Register".into())
+        }
+    }
+}
+
+async fn control_loop(
+    reader: BufReader<TcpStream>,
+    peer_addr: SocketAddr,
+    relay: Relay,
+    id: String,
+    username: String,
+    room: String,
+    addrs: Vec<SocketAddr>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (read_half, mut write_half) = tokio::io::split(reader);
+    let (tx, mut rx) = mpsc::unbounded_channel::<RelayResponse>();
+
+    tokio::spawn(async move {
+        while let Some(response) = rx.recv().await {
+            if write_line(&mut write_half, &response).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    // uno per connessione: chi conosce solo l'id non può spostare la mappatura UDP del nodo
+    let token = Uuid::new_v4().to_string();
+    let registration = Registration::new(peer_addr, &token, username, room, addrs, tx.clone());
+    if let Err(e) = register(&relay, &id, registration).await {
+        let _ = tx.send(RelayResponse::Error(e));
+        return Ok(());
+    }
+    println!("{} registered from {}", id, peer_addr);
+
+    let mut lines = BufReader::new(read_half).lines();
+    let result = loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => break Ok(()),
+            Err(e) => break Err(e.into()),
+        };
+
+        // l'id resta quello della prima Register: una connessione non può parlare a nome di un altro nodo
+        match serde_json::from_str::<RelayRequest>(line.trim()) {
+            Ok(RelayRequest::Register { username, room, addrs, .. }) => {
+                let registration = Registration::new(peer_addr, &token, username, room, addrs, tx.clone());
+                let _ = register(&relay, &id, registration).await;
+            }
+            Ok(RelayRequest::Lookup(room)) => {
+                let members: Vec<RelayPeer> = relay
+                    .nodes
+                    .lock()
+                    .await
+                    .iter()
+                    .filter(|(_, n)| n.room == room)
+                    .map(|(id, n)| RelayPeer {
+                        id: id.clone(),
+                        username: n.username.clone(),
+                        addrs: n.addrs.clone(),
+                    })
+                    .collect();
+                let _ = tx.send(RelayResponse::Members(room, members));
+            }
+            Ok(RelayRequest::Connect { to }) => {
+                if let Err(e) = start_splice(&relay, &id, &to).await {
+                    let _ = tx.send(RelayResponse::Error(e));
+                }
+            }
+            Ok(RelayRequest::Punch { to }) => {
+                if let Err(e) = start_punch(&relay, &id, &to).await {
+                    let _ = tx.send(RelayResponse::Error(e));
+                }
+            }
+            Ok(_) => {
+                let _ = tx.send(RelayResponse::Error("unexpected request on control connection".to_string()));
+            }
+            Err(e) => break Err(e.into()),
+        }
+    };
+
+    // solo se è ancora la nostra: intanto il nodo può essersi registrato di nuovo su un'altra connessione
+    let mut nodes = relay.nodes.lock().await;
+    if nodes.get(&id).is_some_and(|n| n.control.same_channel(&tx)) {
+        nodes.remove(&id);
+        relay.udp.lock().await.remove(&id);
+        println!("{} unregistered", id);
+    }
+    result
+}
+
+impl Registration {
+    // L'indirizzo osservato va in cima: se il nodo è raggiungibile da internet è quello giusto
+    fn new(
+        peer_addr: SocketAddr,
+        token: &str,
+        username: String,
+        room: String,
+        mut addrs: Vec<SocketAddr>,
+        control: mpsc::UnboundedSender<RelayResponse>,
+    ) -> Self {
+        let observed = SocketAddr::new(peer_addr.ip().to_canonical(), peer_addr.port());
+
+        if let Some(port) = addrs.first().map(|a| a.port()) {
+            let public = SocketAddr::new(observed.ip(), port);
+            addrs.retain(|a| *a != public);
+            addrs.insert(0, public);
+        }
+
+        Self {
+            observed,
+            token: token.to_string(),
+            username,
+            room,
+            addrs,
+            control,
+        }
+    }
+}
+
+// Un id già registrato da un'altra connessione ancora aperta non si può prendere
+async fn register(relay: &Relay, id: &str, registration: Registration) -> Result<(), String> {
+    let mut nodes = relay.nodes.lock().await;
+    if nodes
+        .get(id)
+        .is_some_and(|n| !n.control.same_channel(&registration.control) && !n.control.is_closed())
+    {
+        return Err(format!("{} is already registered", id));
+    }
+
+    let _ = registration
+        .control
+        .send(RelayResponse::Registered(registration.observed, registration.token.clone()));
+    nodes.insert(id.to_string(), registration);
+    Ok(())
+}
+
+// Gli Hello arrivano dal socket UDP del nodo: la sorgente è la mappatura che il suo NAT ha creato
+async fn udp_loop(udp_socket: UdpSocket, relay: Relay) {
+    let mut buf = [0u8; 2048];
+
+    loop {
+        let Ok((len, src)) = udp_socket.recv_from(&mut buf).await else {
+            continue;
+        };
+
+        if len == 0 || buf[0] != UDP_CONTROL {
+            continue;
+        }
+
+        if let Ok(PunchMessage::Hello(id, token)) = serde_json::from_slice::<PunchMessage>(&buf[1..len]) {
+            if relay.nodes.lock().await.get(&id).is_none_or(|n| n.token != token) {
+                continue;
+            }
+
+            let mapping = SocketAddr::new(src.ip().to_canonical(), src.port());
+            relay.udp.lock().await.insert(id, mapping);
+
+            let mut reply = vec![UDP_CONTROL];
+            reply.extend(serde_json::to_vec(&PunchMessage::Mapping(mapping)).expect("Failed to serialize"));
+            let _ = udp_socket.send_to(&reply, src).await;
+        }
+    }
+}
+
+// Stessa sessione a entrambi; una sessione che nessuno usa sparisce dopo ACCEPT_TIMEOUT
+async fn start_splice(relay: &Relay, from: &str, to: &str) -> Result<(), String> {
+    let nodes = relay.nodes.lock().await;
+    let (Some(from_node), Some(to_node)) = (nodes.get(from), nodes.get(to)) else {
+        return Err(format!("{} is not registered", to));
+    };
+
+    let session = Uuid::new_v4().to_string();
+    relay.pending.lock().await.insert(
+        session.clone(),
+        Splice {
+            peers: format!("{} <-> {}", from, to),
+            waiting: None,
+        },
+    );
+
+    let _ = to_node.control.send(RelayResponse::Incoming {
+        from: from.to_string(),
+        session: session.clone(),
+    });
+    let _ = from_node.control.send(RelayResponse::Outgoing {
+        to: to.to_string(),
+        session: session.clone(),
+    });
+
+    let pending = Arc::clone(&relay.pending);
+    tokio::spawn(async move {
+        sleep(ACCEPT_TIMEOUT).await;
+        let mut pending = pending.lock().await;
+        if pending.get(&session).is_some_and(|s| s.waiting.is_none()) {
+            pending.remove(&session);
+        }
+    });
+    Ok(())
+}
+
+// Entrambi ricevono PunchStart insieme, così i tentativi di apertura partono quasi in contemporanea
+async fn start_punch(relay: &Relay, from: &str, to: &str) -> Result<(), String> {
+    let nodes = relay.nodes.lock().await;
+    let udp = relay.udp.lock().await;
+    let session = Uuid::new_v4().to_string();
+
+    let (Some(from_node), Some(to_node)) = (nodes.get(from), nodes.get(to)) else {
+        return Err(format!("{} is not registered", to));
+    };
+    let (Some(from_udp), Some(to_udp)) = (udp.get(from), udp.get(to)) else {
+        return Err(format!("no UDP mapping known for {}", to));
+    };
+
+    let _ = to_node.control.send(RelayResponse::PunchStart(PunchStart {
+        peer: from.to_string(),
+        session: session.clone(),
+        udp: *from_udp,
+        tcp: from_node.observed,
+        initiator: false,
+    }));
+    let _ = from_node.control.send(RelayResponse::PunchStart(PunchStart {
+        peer: to.to_string(),
+        session,
+        udp: *to_udp,
+        tcp: to_node.observed,
+        initiator: true,
+    }));
+
+    println!("Punching {} <-> {}", from, to);
+    Ok(())
+}
+
+async fn write_line<W: AsyncWrite + Unpin>(writer: &mut W, response: &RelayResponse) -> std::io::Result<()> {
+    let mut data = serde_json::to_vec(response).expect("Failed to serialize");
+    data.push(b'\n');
+    writer.write_all(&data).await?;
+    writer.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, Lines};
+    use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+
+    async fn start() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener));
+        addr
+    }
+
+    async fn send<W: AsyncWrite + Unpin>(writer: &mut W, request: &RelayRequest) {
+        let mut data = serde_json::to_vec(request).unwrap();
+        data.push(b'\n');
+        writer.write_all(&data).await.unwrap();
+    }
+
+    async fn recv(lines: &mut Lines<BufReader<OwnedReadHalf>>) -> RelayResponse {
+        let line = timeout(Duration::from_secs(5), lines.next_line()).await.unwrap().unwrap().unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    async fn register(relay: SocketAddr, id: &str) -> (Lines<BufReader<OwnedReadHalf>>, OwnedWriteHalf) {
+        let (reader, mut writer) = TcpStream::connect(relay).await.unwrap().into_split();
+        let mut lines = BufReader::new(reader).lines();
+        let request = RelayRequest::Register {
+            id: id.to_string(),
+            username: id.to_string(),
+            room: "general".to_string(),
+            addrs: vec!["10.0.0.1:4000".parse().unwrap()],
+        };
+
+        send(&mut writer, &request).await;
+        assert!(matches!(recv(&mut lines).await, RelayResponse::Registered(..)));
+        (lines, writer)
+    }
+
+    // Come wait_spliced nel client: un byte alla volta fino alla fine della riga
+    async fn accept(relay: SocketAddr, session: String) -> TcpStream {
+        let mut stream = TcpStream::connect(relay).await.unwrap();
+        send(&mut stream, &RelayRequest::Accept(session)).await;
+
+        let mut line = vec![];
+        loop {
+            match stream.read_u8().await.unwrap() {
+                b'\n' => break,
+                byte => line.push(byte),
+            }
+        }
+        assert!(matches!(serde_json::from_slice(&line).unwrap(), RelayResponse::Spliced));
+        stream
+    }
+
+    #[tokio::test]
+    async fn lookup_lists_the_room() {
+        let relay = start().await;
+        let (_a_lines, _a) = register(relay, "a").await;
+        let (mut b_lines, mut b) = register(relay, "b").await;
+
+        send(&mut b, &RelayRequest::Lookup("general".to_string())).await;
+        let RelayResponse::Members(room, members) = recv(&mut b_lines).await else {
+            panic!("expected Members");
+        };
+
+        let mut ids: Vec<String> = members.iter().map(|m| m.id.clone()).collect();
+        ids.sort();
+        assert_eq!(room, "general");
+        assert_eq!(ids, ["a", "b"]);
+        assert_eq!(members[0].addrs[0].ip().to_string(), "127.0.0.1"); // l'osservato va in cima
+
+        send(&mut b, &RelayRequest::Lookup("other".to_string())).await;
+        let RelayResponse::Members(_, members) = recv(&mut b_lines).await else {
+            panic!("expected Members");
+        };
+        assert!(members.is_empty());
+    }
+
+    #[tokio::test]
+    async fn connect_splices_the_two_peers() {
+        let relay = start().await;
+        let (mut a_lines, mut a) = register(relay, "a").await;
+        let (mut b_lines, _b) = register(relay, "b").await;
+
+        send(&mut a, &RelayRequest::Connect { to: "b".to_string() }).await;
+        let RelayResponse::Outgoing { to, session } = recv(&mut a_lines).await else {
+            panic!("expected Outgoing");
+        };
+        let RelayResponse::Incoming { from, session: incoming } = recv(&mut b_lines).await else {
+            panic!("expected Incoming");
+        };
+        assert_eq!((to.as_str(), from.as_str()), ("b", "a")); // from è l'id registrato, non scelto dal client
+        assert_eq!(session, incoming);
+
+        let (mut a_data, mut b_data) = tokio::join!(accept(relay, session), accept(relay, incoming));
+        a_data.write_all(b"ping").await.unwrap();
+        b_data.write_all(b"pong").await.unwrap();
+
+        let mut buf = [0u8; 4];
+        b_data.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+        a_data.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"pong");
+    }
+
+    #[tokio::test]
+    async fn connect_to_unknown_peer_fails() {
+        let relay = start().await;
+        let (mut a_lines, mut a) = register(relay, "a").await;
+
+        send(&mut a, &RelayRequest::Connect { to: "nobody".to_string() }).await;
+        assert!(matches!(recv(&mut a_lines).await, RelayResponse::Error(_)));
+    }
+
+    #[tokio::test]
+    async fn registered_id_cannot_be_taken() {
+        let relay = start().await;
+        let (a_lines, a) = register(relay, "a").await;
+
+        let (reader, mut writer) = TcpStream::connect(relay).await.unwrap().into_split();
+        let mut lines = BufReader::new(reader).lines();
+        let request = RelayRequest::Register {
+            id: "a".to_string(),
+            username: "mallory".to_string(),
+            room: "general".to_string(),
+            addrs: vec![],
+        };
+        send(&mut writer, &request).await;
+        assert!(matches!(recv(&mut lines).await, RelayResponse::Error(_)));
+        assert!(lines.next_line().await.unwrap().is_none()); // il relay chiude la connessione
+
+        // la registrazione rifiutata non deve cancellare quella vera
+        let (mut b_lines, mut b) = register(relay, "b").await;
+        send(&mut b, &RelayRequest::Lookup("general".to_string())).await;
+        let RelayResponse::Members(_, members) = recv(&mut b_lines).await else {
+            panic!("expected Members");
+        };
+        let a_peer = members.iter().find(|m| m.id == "a").expect("a is still registered");
+        assert_eq!(a_peer.username, "a");
+
+        // chiusa la prima connessione l'id torna libero
+        drop((a, a_lines));
+        let mut registered = false;
+        for _ in 0..50 {
+            let (reader, mut writer) = TcpStream::connect(relay).await.unwrap().into_split();
+            let mut lines = BufReader::new(reader).lines();
+            send(&mut writer, &request).await;
+            if matches!(recv(&mut lines).await, RelayResponse::Registered(..)) {
+                registered = true;
+                break;
+            }
+            sleep(Duration::from_millis(20)).await;
+        }
+        assert!(registered);
+    }
+
+    async fn hello(udp: &UdpSocket, relay: SocketAddr, id: &str, token: &str) -> Option<SocketAddr> {
+        let mut data = vec![UDP_CONTROL];
+        data.extend(serde_json::to_vec(&PunchMessage::Hello(id.to_string(), token.to_string())).unwrap());
+        udp.send_to(&data, relay).await.unwrap();
+
+        let mut buf = [0u8; 2048];
+        let (len, _) = timeout(Duration::from_millis(500), udp.recv_from(&mut buf)).await.ok()?.unwrap();
+        match serde_json::from_slice(&buf[1..len]).unwrap() {
+            PunchMessage::Mapping(addr) => Some(addr),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn hello_needs_the_registration_token() {
+        let relay = start().await;
+        let (reader, mut writer) = TcpStream::connect(relay).await.unwrap().into_split();
+        let mut lines = BufReader::new(reader).lines();
+        let request = RelayRequest::Register {
+            id: "a".to_string(),
+            username: "a".to_string(),
+            room: "general".to_string(),
+            addrs: vec![],
+        };
+        send(&mut writer, &request).await;
+        let RelayResponse::Registered(_, token) = recv(&mut lines).await else {
+            panic!("expected Registered");
+        };
+
+        let udp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        assert!(hello(&udp, relay, "a", "guessed").await.is_none());
+        assert!(hello(&udp, relay, "nobody", &token).await.is_none());
+        assert_eq!(hello(&udp, relay, "a", &token).await, Some(udp.local_addr().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn unknown_session_is_refused() {
+        let relay = start().await;
+        let mut stream = TcpStream::connect(relay).await.unwrap();
+        send(&mut stream, &RelayRequest::Accept("made-up".to_string())).await;
+
+        let mut lines = BufReader::new(stream).lines();
+        let line = lines.next_line().await.unwrap().unwrap();
+        assert!(matches!(serde_json::from_str(&line).unwrap(), RelayResponse::Error(_)));
+    }
+}
@@ -0,0 +1,63 @@
+// Protocollo tra i nodi e il server di rendezvous/relay (relay_server.rs).
+// Un JSON per riga come per i Packet. Ogni connessione TCP verso il relay inizia con una RelayRequest:
+// - Register apre la connessione di controllo, su cui poi viaggiano Lookup, Connect, Punch e le risposte.
+//   Chi chiede Connect o Punch è sempre l'id con cui la connessione di controllo si è registrata
+// - Connect fa arrivare Incoming all'altro peer e Outgoing a chi chiede, con la stessa sessione:
+//   entrambi aprono una connessione dati con Accept, che dopo Spliced viene unita a quella dell'altro
+// - Punch chiede al relay di coordinare un'apertura simultanea (hole punching) con un altro peer.
+//   Sulla stessa porta in UDP il relay risponde agli Hello con la mappatura pubblica osservata;
+//   un Hello vale solo con il token che la connessione di controllo ha ricevuto in Registered
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+
+pub const DEFAULT_RELAY_PORT: u16 = 7000;
+pub const UDP_CONTROL: u8 = 0; // primo byte dei datagrammi che contengono un PunchMessage in JSON
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum RelayRequest {
+    Register {
+        id: String,
+        username: String,
+        room: String,
+        addrs: Vec<SocketAddr>,
+    },
+    Lookup(String),
+    Connect { to: String },
+    Accept(String),
+    Punch { to: String },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum RelayResponse {
+    Registered(SocketAddr, String), // indirizzo pubblico con cui il relay ci vede e token per gli Hello
+    Members(String, Vec<RelayPeer>),
+    Incoming { from: String, session: String },
+    Outgoing { to: String, session: String },
+    Spliced,
+    PunchStart(PunchStart),
+    Error(String),
+}
+
+// Mandato a entrambi i peer nello stesso momento: ognuno riceve gli indirizzi pubblici dell'altro
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PunchStart {
+    pub peer: String,
+    pub session: String,
+    pub udp: SocketAddr,
+    pub tcp: SocketAddr,
+    pub initiator: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum PunchMessage {
+    Hello(String, String), // id e token di Registered
+    Mapping(SocketAddr),
+    Punch(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RelayPeer {
+    pub id: String,
+    pub username: String,
+    pub addrs: Vec<SocketAddr>,
+}
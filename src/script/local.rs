@@ -0,0 +1,194 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde_json::json;
+use tokio::sync::Mutex;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::Instant;
+
+use crate::daemon::server::{history, member_infos};
+use crate::script::output::JsonOut;
+use crate::script::{EXIT_FAILED, EXIT_OK, EXIT_UNREACHABLE, EXIT_USAGE};
+use crate::state::state_chat::{Chat, Connections, Member};
+use crate::state::state_discovery::DiscoveredPeers;
+use crate::state::state_events::ChatEvent;
+use crate::state::state_node::Node;
+use crate::ui::handle_format::sanitize_line;
+use crate::ui::handle_input::send_message;
+use crate::ui::handle_output::say;
+
+const MESH_WAIT: Duration = Duration::from_secs(3);
+const ACK_WAIT: Duration = Duration::from_secs(5);
+const POLL: Duration = Duration::from_millis(100);
+
+// Sottocomandi eseguiti con un nodo usa e getta: entra nella stanza con -c/-j/--relay/-d,
+// aspetta il Sync, fa una cosa sola ed esce
+pub struct ScriptContext {
+    pub chat: Arc<Mutex<Chat>>,
+    pub myself: Arc<Member>,
+    pub node: Node,
+    pub connections: Connections,
+    pub room: String, // stanza chiesta con --room o dall'invito
+}
+
+async fn join(wait: Duration, ctx: &ScriptContext) -> bool {
+    let ScriptContext {
+        chat,
+        myself,
+        node,
+        connections,
+        room,
+    } = ctx;
+
+    if tokio::time::timeout(wait, node.synced.notified()).await.is_err() {
+        say!("No room history received within {}s", wait.as_secs());
+        return false;
+    }
+
+    // il Sync porta la stanza di chi ci ha accolto, che può non essere quella chiesta
+    let joined = chat.lock().await.room.clone();
+    if &joined != room {
+        say!("The peer is in room {}, not {}", sanitize_line(&joined), sanitize_line(room));
+        return false;
+    }
+
+    // i messaggi vanno direttamente a ogni membro: prima di scrivere si aspetta di essere collegati a tutti
+    let deadline = Instant::now() + MESH_WAIT;
+    while Instant::now() < deadline {
+        let others: Vec<String> = {
+            let chat_lock = chat.lock().await;
+            chat_lock.members.iter().filter(|m| m.id != myself.id).map(|m| m.id.clone()).collect()
+        };
+
+        let mut missing = false;
+        for id in &others {
+            missing |= !connections.is_connected(id).await;
+        }
+        if !missing {
+            break;
+        }
+        tokio::time::sleep(POLL).await;
+    }
+    true
+}
+
+// Esce con successo se almeno un membro conferma di averlo ricevuto
+pub async fn send(out: &mut JsonOut, text: String, reply_to: Option<String>, wait: Duration, ctx: &ScriptContext) -> i32 {
+    if !join(wait, ctx).await {
+        return EXIT_UNREACHABLE;
+    }
+    let ScriptContext {
+        chat,
+        myself,
+        node,
+        connections,
+        room,
+    } = ctx;
+
+    if let Some(parent) = &reply_to
+        && !chat.lock().await.all_messages.iter().any(|m| &m.id == parent)
+    {
+        say!("No message {} to reply to", parent);
+        return EXIT_USAGE;
+    }
+
+    let id = send_message(text, reply_to, chat, myself, node, connections).await;
+    let recipients = chat.lock().await.members.iter().filter(|m| m.id != myself.id).count();
+
+    let deadline = Instant::now() + ACK_WAIT;
+    let mut delivered = 0;
+    while Instant::now() < deadline {
+        delivered = chat.lock().await.receipts.get(&id).map_or(0, |r| r.delivered.len());
+        if delivered >= recipients {
+            break;
+        }
+        tokio::time::sleep(POLL).await;
+    }
+
+    let _ = out.emit(&json!({ "id": id, "room": room, "recipients": recipients, "delivered": delivered }));
+
+    if delivered == 0 { EXIT_FAILED } else { EXIT_OK }
+}
+
+pub async fn members(out: &mut JsonOut, wait: Duration, ctx: &ScriptContext) -> i32 {
+    if !join(wait, ctx).await {
+        return EXIT_UNREACHABLE;
+    }
+
+    let infos = member_infos(&*ctx.chat.lock().await, &ctx.myself.id);
+    let _ = out.emit(&infos);
+    EXIT_OK
+}
+
+// Ultimi count messaggi, uno per riga; con follow anche gli eventi nuovi finché la stanza non si svuota
+pub async fn tail(out: &mut JsonOut, count: usize, follow: bool, wait: Duration, ctx: &ScriptContext) -> i32 {
+    // ci si iscrive prima di leggere la storia, così non si perde niente in mezzo
+    let mut events = ctx.node.events.subscribe();
+
+    if !join(wait, ctx).await {
+        return EXIT_UNREACHABLE;
+    }
+    let ScriptContext {
+        chat, node, connections, ..
+    } = ctx;
+
+    let mut seen = HashSet::new();
+    for event in history(&*chat.lock().await, node, count).await {
+        if let ChatEvent::Message { id, .. } = &event {
+            seen.insert(id.clone());
+        }
+        if out.emit(&event).is_err() {
+            return EXIT_OK;
+        }
+    }
+
+    if !follow {
+        return EXIT_OK;
+    }
+
+    let mut check = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(ChatEvent::Message { id, .. }) if seen.contains(&id) => {}
+                Ok(event) => {
+                    if out.emit(&event).is_err() {
+                        return EXIT_OK;
+                    }
+                }
+                Err(RecvError::Lagged(n)) => say!("Skipped {} events", n),
+                Err(RecvError::Closed) => return EXIT_FAILED,
+            },
+            _ = check.tick() => {
+                if connections.connections.lock().await.is_empty() {
+                    say!("Disconnected from the room");
+                    return EXIT_UNREACHABLE;
+                }
+            }
+            _ = tokio::signal::ctrl_c() => return EXIT_OK,
+        }
+    }
+}
+
+// Peer visti sulla LAN entro timeout; non serve essere in una stanza
+pub async fn discover(out: &mut JsonOut, timeout: Duration, discovered: &DiscoveredPeers) -> i32 {
+    tokio::time::sleep(timeout).await;
+
+    let peers: Vec<_> = discovered
+        .list()
+        .await
+        .into_iter()
+        .map(|p| {
+            json!({
+                "id": p.id,
+                "username": p.username,
+                "room": p.room,
+                "addrs": p.addrs.iter().map(|a| a.to_string()).collect::<Vec<_>>(),
+            })
+        })
+        .collect();
+
+    let _ = out.emit(&peers);
+    EXIT_OK
+}
@@ -0,0 +1,9 @@
+pub mod local;
+pub mod output;
+pub mod remote;
+
+// Codici di uscita dei sottocomandi per script (send, tail, members, discover)
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_FAILED: i32 = 1; // es. messaggio non consegnato a nessuno
+pub const EXIT_USAGE: i32 = 2; // come clap per gli argomenti sbagliati
+pub const EXIT_UNREACHABLE: i32 = 3; // stanza o daemon non raggiungibili, storia non arrivata in tempo
@@ -0,0 +1,23 @@
+use std::io::{self, Write};
+
+use serde::Serialize;
+
+// Per gli script stdout deve contenere solo JSON: quello che il nodo stampa (indirizzi,
+// connessioni, avvisi...) va su stderr con Output::Stderr, qui passano solo i risultati
+pub struct JsonOut {
+    stdout: io::Stdout,
+}
+
+impl JsonOut {
+    pub fn new() -> Self {
+        Self { stdout: io::stdout() }
+    }
+
+    // Un valore JSON per riga; se chi legge ha chiuso la pipe (es. | head) non c'è altro da fare
+    pub fn emit<T: Serialize>(&mut self, value: &T) -> io::Result<()> {
+        let line = serde_json::to_string(value).expect("Failed to serialize");
+        let mut stdout = self.stdout.lock();
+        writeln!(stdout, "{}", line)?;
+        stdout.flush()
+    }
+}
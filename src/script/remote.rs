@@ -0,0 +1,107 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use serde_json::{Value, json};
+
+use crate::daemon::client::RpcClient;
+use crate::script::output::JsonOut;
+use crate::script::{EXIT_FAILED, EXIT_OK, EXIT_UNREACHABLE};
+use crate::state::state_events::ChatEvent;
+use crate::ui::handle_output::say;
+
+// Gli stessi sottocomandi attraverso un daemon già nella stanza (docs/daemon-protocol.md)
+
+async fn client(socket: &Path) -> Option<RpcClient> {
+    match RpcClient::connect(socket).await {
+        Ok(client) => Some(client),
+        Err(e) => {
+            say!("{} (start one with `p2pchat daemon`, or use -c/-j/--relay/-d)", e);
+            None
+        }
+    }
+}
+
+pub async fn send(out: &mut JsonOut, socket: &Path, text: String, reply_to: Option<String>) -> i32 {
+    let Some(mut client) = client(socket).await else {
+        return EXIT_UNREACHABLE;
+    };
+
+    match client.call("send", json!({ "text": text, "reply_to": reply_to })).await {
+        Ok(result) => {
+            let _ = out.emit(&result);
+            EXIT_OK
+        }
+        Err(e) => {
+            say!("Send failed: {}", e);
+            EXIT_FAILED
+        }
+    }
+}
+
+pub async fn members(out: &mut JsonOut, socket: &Path) -> i32 {
+    let Some(mut client) = client(socket).await else {
+        return EXIT_UNREACHABLE;
+    };
+
+    match client.call("members", Value::Null).await {
+        Ok(result) => {
+            let _ = out.emit(&result);
+            EXIT_OK
+        }
+        Err(e) => {
+            say!("Cannot list members: {}", e);
+            EXIT_FAILED
+        }
+    }
+}
+
+pub async fn tail(out: &mut JsonOut, socket: &Path, count: usize, follow: bool) -> i32 {
+    let Some(mut client) = client(socket).await else {
+        return EXIT_UNREACHABLE;
+    };
+
+    if follow && let Err(e) = client.call("subscribe", Value::Null).await {
+        say!("Cannot subscribe: {}", e);
+        return EXIT_FAILED;
+    }
+
+    let history: Vec<ChatEvent> = match client.call("history", json!({ "count": count })).await {
+        Ok(result) => serde_json::from_value(result).unwrap_or_default(),
+        Err(e) => {
+            say!("Cannot read the history: {}", e);
+            return EXIT_FAILED;
+        }
+    };
+
+    let mut seen = HashSet::new();
+    for event in history {
+        if let ChatEvent::Message { id, .. } = &event {
+            seen.insert(id.clone());
+        }
+        if out.emit(&event).is_err() {
+            return EXIT_OK;
+        }
+    }
+
+    if !follow {
+        return EXIT_OK;
+    }
+
+    loop {
+        tokio::select! {
+            event = client.next_event() => match event {
+                Some(ChatEvent::Message { id, .. }) if seen.contains(&id) => {}
+                Some(event) => {
+                    if out.emit(&event).is_err() {
+                        return EXIT_OK;
+                    }
+                }
+                None => {
+                    say!("The daemon closed the connection");
+                    return EXIT_UNREACHABLE;
+                }
+            },
+            _ = tokio::signal::ctrl_c() => return EXIT_OK,
+        }
+    }
+}
@@ -1,3 +1,20 @@
+pub mod state_access;
+pub mod state_amendment;
+pub mod state_archive;
 pub mod state_chat;
 pub mod state_packets;
-pub mod state_discovery;
\ No newline at end of file
+pub mod state_discovery;
+pub mod state_events;
+pub mod state_format;
+pub mod state_handles;
+pub mod state_invite;
+pub mod state_keyring;
+pub mod state_mentions;
+pub mod state_node;
+pub mod state_presence;
+pub mod state_reactions;
+pub mod state_receipts;
+pub mod state_retention;
+pub mod state_search;
+pub mod state_session;
+pub mod state_typing;
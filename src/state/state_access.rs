@@ -0,0 +1,123 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use crate::state::state_retention::Retention;
+
+// Regole della stanza, viaggiano nel Sync così ogni membro le applica a chi si connette a lui.
+// Chiavi in allow e banned sono impronte (vedi crypto::identity::fingerprint)
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct RoomAccess {
+    pub owner: Option<String>, // chiave pubblica di chi ha creato la stanza, l'unico che può fare /kick e /ban
+    pub password: bool,
+    pub allow: Vec<String>,
+    pub banned: Vec<String>,
+}
+
+impl RoomAccess {
+    pub fn is_banned(&self, fingerprint: &str) -> bool {
+        self.banned.iter().any(|b| b == fingerprint)
+    }
+
+    pub fn is_allowed(&self, fingerprint: &str) -> bool {
+        self.allow.is_empty() || self.allow.iter().any(|a| a == fingerprint)
+    }
+
+    pub fn ban(&mut self, fingerprint: &str) {
+        if !self.is_banned(fingerprint) {
+            self.banned.push(fingerprint.to_string());
+        }
+        self.allow.retain(|a| a != fingerprint);
+    }
+}
+
+// Firma dell'owner sulle regole della stanza (accessi e retention): nel Sync le può portare
+// qualunque membro, ma chi le riceve le accetta solo se sono firmate dall'owner che conosce
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RulesSignature {
+    pub timestamp: u64, // cresce a ogni modifica, così una firma vecchia non sostituisce una nuova
+    pub signature: String,
+}
+
+pub fn rules_payload(room: &str, access: &RoomAccess, retention: &Retention, timestamp: u64) -> Vec<u8> {
+    let access = serde_json::to_string(access).expect("Failed to serialize");
+    let retention = serde_json::to_string(retention).expect("Failed to serialize");
+    format!("p2pchat-rules:{}:{}:{}:{}", room, timestamp, access, retention).into_bytes()
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum ModAction {
+    Kick,
+    Ban,
+}
+
+impl fmt::Display for ModAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ModAction::Kick => write!(f, "kicked"),
+            ModAction::Ban => write!(f, "banned"),
+        }
+    }
+}
+
+// Un /kick o /ban vale solo appena firmato: viaggia solo in diretta, mai nel Sync, quindi uno
+// più vecchio (o troppo nel futuro, orologi a parte) è qualcuno che ripete un pacchetto visto
+pub const MODERATION_MAX_AGE: u64 = 600;
+
+// /kick e /ban firmati dall'owner: chi li riceve controlla la firma con RoomAccess.owner
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Moderation {
+    pub action: ModAction,
+    pub room: String,
+    pub target: String,     // id del membro
+    pub target_key: String, // impronta della sua chiave, per il ban
+    pub timestamp: u64,
+    pub signature: String,
+}
+
+impl Moderation {
+    pub fn is_fresh(&self, now: u64) -> bool {
+        self.timestamp.saturating_add(MODERATION_MAX_AGE) >= now && self.timestamp <= now.saturating_add(MODERATION_MAX_AGE)
+    }
+
+    pub fn payload(&self) -> Vec<u8> {
+        format!(
+            "p2pchat-moderation:{:?}:{}:{}:{}:{}",
+            self.action, self.room, self.target, self.target_key, self.timestamp
+        )
+        .into_bytes()
+    }
+}
+
+// Parte locale del controllo accessi: la password non va mai nel Sync
+#[derive(Clone)]
+pub struct Access {
+    pub password: Option<String>,
+    kicked: Arc<Mutex<HashSet<String>>>,
+    applied: Arc<Mutex<HashSet<String>>>, // firme dei /kick e /ban già applicati
+}
+
+impl Access {
+    pub fn new(password: Option<String>) -> Self {
+        Self {
+            password,
+            kicked: Arc::new(Mutex::new(HashSet::new())),
+            applied: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    // false se la stessa azione è già stata applicata: un pacchetto ripetuto non conta di nuovo
+    pub async fn first_time(&self, moderation: &Moderation) -> bool {
+        self.applied.lock().await.insert(moderation.signature.clone())
+    }
+
+    pub async fn kick(&self, id: &str) {
+        self.kicked.lock().await.insert(id.to_string());
+    }
+
+    pub async fn is_kicked(&self, id: &str) -> bool {
+        self.kicked.lock().await.contains(id)
+    }
+}
@@ -0,0 +1,54 @@
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::identity::verify;
+use crate::state::state_chat::Message;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum AmendAction {
+    Edit { text: String, key_id: Option<String> }, // testo cifrato come quello dei messaggi
+    Delete,
+}
+
+// /edit e /delete: firmati dall'autore, restano nel messaggio così arrivano anche col Sync.
+// La firma copre la modifica, non il messaggio di partenza, che resta non firmato
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Amendment {
+    pub message: String,
+    pub author: String,
+    pub action: AmendAction,
+    pub timestamp: u64,
+    pub signature: String,
+}
+
+impl Amendment {
+    pub fn payload(&self) -> Vec<u8> {
+        let (action, text) = match &self.action {
+            AmendAction::Edit { text, key_id } => ("edit", format!("{}:{}", key_id.as_deref().unwrap_or(""), text)),
+            AmendAction::Delete => ("delete", String::new()),
+        };
+
+        format!(
+            "p2pchat-amend:{}:{}:{}:{}:{}",
+            self.message, self.author, action, text, self.timestamp
+        )
+        .into_bytes()
+    }
+
+    // Modifica già applicata a un messaggio (es. arrivato col Sync): deve essere dell'autore, firmata
+    // con la sua chiave e corrispondere al testo che il messaggio porta
+    pub fn is_valid_for(&self, message: &Message, author_key: &str) -> bool {
+        let text_matches = match &self.action {
+            AmendAction::Edit { text, key_id } => message.text == *text && message.key_id == *key_id,
+            AmendAction::Delete => message.text.is_empty() && message.key_id.is_none(),
+        };
+
+        self.message == message.id
+            && message.sender_id.as_ref() == Some(&self.author)
+            && text_matches
+            && verify(author_key, &self.payload(), &self.signature)
+    }
+
+    pub fn is_delete(&self) -> bool {
+        self.action == AmendAction::Delete
+    }
+}
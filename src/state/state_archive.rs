@@ -0,0 +1,67 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::state::state_chat::Message;
+
+// Storia esportata in chiaro: si legge senza chiavi e si può reimportare con /import
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Archive {
+    pub room: String,
+    pub exported_at: u64,
+    pub messages: Vec<ArchivedMessage>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ArchivedMessage {
+    pub id: String,
+    pub sender: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sender_id: Option<String>,
+    pub timestamp: u64,
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<String>,
+    #[serde(default)]
+    pub edited: bool,
+    #[serde(default)]
+    pub deleted: bool,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub reactions: BTreeMap<String, Vec<String>>,
+}
+
+impl ArchivedMessage {
+    pub fn new(message: &Message, text: String) -> Self {
+        let deleted = message.amended.as_ref().is_some_and(|a| a.is_delete());
+
+        Self {
+            id: message.id.clone(),
+            sender: message.sender.clone(),
+            sender_id: message.sender_id.clone(),
+            timestamp: message.timestamp,
+            text: if deleted { String::new() } else { text },
+            reply_to: message.reply_to.clone(),
+            edited: message.amended.is_some() && !deleted,
+            deleted,
+            reactions: message.reactions.clone(),
+        }
+    }
+
+    // Torna un messaggio della Chat, ancora in chiaro: chi importa lo cifra con la chiave attuale.
+    // Le modifiche non si possono ricostruire, la firma non è nell'archivio: resta il testo finale.
+    // Mittente e testo vengono da un file qualsiasi, quindi il messaggio resta locale
+    pub fn into_message(self) -> Message {
+        Message {
+            id: self.id,
+            sender: self.sender,
+            text: self.text,
+            timestamp: self.timestamp,
+            key_id: None,
+            sender_id: self.sender_id,
+            amended: None,
+            reply_to: self.reply_to,
+            reactions: self.reactions,
+            expires: None,
+            imported: true,
+        }
+    }
+}
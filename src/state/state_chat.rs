@@ -1,82 +1,558 @@
+use rand::random;
 use serde::{Deserialize, Serialize};
-use tokio::sync::{Mutex, mpsc};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
+use tokio::sync::{Mutex, mpsc};
 
+use crate::crypto::identity::{Identity, fingerprint, verify};
+use crate::state::state_access::{RoomAccess, RulesSignature, rules_payload};
+use crate::state::state_amendment::{AmendAction, Amendment};
 use crate::state::state_packets::Packet;
+use crate::state::state_presence::{Presence, Status};
+use crate::state::state_receipts::Receipt;
+use crate::state::state_retention::Retention;
+
+pub const MAX_USERNAME_LEN: usize = 24;
+
+// Niente spazi né simboli strani: i nomi finiscono nei prompt, nei comandi e nelle menzioni
+pub fn validate_username(username: &str) -> Result<(), String> {
+    if username.is_empty() || username.chars().count() > MAX_USERNAME_LEN {
+        return Err(format!("usernames must be 1 to {} characters long", MAX_USERNAME_LEN));
+    }
+
+    if !username.chars().all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.')) {
+        return Err("usernames may only contain letters, digits, '_', '-' and '.'".to_string());
+    }
+
+    Ok(())
+}
 
 #[derive(Clone)]
 pub struct Connections {
     pub connections: Arc<Mutex<Vec<mpsc::UnboundedSender<Packet>>>>,
+    by_member: Arc<Mutex<HashMap<String, mpsc::UnboundedSender<Packet>>>>, // per /kick: chi sta dietro a ogni connessione
 }
 
 impl Connections {
     pub fn new() -> Self {
         Self {
             connections: Arc::new(Mutex::new(vec![])),
+            by_member: Arc::new(Mutex::new(HashMap::new())),
         }
     }
+
+    pub async fn add(&self, tx: mpsc::UnboundedSender<Packet>) {
+        self.connections.lock().await.push(tx);
+    }
+
+    pub async fn tag(&self, id: &str, tx: &mpsc::UnboundedSender<Packet>) {
+        self.by_member.lock().await.insert(id.to_string(), tx.clone());
+    }
+
+    pub async fn remove(&self, tx: &mpsc::UnboundedSender<Packet>) {
+        self.connections.lock().await.retain(|c| !c.same_channel(tx));
+        self.by_member.lock().await.retain(|_, c| !c.same_channel(tx));
+    }
+
+    pub async fn send_to(&self, id: &str, packet: Packet) {
+        if let Some(tx) = self.by_member.lock().await.get(id) {
+            let _ = tx.send(packet);
+        }
+    }
+
+    pub async fn is_connected(&self, id: &str) -> bool {
+        self.by_member.lock().await.contains_key(id)
+    }
+
+    // Smette di inoltrare al membro e gli dice perché; un client onesto a quel punto chiude
+    pub async fn drop_member(&self, id: &str, reason: &str) {
+        let Some(tx) = self.by_member.lock().await.remove(id) else {
+            return;
+        };
+
+        let _ = tx.send(Packet::Denied(reason.to_string()));
+        self.connections.lock().await.retain(|c| !c.same_channel(&tx));
+    }
 }
 
+// Il messaggio non è firmato: in diretta il mittente è chi sta dietro alla sessione verificata,
+// ma nella storia di un Sync mittente e testo sono quelli che dice chi la manda. Sono firmate
+// solo le modifiche (Amendment), quindi un membro può inventare messaggi mai modificati a nome di altri
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Message {
+    #[serde(default)]
+    pub id: String,
     pub sender: String,
-    pub text: String,
+    pub text: String, // cifrato con la chiave di gruppo key_id, se presente
     pub timestamp: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sender_id: Option<String>, // sender è il nome al momento dell'invio, l'id serve a ritrovare quello attuale
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub amended: Option<Amendment>, // ultima modifica o cancellazione
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<String>, // id del messaggio a cui risponde
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub reactions: BTreeMap<String, Vec<String>>, // emoji -> id dei membri che l'hanno messa
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<u64>, // messaggi a scomparsa: dopo questo istante nessun membro lo tiene
+    #[serde(skip)]
+    pub imported: bool, // da /import: solo nella nostra storia, non va nel Sync
 }
 
 impl Message {
-    pub fn new(sender: String, text: String, timestamp: u64) -> Self {
+    pub fn new(sender: &Member, text: String, timestamp: u64) -> Self {
         Self {
-            sender,
+            id: hex::encode(random::<[u8; 8]>()),
+            sender: sender.username.clone(),
             text,
             timestamp,
+            key_id: None,
+            sender_id: Some(sender.id.clone()),
+            amended: None,
+            reply_to: None,
+            reactions: BTreeMap::new(),
+            expires: None,
+            imported: false,
         }
     }
+
+    // Dati autenticati insieme al testo cifrato: non si può spostare un testo su un altro messaggio
+    // né togliere o allungare la scadenza di un messaggio a scomparsa
+    pub fn aad(&self) -> Vec<u8> {
+        format!(
+            "{}:{}:{}:{}:{}",
+            self.id,
+            self.sender,
+            self.timestamp,
+            self.reply_to.as_deref().unwrap_or(""),
+            self.expires.map_or(String::new(), |e| e.to_string())
+        )
+        .into_bytes()
+    }
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
 pub struct Member {
-    pub ip: String,
-    pub port: u16,
+    pub addrs: Vec<SocketAddr>,
     pub username: String,
     pub id: String,
+    pub pubkey: String,
+    #[serde(default)]
+    pub status: Status,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status_message: Option<String>,
 }
 
 impl Member {
-    pub fn new(username: String, ip: String, port: u16, id: String) -> Self {
+    pub fn new(username: String, addrs: Vec<SocketAddr>, id: String, pubkey: String) -> Self {
         Self {
             username,
-            ip,
-            port,
+            addrs,
             id,
+            pubkey,
+            status: Status::Online,
+            status_message: None,
         }
     }
+
+    // Indirizzo da cui abbiamo visto arrivare (o a cui abbiamo raggiunto) il membro:
+    // va in cima alla lista perché è l'unico di cui sappiamo che funziona
+    pub fn add_observed(&mut self, observed: IpAddr) {
+        let Some(port) = self.addrs.first().map(|a| a.port()) else {
+            return;
+        };
+        let addr = SocketAddr::new(observed.to_canonical(), port);
+
+        self.addrs.retain(|a| *a != addr);
+        self.addrs.insert(0, addr);
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Chat {
+    pub room: String,
     pub all_messages: Vec<Arc<Message>>,
     pub members: Vec<Arc<Member>>,
+    #[serde(default)]
+    pub access: RoomAccess,
+    #[serde(default)]
+    pub retention: Retention,
+    #[serde(default)]
+    pub rules: Option<RulesSignature>,
+    #[serde(skip)]
+    pub receipts: HashMap<String, Receipt>, // solo per i nostri messaggi, non va nel Sync
 }
 
 impl Chat {
-    pub fn new() -> Self {
+    pub fn new(room: String) -> Self {
         Self {
+            room,
             all_messages: Vec::new(),
             members: Vec::new(),
+            access: RoomAccess::default(),
+            retention: Retention::default(),
+            rules: None,
+            receipts: HashMap::new(),
+        }
+    }
+
+    // Solo l'owner, dopo ogni modifica alle regole
+    pub fn sign_rules(&mut self, identity: &Identity, now: u64) {
+        let timestamp = self.rules.as_ref().map_or(0, |r| r.timestamp + 1).max(now);
+        let signature = identity.sign(&rules_payload(&self.room, &self.access, &self.retention, timestamp));
+        self.rules = Some(RulesSignature { timestamp, signature });
+    }
+
+    // Regole arrivate da un altro membro: valgono solo se le firma l'owner con l'impronta attesa
+    // (quando la conosciamo) e non sono più vecchie delle nostre. Altrimenti restano le nostre
+    pub fn accept_rules(
+        &mut self,
+        access: &RoomAccess,
+        retention: &Retention,
+        signed: &RulesSignature,
+        owner: Option<&str>,
+    ) -> Result<(), String> {
+        let Some(key) = &access.owner else {
+            return Err("room rules without an owner".to_string());
+        };
+
+        if owner.is_some_and(|f| fingerprint(key) != f) {
+            return Err("room rules from a different owner".to_string());
+        }
+
+        if self.access.owner.as_ref() == Some(key) && self.rules.as_ref().is_some_and(|r| r.timestamp > signed.timestamp) {
+            return Err("room rules older than ours".to_string());
+        }
+
+        if !verify(key, &rules_payload(&self.room, access, retention, signed.timestamp), &signed.signature) {
+            return Err("room rules with an invalid signature".to_string());
         }
+
+        self.access = access.clone();
+        self.retention = retention.clone();
+        self.rules = Some(signed.clone());
+        Ok(())
     }
 
-    pub fn set_all_messages(&mut self, messages: Vec<Arc<Message>>) {
-        self.all_messages = messages;
+    // Storia di un Sync: il testo originale di un messaggio modificato non c'è più, quindi
+    // se la modifica non è valida (firma, autore o testo) si scarta tutto il messaggio.
+    // I messaggi importati restano. Restituisce quanti ne sono stati scartati
+    pub fn set_all_messages(&mut self, messages: Vec<Arc<Message>>, members: &[Arc<Member>]) -> usize {
+        let author_key = |id: &str| {
+            self.member(id)
+                .or_else(|| members.iter().find(|m| m.id == id))
+                .map(|m| m.pubkey.clone())
+        };
+
+        let (valid, invalid): (Vec<_>, Vec<_>) = messages.into_iter().partition(|m| match &m.amended {
+            Some(amendment) => author_key(&amendment.author).is_some_and(|key| amendment.is_valid_for(m, &key)),
+            None => true,
+        });
+
+        // i messaggi importati non viaggiano nel Sync: restano quelli che non sono arrivati con la storia
+        let known: HashSet<String> = valid.iter().map(|m| m.id.clone()).collect();
+        let imported: Vec<_> = self
+            .all_messages
+            .drain(..)
+            .filter(|m| m.imported && !known.contains(&m.id))
+            .collect();
+
+        self.all_messages = valid;
+        if !imported.is_empty() {
+            self.all_messages.extend(imported);
+            self.all_messages.sort_by_key(|m| m.timestamp);
+        }
+        invalid.len()
     }
 
     pub fn add_message(&mut self, message: Message) {
         self.all_messages.push(Arc::new(message));
     }
 
+    // Messaggi importati: quelli con un id già presente si saltano, il resto va al suo posto nel tempo.
+    // Restituisce gli id aggiunti
+    pub fn merge_messages(&mut self, messages: Vec<Message>) -> Vec<String> {
+        let mut known: HashSet<String> = self.all_messages.iter().map(|m| m.id.clone()).collect();
+        let mut added = vec![];
+
+        for message in messages {
+            if !message.id.is_empty() && known.insert(message.id.clone()) {
+                added.push(message.id.clone());
+                self.all_messages.push(Arc::new(message));
+            }
+        }
+
+        if !added.is_empty() {
+            self.all_messages.sort_by_key(|m| m.timestamp);
+        }
+        added
+    }
+
+    // Copia da mandare col Sync: i messaggi importati restano solo in questo nodo, nessuno
+    // può far arrivare agli altri messaggi attribuiti a qualcun altro
+    pub fn for_sync(&self) -> Chat {
+        let mut chat = self.clone();
+        chat.all_messages.retain(|m| !m.imported);
+        chat
+    }
+
+    // Applica Retention: prima le scadenze e l'età, poi si tengono i più recenti finché
+    // stanno nei limiti di numero e byte. Restituisce quanti messaggi sono stati tolti
+    pub fn prune(&mut self, now: u64) -> usize {
+        let before = self.all_messages.len();
+        let oldest = self.retention.max_age.map_or(0, |age| now.saturating_sub(age));
+
+        self.all_messages
+            .retain(|m| m.timestamp >= oldest && m.expires.is_none_or(|e| e > now));
+
+        if let Some(max) = self.retention.max_count
+            && self.all_messages.len() > max
+        {
+            let excess = self.all_messages.len() - max;
+            self.all_messages.drain(..excess);
+        }
+
+        if let Some(max) = self.retention.max_bytes {
+            let mut total: usize = self.all_messages.iter().map(|m| m.text.len()).sum();
+            let mut excess = 0;
+            for m in self.all_messages.iter() {
+                if total <= max {
+                    break;
+                }
+                total -= m.text.len();
+                excess += 1;
+            }
+            self.all_messages.drain(..excess);
+        }
+
+        before - self.all_messages.len()
+    }
+
     pub fn add_member(&mut self, member: Member) {
         self.members.push(Arc::new(member));
     }
 
-}
\ No newline at end of file
+    pub fn member(&self, id: &str) -> Option<&Arc<Member>> {
+        self.members.iter().find(|m| m.id == id)
+    }
+
+    // Già nella chat con la stessa chiave, es. un membro che si collega dopo un Sync
+    pub fn knows(&self, member: &Member) -> bool {
+        self.member(&member.id).is_some_and(|m| m.pubkey == member.pubkey)
+    }
+
+    // La nostra voce nella chat è quella aggiornata da /nick, myself resta quella dell'avvio
+    pub fn me(&self, myself: &Member) -> Member {
+        self.member(&myself.id).map_or_else(|| myself.clone(), |m| (**m).clone())
+    }
+
+    pub fn set_presence(&mut self, id: &str, presence: Presence) -> bool {
+        let Some(m) = self.members.iter_mut().find(|m| m.id == id) else {
+            return false;
+        };
+
+        let m = Arc::make_mut(m);
+        m.status = presence.status;
+        m.status_message = presence.message;
+        true
+    }
+
+    pub fn rename(&mut self, id: &str, username: &str) -> Option<String> {
+        let m = self.members.iter_mut().find(|m| m.id == id)?;
+        Some(std::mem::replace(&mut Arc::make_mut(m).username, username.to_string()))
+    }
+
+    // Nome da mostrare: se un altro membro si chiama uguale si aggiunge l'inizio dell'id
+    pub fn display_name(&self, id: Option<&str>, username: &str) -> String {
+        let Some(id) = id else {
+            return username.to_string();
+        };
+        let username = self.member(id).map_or(username, |m| m.username.as_str());
+
+        if self.members.iter().any(|m| m.id != id && m.username == username) {
+            format!("{}#{}", username, id.chars().take(4).collect::<String>())
+        } else {
+            username.to_string()
+        }
+    }
+
+    pub fn label(&self, message: &mut Message) {
+        message.sender = self.display_name(message.sender_id.as_deref(), &message.sender);
+    }
+
+    // La firma la controlla chi chiama; qui si verifica solo che sia dell'autore e non superata
+    pub fn amend(&mut self, amendment: Amendment) -> Result<(), String> {
+        let Some(message) = self.all_messages.iter_mut().find(|m| m.id == amendment.message) else {
+            return Err("unknown message".to_string());
+        };
+
+        if message.sender_id.as_ref() != Some(&amendment.author) {
+            return Err("not the author of the message".to_string());
+        }
+
+        if let Some(previous) = &message.amended
+            && (previous.is_delete() || previous.timestamp > amendment.timestamp)
+        {
+            return Err("the message was already changed".to_string());
+        }
+
+        let message = Arc::make_mut(message);
+        match &amendment.action {
+            AmendAction::Edit { text, key_id } => {
+                message.text = text.clone();
+                message.key_id = key_id.clone();
+            }
+            AmendAction::Delete => {
+                message.text = String::new();
+                message.key_id = None;
+            }
+        }
+        message.amended = Some(amendment);
+
+        Ok(())
+    }
+
+    // Restituisce true se è cambiato qualcosa; ognuno può togliere solo le proprie
+    pub fn react(&mut self, message_id: &str, member_id: &str, emoji: &str, add: bool) -> bool {
+        let Some(message) = self.all_messages.iter_mut().find(|m| m.id == message_id) else {
+            return false;
+        };
+
+        let present = message.reactions.get(emoji).is_some_and(|ids| ids.iter().any(|id| id == member_id));
+        if present == add {
+            return false;
+        }
+
+        let reactions = &mut Arc::make_mut(message).reactions;
+        if add {
+            reactions.entry(emoji.to_string()).or_default().push(member_id.to_string());
+        } else if let Some(ids) = reactions.get_mut(emoji) {
+            ids.retain(|id| id != member_id);
+            if ids.is_empty() {
+                reactions.remove(emoji);
+            }
+        }
+
+        true
+    }
+
+    // Conferma da un membro per uno dei messaggi mandati da my_id; la lettura implica la ricezione
+    pub fn acknowledge(&mut self, my_id: &str, message_id: &str, member_id: &str, read: bool) {
+        let ours = self
+            .all_messages
+            .iter()
+            .any(|m| m.id == message_id && m.sender_id.as_deref() == Some(my_id));
+        if !ours {
+            return;
+        }
+
+        let receipt = self.receipts.entry(message_id.to_string()).or_default();
+        receipt.delivered.insert(member_id.to_string());
+        if read {
+            receipt.read.insert(member_id.to_string());
+        }
+    }
+
+    // Segno accanto ai nostri messaggi: quanti degli altri membri l'hanno ricevuto e letto
+    pub fn mark(&self, message: &mut Message, my_id: &str) {
+        if message.sender_id.as_deref() != Some(my_id) {
+            return;
+        }
+
+        let others = self.members.iter().filter(|m| m.id != my_id).count();
+        let (delivered, read) = self
+            .receipts
+            .get(&message.id)
+            .map_or((0, 0), |r| (r.delivered.len(), r.read.len()));
+
+        message.text = if read > 0 {
+            format!("{}  (✓ {}/{}, read {})", message.text, delivered, others, read)
+        } else {
+            format!("{}  (✓ {}/{})", message.text, delivered, others)
+        };
+    }
+
+    pub fn promote_addr(&mut self, id: &str, addr: SocketAddr) {
+        if let Some(m) = self.members.iter_mut().find(|m| m.id == id) {
+            Arc::make_mut(m).add_observed(addr.ip());
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn member(name: &str, id: &str) -> Member {
+        Member::new(name.to_string(), vec![], id.to_string(), Identity::generate().public_key())
+    }
+
+    fn message(sender: &Member, text: &str, timestamp: u64) -> Arc<Message> {
+        Arc::new(Message::new(sender, text.to_string(), timestamp))
+    }
+
+    #[test]
+    fn sync_keeps_imported_messages() {
+        let alice = member("alice", "id-alice");
+        let mut chat = Chat::new("general".to_string());
+        chat.add_member(alice.clone());
+
+        let old = message(&alice, "before the import", 100);
+        chat.all_messages.push(old.clone());
+
+        let mut imported = Message::new(&alice, "from the archive".to_string(), 50);
+        imported.imported = true;
+        let mut already_synced = (*old).clone();
+        already_synced.imported = true;
+        assert_eq!(chat.merge_messages(vec![imported.clone(), already_synced]).len(), 1);
+
+        let history = vec![old.clone(), message(&alice, "after the import", 200)];
+        assert_eq!(chat.set_all_messages(history, &[]), 0);
+
+        let ids: Vec<&str> = chat.all_messages.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, vec![imported.id.as_str(), old.id.as_str(), chat.all_messages[2].id.as_str()]);
+        assert!(chat.all_messages[0].imported);
+        assert!(!chat.all_messages[1].imported && !chat.all_messages[2].imported);
+        assert_eq!(chat.for_sync().all_messages.len(), 2);
+    }
+
+    #[test]
+    fn sync_drops_forged_amendments() {
+        let alice_key = Identity::generate();
+        let alice = Member::new("alice".to_string(), vec![], "id-alice".to_string(), alice_key.public_key());
+        let mallory_key = Identity::generate();
+        let mut chat = Chat::new("general".to_string());
+        chat.add_member(alice.clone());
+
+        let edited = |text: &str, signer: &Identity| {
+            let mut message = Message::new(&alice, text.to_string(), 100);
+            let mut amendment = Amendment {
+                message: message.id.clone(),
+                author: alice.id.clone(),
+                action: AmendAction::Edit {
+                    text: text.to_string(),
+                    key_id: None,
+                },
+                timestamp: 110,
+                signature: String::new(),
+            };
+            amendment.signature = signer.sign(&amendment.payload());
+            message.amended = Some(amendment);
+            Arc::new(message)
+        };
+
+        let genuine = edited("fixed typo", &alice_key);
+        let forged = edited("I resign", &mallory_key);
+
+        // firmata da un altro, o firmata da alice ma con un testo diverso da quello che porta il messaggio
+        let mut retexted = (*genuine).clone();
+        retexted.text = "I resign".to_string();
+
+        let history = vec![genuine.clone(), forged, Arc::new(retexted)];
+        assert_eq!(chat.set_all_messages(history, &[]), 2);
+        assert_eq!(chat.all_messages.len(), 1);
+        assert_eq!(chat.all_messages[0].id, genuine.id);
+    }
+}
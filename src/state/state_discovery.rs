@@ -1,7 +1,94 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+pub const DISCOVERY_PORT: u16 = 9000;
+pub const MULTICAST_V4: Ipv4Addr = Ipv4Addr::new(239, 255, 42, 99);
+pub const MULTICAST_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0x4242, 0x99); // link-local scope
+
+pub const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(15);
+pub const ANNOUNCE_MIN_GAP: Duration = Duration::from_secs(1);
+pub const PEER_EXPIRY: Duration = Duration::from_secs(60); // 4 annunci persi
+pub const MAX_FRAME_AGE: u64 = 30; // secondi, oltre si considera un replay
+
+// Busta di ogni pacchetto di discovery, il mac c'è solo se è configurato un segreto
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DiscoveryFrame {
+    pub packet: DiscoveryPacket,
+    pub timestamp: u64,
+    pub mac: Option<String>,
+}
 
 #[derive(Serialize, Deserialize, Clone)]
 pub enum DiscoveryPacket {
     Discovery(String),
-    DiscoveryRes(String, u16, String, String),
-}
\ No newline at end of file
+    Announce(Announcement),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Announcement {
+    pub id: String,
+    pub username: String,
+    pub room: String,
+    pub addrs: Vec<SocketAddr>,
+}
+
+#[derive(Clone, Debug)]
+pub struct DiscoveredPeer {
+    pub id: String,
+    pub username: String,
+    pub room: String,
+    pub addrs: Vec<SocketAddr>,
+    pub last_seen: Instant,
+}
+
+#[derive(Clone)]
+pub struct DiscoveredPeers {
+    pub peers: Arc<Mutex<HashMap<String, DiscoveredPeer>>>,
+}
+
+impl DiscoveredPeers {
+    pub fn new() -> Self {
+        Self {
+            peers: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    // Ritorna true se il peer non era nella tabella (o era scaduto)
+    pub async fn update(&self, announcement: Announcement) -> bool {
+        let mut peers = self.peers.lock().await;
+        let is_new = peers
+            .get(&announcement.id)
+            .is_none_or(|p| p.last_seen.elapsed() > PEER_EXPIRY);
+
+        peers.insert(
+            announcement.id.clone(),
+            DiscoveredPeer {
+                id: announcement.id,
+                username: announcement.username,
+                room: announcement.room,
+                addrs: announcement.addrs,
+                last_seen: Instant::now(),
+            },
+        );
+
+        is_new
+    }
+
+    pub async fn remove(&self, id: &str) {
+        self.peers.lock().await.remove(id);
+    }
+
+    // Peer visti di recente, ordinati per stanza e nome così che gli indici di /discover siano stabili
+    pub async fn list(&self) -> Vec<DiscoveredPeer> {
+        let mut peers = self.peers.lock().await;
+        peers.retain(|_, p| p.last_seen.elapsed() <= PEER_EXPIRY);
+
+        let mut list: Vec<DiscoveredPeer> = peers.values().cloned().collect();
+        list.sort_by(|a, b| (&a.room, &a.username, &a.id).cmp(&(&b.room, &b.username, &b.id)));
+        list
+    }
+}
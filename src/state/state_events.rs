@@ -0,0 +1,80 @@
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::state::state_chat::{Member, Message};
+
+const EVENT_BUFFER: usize = 256;
+
+// Quello che succede nella stanza, per chi non guarda il terminale (client del daemon, sottocomandi...)
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChatEvent {
+    Message {
+        id: String,
+        sender: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sender_id: Option<String>,
+        text: String,
+        timestamp: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reply_to: Option<String>,
+    },
+    MemberJoined {
+        id: String,
+        username: String,
+    },
+    MemberLeft {
+        id: String,
+        username: String,
+    },
+}
+
+impl ChatEvent {
+    // Il messaggio deve essere già in chiaro (Keyring::reveal) e con il nome da mostrare
+    pub fn message(shown: &Message) -> Self {
+        ChatEvent::Message {
+            id: shown.id.clone(),
+            sender: shown.sender.clone(),
+            sender_id: shown.sender_id.clone(),
+            text: shown.text.clone(),
+            timestamp: shown.timestamp,
+            reply_to: shown.reply_to.clone(),
+        }
+    }
+
+    pub fn joined(member: &Member) -> Self {
+        ChatEvent::MemberJoined {
+            id: member.id.clone(),
+            username: member.username.clone(),
+        }
+    }
+
+    pub fn left(member: &Member) -> Self {
+        ChatEvent::MemberLeft {
+            id: member.id.clone(),
+            username: member.username.clone(),
+        }
+    }
+}
+
+// Chi si iscrive riceve solo gli eventi da lì in poi; chi resta troppo indietro ne perde alcuni
+#[derive(Clone)]
+pub struct EventBus {
+    tx: broadcast::Sender<ChatEvent>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(EVENT_BUFFER);
+        Self { tx }
+    }
+
+    // nessun iscritto non è un errore: gli eventi semplicemente non servono a nessuno
+    pub fn publish(&self, event: ChatEvent) {
+        let _ = self.tx.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ChatEvent> {
+        self.tx.subscribe()
+    }
+}
@@ -0,0 +1,24 @@
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+// Resa di **grassetto**, *corsivo*, `codice` e link: preferenza solo locale, spenta di default
+#[derive(Clone)]
+pub struct Formatting {
+    inner: Arc<Mutex<bool>>,
+}
+
+impl Formatting {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(enabled)),
+        }
+    }
+
+    pub async fn enabled(&self) -> bool {
+        *self.inner.lock().await
+    }
+
+    pub async fn set(&self, enabled: bool) {
+        *self.inner.lock().await = enabled;
+    }
+}
@@ -0,0 +1,46 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+struct HandlesInner {
+    next: u32,
+    by_id: HashMap<String, u32>,
+    by_handle: HashMap<u32, String>,
+}
+
+// Numeri corti (#3) per riferirsi ai messaggi mostrati, validi solo in questa sessione
+#[derive(Clone)]
+pub struct Handles {
+    inner: Arc<Mutex<HandlesInner>>,
+}
+
+impl Handles {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(HandlesInner {
+                next: 1,
+                by_id: HashMap::new(),
+                by_handle: HashMap::new(),
+            })),
+        }
+    }
+
+    pub async fn assign(&self, message_id: &str) -> u32 {
+        let mut inner = self.inner.lock().await;
+        if let Some(handle) = inner.by_id.get(message_id) {
+            return *handle;
+        }
+
+        let handle = inner.next;
+        inner.next += 1;
+        inner.by_id.insert(message_id.to_string(), handle);
+        inner.by_handle.insert(handle, message_id.to_string());
+        handle
+    }
+
+    // Accetta "#3" o "3"
+    pub async fn resolve(&self, reference: &str) -> Option<String> {
+        let handle: u32 = reference.strip_prefix('#').unwrap_or(reference).parse().ok()?;
+        self.inner.lock().await.by_handle.get(&handle).cloned()
+    }
+}
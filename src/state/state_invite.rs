@@ -0,0 +1,104 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use rand::random;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::Mutex;
+
+use crate::ui::handle_input::get_timestamp;
+
+pub const INVITE_PREFIX: &str = "p2pchat:";
+
+// Contenuto del token di /invite, chiavi di una lettera per tenerlo corto
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Invite {
+    #[serde(rename = "a")]
+    pub addrs: Vec<SocketAddr>,
+    #[serde(rename = "r")]
+    pub room: String,
+    #[serde(rename = "f")]
+    pub fingerprint: String,
+    #[serde(rename = "o", default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>, // impronta dell'owner, per accettare solo le regole firmate da lui
+    #[serde(rename = "e", default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<u64>,
+    #[serde(rename = "s", default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+}
+
+impl Invite {
+    pub fn encode(&self) -> String {
+        let json = serde_json::to_vec(self).expect("Failed to serialize");
+        format!("{}{}", INVITE_PREFIX, URL_SAFE_NO_PAD.encode(json))
+    }
+
+    pub fn decode(token: &str) -> Result<Self, String> {
+        let data = token.trim().strip_prefix(INVITE_PREFIX).ok_or("not a p2pchat invite")?;
+        let json = URL_SAFE_NO_PAD.decode(data).map_err(|e| e.to_string())?;
+        let invite: Invite = serde_json::from_slice(&json).map_err(|e| e.to_string())?;
+
+        if invite.expires.is_some_and(|e| e < get_timestamp()) {
+            return Err("invite expired".to_string());
+        }
+
+        Ok(invite)
+    }
+}
+
+struct IssuedInvite {
+    expires: Option<u64>,
+    one_time: bool,
+}
+
+// Inviti emessi da questo nodo. Hanno un segreto solo quelli con scadenza o monouso,
+// che l'host deve poter controllare quando il peer si presenta. I segreti li conosce solo
+// questo nodo: gli altri membri continuano ad accettare chi si collega direttamente a loro
+#[derive(Clone)]
+pub struct Invites {
+    invites: Arc<Mutex<HashMap<String, IssuedInvite>>>,
+    required: Arc<AtomicBool>, // dopo il primo invito con segreto chi entra deve riscattarne uno
+}
+
+impl Invites {
+    pub fn new() -> Self {
+        Self {
+            invites: Arc::new(Mutex::new(HashMap::new())),
+            required: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub async fn issue(&self, expires: Option<u64>, one_time: bool) -> String {
+        let secret = hex::encode(random::<[u8; 16]>());
+        self.invites
+            .lock()
+            .await
+            .insert(secret.clone(), IssuedInvite { expires, one_time });
+        self.required.store(true, Ordering::Relaxed);
+        secret
+    }
+
+    pub fn required(&self) -> bool {
+        self.required.load(Ordering::Relaxed)
+    }
+
+    pub async fn redeem(&self, secret: &str) -> Result<(), String> {
+        let mut invites = self.invites.lock().await;
+        let Some(invite) = invites.get(secret) else {
+            return Err("unknown or already used invite".to_string());
+        };
+
+        if invite.expires.is_some_and(|e| e < get_timestamp()) {
+            invites.remove(secret);
+            return Err("invite expired".to_string());
+        }
+
+        if invite.one_time {
+            invites.remove(secret);
+        }
+
+        Ok(())
+    }
+}
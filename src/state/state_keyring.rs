@@ -0,0 +1,194 @@
+use rand::random;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use crate::crypto::group;
+use crate::crypto::identity::Identity;
+use crate::state::state_chat::Message;
+
+pub const UNREADABLE: &str = "[encrypted message]";
+pub const DELETED: &str = "message deleted";
+
+// Chiave simmetrica della stanza. Una nuova a ogni uscita o kick, con epoch crescente;
+// se due membri ruotano insieme vince quella con l'issuer di id minore
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct GroupKey {
+    pub epoch: u64,
+    pub issuer: String,
+    pub key: String,
+}
+
+impl GroupKey {
+    fn generate(epoch: u64, issuer: &str) -> Self {
+        Self {
+            epoch,
+            issuer: issuer.to_string(),
+            key: hex::encode(random::<[u8; 32]>()),
+        }
+    }
+
+    // I messaggi indicano la chiave con questo id, non con l'epoch che può non essere unico
+    pub fn id(&self) -> String {
+        hex::encode(&Sha256::digest(self.key.as_bytes())[..8])
+    }
+
+    fn bytes(&self) -> Option<[u8; 32]> {
+        hex::decode(&self.key).ok()?.try_into().ok()
+    }
+
+    fn newer_than(&self, other: &GroupKey) -> bool {
+        (self.epoch, std::cmp::Reverse(&self.issuer)) > (other.epoch, std::cmp::Reverse(&other.issuer))
+    }
+}
+
+// Chiavi di gruppo cifrate per un solo destinatario con la chiave derivata da issuer e recipient
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct KeyShare {
+    pub issuer: String,
+    pub recipient: String,
+    pub keys: String,
+}
+
+impl KeyShare {
+    pub fn seal(identity: &Identity, issuer: &str, recipient_id: &str, recipient_key: &str, keys: &[GroupKey]) -> Option<Self> {
+        let wrap = identity.shared_key(recipient_key)?;
+        let json = serde_json::to_vec(keys).expect("Failed to serialize");
+
+        Some(Self {
+            issuer: issuer.to_string(),
+            recipient: recipient_id.to_string(),
+            keys: group::seal(&wrap, &json, &Self::aad(issuer, recipient_id)),
+        })
+    }
+
+    pub fn open(&self, identity: &Identity, issuer_key: &str) -> Option<Vec<GroupKey>> {
+        let wrap = identity.shared_key(issuer_key)?;
+        let json = group::open(&wrap, &self.keys, &Self::aad(&self.issuer, &self.recipient))?;
+        serde_json::from_slice(&json).ok()
+    }
+
+    fn aad(issuer: &str, recipient: &str) -> Vec<u8> {
+        format!("p2pchat-key-share:{}:{}", issuer, recipient).into_bytes()
+    }
+}
+
+struct KeyringInner {
+    keys: HashMap<String, GroupKey>, // tutte quelle viste, per leggere la storia vecchia
+    current: Option<GroupKey>,
+}
+
+// Sta nel Node: le chiavi non devono mai finire in un Sync
+#[derive(Clone)]
+pub struct Keyring {
+    inner: Arc<Mutex<KeyringInner>>,
+}
+
+impl Keyring {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(KeyringInner {
+                keys: HashMap::new(),
+                current: None,
+            })),
+        }
+    }
+
+    pub async fn rotate(&self, issuer: &str) -> GroupKey {
+        let mut inner = self.inner.lock().await;
+        let epoch = inner.current.as_ref().map_or(1, |k| k.epoch + 1);
+        let key = GroupKey::generate(epoch, issuer);
+
+        inner.keys.insert(key.id(), key.clone());
+        inner.current = Some(key.clone());
+        key
+    }
+
+    pub async fn current(&self) -> Option<GroupKey> {
+        self.inner.lock().await.current.clone()
+    }
+
+    pub async fn all(&self) -> Vec<GroupKey> {
+        self.inner.lock().await.keys.values().cloned().collect()
+    }
+
+    // Entrando in una stanza si buttano le chiavi create da soli all'avvio
+    pub async fn adopt(&self, keys: Vec<GroupKey>) {
+        {
+            let mut inner = self.inner.lock().await;
+            inner.keys.clear();
+            inner.current = None;
+        }
+        self.merge(keys).await;
+    }
+
+    pub async fn merge(&self, keys: Vec<GroupKey>) {
+        let mut inner = self.inner.lock().await;
+
+        for key in keys {
+            if key.bytes().is_none() {
+                continue;
+            }
+
+            if inner.current.as_ref().is_none_or(|c| key.newer_than(c)) {
+                inner.current = Some(key.clone());
+            }
+            inner.keys.insert(key.id(), key);
+        }
+    }
+
+    pub async fn seal(&self, message: &mut Message) {
+        let Some(key) = self.current().await else {
+            return;
+        };
+        let bytes = key.bytes().expect("Invalid group key");
+
+        message.text = group::seal(&bytes, message.text.as_bytes(), &message.aad());
+        message.key_id = Some(key.id());
+    }
+
+    // Per /edit: nuovo testo cifrato con la chiave attuale, legato allo stesso messaggio
+    pub async fn seal_edit(&self, message: &Message, text: &str) -> (String, Option<String>) {
+        let Some(key) = self.current().await else {
+            return (text.to_string(), None);
+        };
+        let bytes = key.bytes().expect("Invalid group key");
+
+        (group::seal(&bytes, text.as_bytes(), &message.aad()), Some(key.id()))
+    }
+
+    // Copia del messaggio con il testo in chiaro, da mostrare; quello nella Chat resta cifrato
+    pub async fn reveal(&self, message: &Message) -> Message {
+        let mut shown = message.clone();
+        shown.key_id = None;
+
+        if message.amended.as_ref().is_some_and(|a| a.is_delete()) {
+            shown.text = DELETED.to_string();
+            return shown;
+        }
+
+        shown.text = self.plaintext(message).await.unwrap_or_else(|| UNREADABLE.to_string());
+
+        if message.amended.is_some() {
+            shown.text.push_str(" (edited)");
+        }
+        shown
+    }
+
+    // Solo il testo attuale, senza segni aggiunti per la visualizzazione; None se manca la chiave
+    pub async fn plaintext(&self, message: &Message) -> Option<String> {
+        match &message.key_id {
+            Some(key_id) => self.decrypt(key_id, message).await,
+            None => Some(message.text.clone()),
+        }
+    }
+
+    async fn decrypt(&self, key_id: &str, message: &Message) -> Option<String> {
+        let inner = self.inner.lock().await;
+        let key = inner.keys.get(key_id)?.bytes()?;
+        let text = group::open(&key, &message.text, &message.aad())?;
+        String::from_utf8(text).ok()
+    }
+}
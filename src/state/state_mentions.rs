@@ -0,0 +1,16 @@
+// @nome, con eventualmente il suffisso #abcd usato per distinguere nomi uguali
+pub fn mentions(text: &str) -> Vec<&str> {
+    text.split(|c: char| c.is_whitespace() || matches!(c, ',' | ':' | ';' | '!' | '?' | '(' | ')'))
+        .filter_map(|word| word.strip_prefix('@'))
+        .map(|name| name.trim_end_matches('.'))
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+// Senza suffisso basta il nome, col suffisso deve combaciare anche l'inizio dell'id
+pub fn mentions_member(text: &str, username: &str, id: &str) -> bool {
+    mentions(text).iter().any(|m| match m.split_once('#') {
+        Some((name, suffix)) => name.eq_ignore_ascii_case(username) && id.starts_with(suffix),
+        None => m.eq_ignore_ascii_case(username),
+    })
+}
@@ -0,0 +1,56 @@
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+use crate::crypto::identity::Identity;
+use crate::notify::notifier::Notifier;
+use crate::plugin::api::Plugins;
+use crate::state::state_access::Access;
+use crate::state::state_events::EventBus;
+use crate::state::state_format::Formatting;
+use crate::state::state_handles::Handles;
+use crate::state::state_invite::Invites;
+use crate::state::state_keyring::Keyring;
+use crate::state::state_presence::Activity;
+use crate::state::state_receipts::Unread;
+use crate::state::state_search::SearchIndex;
+use crate::state::state_typing::TypingPeers;
+
+// Stato locale del nodo che non va mai in un Sync: chiavi private e di gruppo, inviti emessi...
+#[derive(Clone)]
+pub struct Node {
+    pub identity: Arc<Identity>,
+    pub invites: Invites,
+    pub access: Access,
+    pub keyring: Keyring,
+    pub activity: Activity,
+    pub typing: TypingPeers,
+    pub unread: Unread,
+    pub handles: Handles,
+    pub notifiers: Arc<Vec<Box<dyn Notifier>>>,
+    pub formatting: Formatting,
+    pub search: SearchIndex,
+    pub events: EventBus,
+    pub plugins: Plugins,
+    pub synced: Arc<Notify>, // arrivato il Sync della stanza, per chi aspetta la storia senza terminale
+}
+
+impl Node {
+    pub fn new(identity: Identity, password: Option<String>, notifiers: Arc<Vec<Box<dyn Notifier>>>, plugins: Plugins, format: bool) -> Self {
+        Self {
+            identity: Arc::new(identity),
+            invites: Invites::new(),
+            access: Access::new(password),
+            keyring: Keyring::new(),
+            activity: Activity::new(),
+            typing: TypingPeers::new(),
+            unread: Unread::new(),
+            handles: Handles::new(),
+            notifiers,
+            formatting: Formatting::new(format),
+            search: SearchIndex::new(),
+            events: EventBus::new(),
+            plugins,
+            synced: Arc::new(Notify::new()),
+        }
+    }
+}
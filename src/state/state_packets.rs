@@ -1,5 +1,10 @@
 use serde::{Deserialize, Serialize};
 
+use crate::state::state_access::{Moderation, RoomAccess, RulesSignature};
+use crate::state::state_amendment::Amendment;
+use crate::state::state_keyring::KeyShare;
+use crate::state::state_presence::Presence;
+use crate::state::state_retention::Retention;
 use crate::state_chat::{Chat, Member, Message};
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -8,4 +13,19 @@ pub enum Packet {
     InitSyncRequest,
     Sync(Chat),
     Identity(Member, bool),
+    Challenge(String),
+    ChallengeRes(String),
+    InviteAuth(String),
+    Denied(String),
+    AccessChallenge { nonce: String, password: bool },
+    AccessResponse { signature: String, password: Option<String> },
+    Moderation(Moderation),
+    GroupKey(KeyShare),
+    Nick(String), // vale per il membro dall'altra parte della connessione
+    Presence(Presence),
+    Typing, // effimero: solo notifica, mai nella Chat
+    Ack { message: String, read: bool },
+    Amend(Amendment),
+    React { message: String, emoji: String, add: bool }, // vale per il membro dall'altra parte della connessione
+    Rules { access: RoomAccess, retention: Retention, signed: RulesSignature }, // firmate dall'owner, dopo ogni modifica
 }
\ No newline at end of file
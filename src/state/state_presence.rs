@@ -0,0 +1,89 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+pub const AWAY_AFTER: Duration = Duration::from_secs(300);
+pub const IDLE_CHECK: Duration = Duration::from_secs(15);
+pub const MAX_STATUS_LEN: usize = 80;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+pub enum Status {
+    #[default]
+    Online,
+    Away,
+    Busy,
+}
+
+impl Status {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "online" => Some(Status::Online),
+            "away" => Some(Status::Away),
+            "busy" => Some(Status::Busy),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Status::Online => write!(f, "online"),
+            Status::Away => write!(f, "away"),
+            Status::Busy => write!(f, "busy"),
+        }
+    }
+}
+
+// Vale per il membro dall'altra parte della connessione, come Packet::Nick
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Presence {
+    pub status: Status,
+    pub message: Option<String>,
+}
+
+struct ActivityInner {
+    last_input: Instant,
+    auto_away: bool, // l'away l'ha messo il timer, quindi al primo input si torna online
+}
+
+// Attività locale per l'away automatico
+#[derive(Clone)]
+pub struct Activity {
+    inner: Arc<Mutex<ActivityInner>>,
+}
+
+impl Activity {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(ActivityInner {
+                last_input: Instant::now(),
+                auto_away: false,
+            })),
+        }
+    }
+
+    // Restituisce true se eravamo in away automatico
+    pub async fn touch(&self) -> bool {
+        let mut inner = self.inner.lock().await;
+        inner.last_input = Instant::now();
+        std::mem::replace(&mut inner.auto_away, false)
+    }
+
+    // true una sola volta, quando si supera AWAY_AFTER senza input
+    pub async fn went_idle(&self) -> bool {
+        let mut inner = self.inner.lock().await;
+        if inner.auto_away || inner.last_input.elapsed() < AWAY_AFTER {
+            return false;
+        }
+
+        inner.auto_away = true;
+        true
+    }
+
+    pub async fn clear_auto_away(&self) {
+        self.inner.lock().await.auto_away = false;
+    }
+}
@@ -0,0 +1,31 @@
+pub const MAX_REACTION_LEN: usize = 8;
+
+// Scorciatoie per chi non ha un modo comodo di scrivere emoji nel terminale
+const SHORTCODES: &[(&str, &str)] = &[
+    ("+1", "👍"),
+    ("thumbsup", "👍"),
+    ("-1", "👎"),
+    ("heart", "❤️"),
+    ("laugh", "😂"),
+    ("wow", "😮"),
+    ("sad", "😢"),
+    ("tada", "🎉"),
+    ("eyes", "👀"),
+    ("check", "✅"),
+];
+
+// ":+1:", "+1" o direttamente l'emoji; niente spazi né testo lungo, è una reazione e non un messaggio
+pub fn parse_reaction(input: &str) -> Option<String> {
+    let code = input.trim_matches(':');
+    if let Some((_, emoji)) = SHORTCODES.iter().find(|(c, _)| *c == code) {
+        return Some(emoji.to_string());
+    }
+
+    valid_reaction(input).then(|| input.to_string())
+}
+
+pub fn valid_reaction(emoji: &str) -> bool {
+    !emoji.is_empty()
+        && emoji.chars().count() <= MAX_REACTION_LEN
+        && !emoji.chars().any(|c| c.is_whitespace() || c.is_control() || c.is_ascii_alphanumeric())
+}
@@ -0,0 +1,36 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+// Chi ha ricevuto e chi ha letto uno dei nostri messaggi, per id del membro
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Receipt {
+    pub delivered: HashSet<String>,
+    pub read: HashSet<String>,
+}
+
+// Messaggi ricevuti ma non ancora letti: la conferma di lettura parte al prossimo input
+#[derive(Clone)]
+pub struct Unread {
+    pending: Arc<Mutex<Vec<(String, String)>>>, // (id del messaggio, id del mittente)
+}
+
+impl Unread {
+    pub fn new() -> Self {
+        Self {
+            pending: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub async fn push(&self, message_id: &str, sender_id: &str) {
+        self.pending
+            .lock()
+            .await
+            .push((message_id.to_string(), sender_id.to_string()));
+    }
+
+    pub async fn take(&self) -> Vec<(String, String)> {
+        std::mem::take(&mut *self.pending.lock().await)
+    }
+}
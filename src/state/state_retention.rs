@@ -0,0 +1,80 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+
+pub const RETENTION_CHECK: Duration = Duration::from_secs(1);
+
+// Quanta storia tiene la stanza. La decide l'owner, viaggia firmata nel Sync e in Packet::Rules
+// e ogni membro la applica alla propria copia; ttl attiva i messaggi che spariscono da soli
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Retention {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_age: Option<u64>, // secondi
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_count: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_bytes: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u64>, // secondi, scritto come scadenza in ogni messaggio nuovo
+}
+
+impl Retention {
+    pub fn is_unlimited(&self) -> bool {
+        *self == Retention::default()
+    }
+
+    // Scadenza da mettere in un messaggio scritto ora
+    pub fn expires(&self, timestamp: u64) -> Option<u64> {
+        self.ttl.map(|ttl| timestamp.saturating_add(ttl))
+    }
+}
+
+impl fmt::Display for Retention {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_unlimited() {
+            return write!(f, "history kept forever");
+        }
+
+        let mut rules = vec![];
+        if let Some(age) = self.max_age {
+            rules.push(format!("max age {}", format_duration(age)));
+        }
+        if let Some(count) = self.max_count {
+            rules.push(format!("last {} messages", count));
+        }
+        if let Some(bytes) = self.max_bytes {
+            rules.push(format!("max {}", format_size(bytes)));
+        }
+        if let Some(ttl) = self.ttl {
+            rules.push(format!("messages disappear after {}", format_duration(ttl)));
+        }
+        write!(f, "{}", rules.join(", "))
+    }
+}
+
+// 500, 64k, 2M
+pub fn parse_size(s: &str) -> Option<usize> {
+    let (num, mult) = match s.chars().last()?.to_ascii_lowercase() {
+        'k' => (&s[..s.len() - 1], 1024),
+        'm' => (&s[..s.len() - 1], 1024 * 1024),
+        _ => (s, 1),
+    };
+    num.parse::<usize>().ok()?.checked_mul(mult)
+}
+
+fn format_size(bytes: usize) -> String {
+    match bytes {
+        b if b >= 1024 * 1024 && b % (1024 * 1024) == 0 => format!("{}M", b / (1024 * 1024)),
+        b if b >= 1024 && b % 1024 == 0 => format!("{}k", b / 1024),
+        b => format!("{} bytes", b),
+    }
+}
+
+fn format_duration(secs: u64) -> String {
+    match secs {
+        s if s >= 86400 && s % 86400 == 0 => format!("{}d", s / 86400),
+        s if s >= 3600 && s % 3600 == 0 => format!("{}h", s / 3600),
+        s if s >= 60 && s % 60 == 0 => format!("{}m", s / 60),
+        s => format!("{}s", s),
+    }
+}
@@ -0,0 +1,360 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+pub const MAX_RESULTS: usize = 30;
+
+// Filtri di /search: parole (tutte, anche come inizio di parola), mittente e intervallo di tempo
+#[derive(Default, Debug)]
+pub struct Query {
+    pub words: Vec<String>,
+    pub from: Option<String>,
+    pub after: Option<u64>,
+    pub before: Option<u64>,
+}
+
+impl Query {
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty() && self.from.is_none() && self.after.is_none() && self.before.is_none()
+    }
+}
+
+// Testo in chiaro di un messaggio già decifrato, come lo vede questo nodo
+pub struct Indexed {
+    pub id: String,
+    pub sender_id: Option<String>,
+    pub sender: String, // nome al momento dell'invio, per i messaggi senza id
+    pub timestamp: u64,
+    pub text: String,
+    pub version: Option<u64>, // timestamp dell'ultima modifica: se cambia si reindicizza
+    pub readable: bool,
+}
+
+// Il mittente si tiene per id: il nome attuale si guarda al momento della ricerca, così
+// from: trova anche i messaggi scritti prima di un /nick
+struct Entry {
+    sender_id: Option<String>,
+    sender: String,
+    timestamp: u64,
+    words: Vec<String>,
+    version: Option<u64>,
+    readable: bool,
+}
+
+struct SearchInner {
+    entries: HashMap<String, Entry>,
+    words: BTreeMap<String, HashSet<String>>, // parola -> id dei messaggi, ordinate per cercare i prefissi
+    by_time: BTreeSet<(u64, String)>,
+}
+
+// Indice locale della storia: i messaggi nella Chat sono cifrati, quindi si decifrano una volta sola
+// quando entrano nell'indice e non a ogni ricerca
+#[derive(Clone)]
+pub struct SearchIndex {
+    inner: Arc<Mutex<SearchInner>>,
+}
+
+impl SearchIndex {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(SearchInner {
+                entries: HashMap::new(),
+                words: BTreeMap::new(),
+                by_time: BTreeSet::new(),
+            })),
+        }
+    }
+
+    // Cosa manca o è cambiato rispetto alla storia attuale; i messaggi illeggibili si riprovano,
+    // la chiave può arrivare dopo
+    pub async fn stale(&self, current: &[(String, Option<u64>)]) -> Vec<String> {
+        let inner = self.inner.lock().await;
+        current
+            .iter()
+            .filter(|(id, version)| {
+                inner
+                    .entries
+                    .get(id)
+                    .is_none_or(|e| !e.readable || e.version != *version)
+            })
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+
+    pub async fn insert(&self, message: Indexed) {
+        let mut inner = self.inner.lock().await;
+        inner.remove(&message.id);
+
+        let words = if message.readable { tokenize(&message.text) } else { vec![] };
+        for word in &words {
+            inner.words.entry(word.clone()).or_default().insert(message.id.clone());
+        }
+        inner.by_time.insert((message.timestamp, message.id.clone()));
+        inner.entries.insert(
+            message.id,
+            Entry {
+                sender_id: message.sender_id,
+                sender: message.sender.to_lowercase(),
+                timestamp: message.timestamp,
+                words,
+                version: message.version,
+                readable: message.readable,
+            },
+        );
+    }
+
+    // Via quello che non è più nella storia (Sync da un altro peer, messaggi scaduti...)
+    pub async fn retain(&self, ids: &HashSet<&str>) {
+        let mut inner = self.inner.lock().await;
+        let gone: Vec<String> = inner
+            .entries
+            .keys()
+            .filter(|id| !ids.contains(id.as_str()))
+            .cloned()
+            .collect();
+
+        for id in gone {
+            inner.remove(&id);
+        }
+    }
+
+    // Id dei risultati dal più vecchio al più recente, al massimo gli ultimi MAX_RESULTS.
+    // names: id del membro -> nome attuale in minuscolo
+    pub async fn search(&self, query: &Query, names: &HashMap<String, String>) -> Vec<String> {
+        let inner = self.inner.lock().await;
+
+        let mut candidates: Option<HashSet<&String>> = None;
+        for word in &query.words {
+            let matches: HashSet<&String> = inner
+                .words
+                .range(word.clone()..)
+                .take_while(|(w, _)| w.starts_with(word.as_str()))
+                .flat_map(|(_, ids)| ids.iter())
+                .collect();
+
+            candidates = Some(match candidates {
+                Some(c) => c.intersection(&matches).copied().collect(),
+                None => matches,
+            });
+        }
+
+        let after = query.after.unwrap_or(0);
+        let before = query.before.unwrap_or(u64::MAX);
+        let from = query.from.as_ref().map(|f| f.to_lowercase());
+
+        let mut results: Vec<String> = inner
+            .by_time
+            .range((after, String::new())..)
+            .take_while(|(ts, _)| *ts < before)
+            .map(|(_, id)| id)
+            .filter(|id| candidates.as_ref().is_none_or(|c| c.contains(id)))
+            .filter(|id| {
+                from.as_ref().is_none_or(|f| {
+                    inner.entries.get(*id).is_some_and(|e| {
+                        let name = e.sender_id.as_ref().and_then(|s| names.get(s)).unwrap_or(&e.sender);
+                        name.starts_with(f.as_str())
+                    })
+                })
+            })
+            .cloned()
+            .collect();
+
+        let start = results.len().saturating_sub(MAX_RESULTS);
+        results.split_off(start)
+    }
+}
+
+impl SearchInner {
+    fn remove(&mut self, id: &str) {
+        let Some(entry) = self.entries.remove(id) else {
+            return;
+        };
+
+        for word in entry.words {
+            if let Some(ids) = self.words.get_mut(&word) {
+                ids.remove(id);
+                if ids.is_empty() {
+                    self.words.remove(&word);
+                }
+            }
+        }
+        self.by_time.remove(&(entry.timestamp, id.to_string()));
+    }
+}
+
+pub fn tokenize(text: &str) -> Vec<String> {
+    let mut words: Vec<String> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    words.sort();
+    words.dedup();
+    words
+}
+
+// AAAA-MM-GG (UTC) -> secondi dall'epoch, a mezzanotte
+pub fn parse_date(s: &str) -> Option<u64> {
+    let mut parts = s.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: u32 = parts.next()?.parse().ok()?;
+    let day: u32 = parts.next()?.parse().ok()?;
+
+    // anni assurdi farebbero traboccare i conti sui giorni
+    if !(1970..=9999).contains(&year) || !(1..=12).contains(&month) || !(1..=days_in_month(year, month)).contains(&day) {
+        return None;
+    }
+
+    let days = days_from_civil(year, month, day);
+    u64::try_from(days).ok()?.checked_mul(86400)
+}
+
+// secondi dall'epoch -> "AAAA-MM-GG hh:mm" (UTC)
+pub fn format_time(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64;
+    let secs = timestamp % 86400;
+    let (year, month, day) = civil_from_days(days);
+    format!("{:04}-{:02}-{:02} {:02}:{:02}", year, month, day, secs / 3600, secs % 3600 / 60)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Algoritmi di Howard Hinnant per il calendario gregoriano, senza dipendenze
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let m = month as i64;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn indexed(id: &str, sender_id: &str, sender: &str, timestamp: u64, text: &str) -> Indexed {
+        Indexed {
+            id: id.to_string(),
+            sender_id: Some(sender_id.to_string()),
+            sender: sender.to_string(),
+            timestamp,
+            text: text.to_string(),
+            version: None,
+            readable: true,
+        }
+    }
+
+    async fn index() -> SearchIndex {
+        let index = SearchIndex::new();
+        index.insert(indexed("m1", "a", "alice", 100, "Deploy the relay tonight")).await;
+        index.insert(indexed("m2", "b", "bob", 200, "relay is down, again!")).await;
+        index.insert(indexed("m3", "a", "alice", 300, "fixed the relayed path")).await;
+        index
+    }
+
+    fn names() -> HashMap<String, String> {
+        HashMap::from([("a".to_string(), "alice".to_string()), ("b".to_string(), "bob".to_string())])
+    }
+
+    #[test]
+    fn tokenize_lowercases_and_dedups() {
+        assert_eq!(tokenize("Hello, hello WORLD! ciao-ciao"), vec!["ciao", "hello", "world"]);
+        assert!(tokenize(" ...!? ").is_empty());
+    }
+
+    #[test]
+    fn parse_date_checks_the_calendar() {
+        assert_eq!(parse_date("1970-01-01"), Some(0));
+        assert_eq!(parse_date("2024-02-29"), Some(1709164800));
+        assert_eq!(parse_date("2023-02-29"), None);
+        assert_eq!(parse_date("2024-13-01"), None);
+        assert_eq!(parse_date("2024-01"), None);
+        assert_eq!(parse_date("1969-12-31"), None);
+        assert_eq!(parse_date("9223372036854775807-01-01"), None);
+    }
+
+    #[test]
+    fn format_time_roundtrips_parse_date() {
+        assert_eq!(format_time(parse_date("2024-02-29").unwrap() + 3661), "2024-02-29 01:01");
+    }
+
+    #[tokio::test]
+    async fn search_matches_word_prefixes() {
+        let index = index().await;
+        let query = Query {
+            words: vec!["relay".to_string()],
+            ..Query::default()
+        };
+        assert_eq!(index.search(&query, &names()).await, vec!["m1", "m2", "m3"]);
+
+        let query = Query {
+            words: vec!["relay".to_string(), "fix".to_string()],
+            ..Query::default()
+        };
+        assert_eq!(index.search(&query, &names()).await, vec!["m3"]);
+    }
+
+    #[tokio::test]
+    async fn search_filters_by_time() {
+        let index = index().await;
+        let query = Query {
+            after: Some(150),
+            before: Some(300),
+            ..Query::default()
+        };
+        assert_eq!(index.search(&query, &names()).await, vec!["m2"]);
+    }
+
+    #[tokio::test]
+    async fn search_from_uses_the_current_name() {
+        let index = index().await;
+        let query = Query {
+            from: Some("Alicia".to_string()),
+            ..Query::default()
+        };
+        assert!(index.search(&query, &names()).await.is_empty());
+
+        // dopo /nick alicia i messaggi vecchi seguono il nuovo nome
+        let mut renamed = names();
+        renamed.insert("a".to_string(), "alicia".to_string());
+        assert_eq!(index.search(&query, &renamed).await, vec!["m1", "m3"]);
+    }
+
+    #[tokio::test]
+    async fn reindexing_replaces_the_old_words() {
+        let index = index().await;
+        let mut edited = indexed("m2", "b", "bob", 200, "all good now");
+        edited.version = Some(250);
+        index.insert(edited).await;
+
+        let query = Query {
+            words: vec!["down".to_string()],
+            ..Query::default()
+        };
+        assert!(index.search(&query, &names()).await.is_empty());
+        assert!(index.stale(&[("m2".to_string(), Some(250))]).await.is_empty());
+        assert_eq!(index.stale(&[("m2".to_string(), None)]).await, vec!["m2"]);
+    }
+}
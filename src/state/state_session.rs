@@ -0,0 +1,66 @@
+use std::net::SocketAddr;
+
+use rand::random;
+
+use crate::state::state_chat::Member;
+
+// Stato di una singola connessione, vive quanto listen_main
+pub struct Session {
+    pub peer_addr: Option<SocketAddr>,
+    pub remote_id: Option<String>, // per sapere chi devo rimuovere quando qualcuno si disconnette
+    pub remote_key: Option<String>,
+    pub expected_fingerprint: Option<String>, // arrivati con un invito: la chiave dell'host deve corrispondere
+    pub expected_owner: Option<String>, // impronta dell'owner scritta nell'invito
+    pub invite_secret: Option<String>, // parte con InitSyncRequest solo dopo che l'host ha firmato il nonce
+    pub challenge: Option<String>,
+    pub verified: bool, // il peer ha firmato il nostro nonce: remote_id e remote_key sono davvero suoi
+    pub joining: bool, // abbiamo chiesto la chat: le chiavi che arrivano sostituiscono le nostre
+    pub sync_requested: bool, // il Sync che arriva risponde al nostro InitSyncRequest
+    pub incoming: bool,
+    pub authorized: bool, // le connessioni in ingresso ricevono la chat solo dopo i controlli della stanza
+    pub access_challenge: Option<String>,
+    pub pending_member: Option<Member>, // entra nella chat quando supera i controlli
+    pub pending_sync: bool, // InitSyncRequest arrivato prima dell'autorizzazione
+    pub invited: bool, // il peer ha riscattato un invito valido
+    pub close: bool,
+}
+
+impl Session {
+    // Connessione aperta da noi: il peer l'abbiamo scelto, niente controlli
+    pub fn new(peer_addr: Option<SocketAddr>) -> Self {
+        Self {
+            peer_addr,
+            remote_id: None,
+            remote_key: None,
+            expected_fingerprint: None,
+            expected_owner: None,
+            invite_secret: None,
+            challenge: None,
+            verified: false,
+            joining: false,
+            sync_requested: false,
+            incoming: false,
+            authorized: true,
+            access_challenge: None,
+            pending_member: None,
+            pending_sync: false,
+            invited: false,
+            close: false,
+        }
+    }
+
+    // Nonce che il peer deve firmare con la chiave della sua Identity (Challenge -> ChallengeRes)
+    pub fn new_challenge(&mut self) -> String {
+        let nonce = hex::encode(random::<[u8; 16]>());
+        self.challenge = Some(nonce.clone());
+        nonce
+    }
+
+    pub fn incoming(peer_addr: Option<SocketAddr>) -> Self {
+        Self {
+            incoming: true,
+            authorized: false,
+            ..Self::new(peer_addr)
+        }
+    }
+}
@@ -0,0 +1,36 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+pub const TYPING_INTERVAL: Duration = Duration::from_secs(3); // al massimo un Packet::Typing ogni tanto
+pub const TYPING_TIMEOUT: Duration = Duration::from_secs(6); // senza altri Typing si considera finito
+
+// Chi sta scrivendo, solo per l'output: non entra mai nella Chat né nel Sync
+#[derive(Clone)]
+pub struct TypingPeers {
+    peers: Arc<Mutex<HashMap<String, Instant>>>,
+}
+
+impl TypingPeers {
+    pub fn new() -> Self {
+        Self {
+            peers: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    // true se prima non stava scrivendo, cioè se va mostrato l'avviso
+    pub async fn started(&self, id: &str) -> bool {
+        let mut peers = self.peers.lock().await;
+        let now = Instant::now();
+
+        match peers.insert(id.to_string(), now) {
+            Some(last) => now.duration_since(last) > TYPING_TIMEOUT,
+            None => true,
+        }
+    }
+
+    pub async fn stopped(&self, id: &str) {
+        self.peers.lock().await.remove(id);
+    }
+}
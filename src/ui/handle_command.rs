@@ -0,0 +1,801 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use tokio::sync::Mutex;
+
+use crate::crypto::identity::fingerprint;
+use crate::handler::handle_moderation::apply_moderation;
+use crate::handler::handle_presence::set_presence;
+use crate::handler::handle_retention::set_retention;
+use crate::handler::handle_rules::share_rules;
+use crate::network::connection::connection_main;
+use crate::network::interfaces::format_addrs;
+use crate::state::state_access::{ModAction, Moderation};
+use crate::state::state_amendment::{AmendAction, Amendment};
+use crate::state::state_chat::{Chat, Connections, Member, validate_username};
+use crate::state::state_discovery::{DiscoveredPeer, DiscoveredPeers};
+use crate::state::state_invite::Invite;
+use crate::state::state_keyring::UNREADABLE;
+use crate::state::state_node::Node;
+use crate::state::state_packets::Packet;
+use crate::state::state_presence::{MAX_STATUS_LEN, Presence, Status};
+use crate::state::state_reactions::parse_reaction;
+use crate::state::state_retention::parse_size;
+use crate::state::state_search::{Indexed, Query, format_time, parse_date, tokenize};
+use crate::ui::handle_export::{ExportFormat, default_path, export, import};
+use crate::ui::handle_format::sanitize_line;
+use crate::ui::handle_input::{get_timestamp, send_message};
+use crate::ui::handle_output::{print_all_messages, print_message, print_notice, say, thread_views, view};
+
+// Una sola tabella per l'elenco dei comandi interni e per il dispatch: un comando nuovo
+// va aggiunto qui e il match di handle_command non compila finché non lo gestisce
+macro_rules! builtins {
+    ($($variant:ident => $name:literal),* $(,)?) => {
+        #[derive(Clone, Copy)]
+        enum Builtin {
+            $($variant),*
+        }
+
+        // I comandi interni; i plugin non possono registrarne uno con lo stesso nome
+        pub const COMMANDS: &[&str] = &[$($name),*];
+
+        const BUILTINS: &[(&str, Builtin)] = &[$(($name, Builtin::$variant)),*];
+    };
+}
+
+builtins! {
+    Discover => "/discover",
+    Join => "/join",
+    Invite => "/invite",
+    History => "/history",
+    Search => "/search",
+    Context => "/context",
+    Mentions => "/mentions",
+    Export => "/export",
+    Import => "/import",
+    Retention => "/retention",
+    Format => "/format",
+    Reply => "/reply",
+    React => "/react",
+    Edit => "/edit",
+    Delete => "/delete",
+    Members => "/members",
+    Status => "/status",
+    Nick => "/nick",
+    Kick => "/kick",
+    Ban => "/ban",
+}
+
+pub async fn handle_command(
+    line: &str,
+    chat: &Arc<Mutex<Chat>>,
+    member: &Arc<Member>,
+    node: &Node,
+    connections: &Connections,
+    discovered: &DiscoveredPeers,
+) {
+    let mut parts = line.split_whitespace();
+    let command = parts.next().unwrap_or("");
+    let arg = parts.next();
+    let builtin = BUILTINS.iter().find(|(name, _)| *name == command).map(|(_, b)| *b);
+
+    match builtin {
+        Some(Builtin::Discover) => print_discovered(chat, discovered).await,
+        Some(Builtin::Join) => match arg {
+            Some(arg) => join(arg, chat, member, node, connections, discovered).await,
+            None => say!("Usage: /join <number|username|id>"),
+        },
+        Some(Builtin::Invite) => invite(line, chat, member, node).await,
+        Some(Builtin::Members) => print_members(chat, member).await,
+        Some(Builtin::Reply) => match (arg, rest(line, 2)) {
+            (Some(reference), Some(text)) => match node.handles.resolve(reference).await {
+                Some(id) => {
+                    send_message(text.to_string(), Some(id), chat, member, node, connections).await;
+                }
+                None => say!("No message #{}, see /history", reference.trim_start_matches('#')),
+            },
+            _ => say!("Usage: /reply <#n> <text>"),
+        },
+        Some(Builtin::React) => match (arg, parts.next().and_then(parse_reaction)) {
+            (Some(reference), Some(emoji)) => react(reference, emoji, chat, member, node, connections).await,
+            _ => say!("Usage: /react <#n> <emoji|+1|heart|laugh|tada|...>"),
+        },
+        Some(Builtin::Edit) => {
+            // il riferimento è facoltativo: senza si modifica l'ultimo messaggio
+            let (reference, text) = match arg {
+                Some(a) if a.starts_with('#') => (Some(a), rest(line, 2)),
+                _ => (None, rest(line, 1)),
+            };
+
+            match text {
+                Some(text) => amend(reference, Some(text), chat, member, node, connections).await,
+                None => say!("Usage: /edit [#n] <new text>"),
+            }
+        }
+        Some(Builtin::Delete) => amend(arg, None, chat, member, node, connections).await,
+        Some(Builtin::History) => match arg.map(str::parse::<usize>) {
+            None => print_history(20, chat, member, node).await,
+            Some(Ok(n)) => print_history(n, chat, member, node).await,
+            Some(Err(_)) => say!("Usage: /history [count]"),
+        },
+        Some(Builtin::Mentions) => match arg.map(str::parse::<usize>) {
+            None => print_mentions(20, chat, member, node).await,
+            Some(Ok(n)) => print_mentions(n, chat, member, node).await,
+            Some(Err(_)) => say!("Usage: /mentions [count]"),
+        },
+        Some(Builtin::Search) => match parse_query(line.split_whitespace().skip(1)) {
+            Ok(query) if !query.is_empty() => search(&query, chat, member, node).await,
+            Ok(_) => say!("Usage: /search [words] [from:name] [after:YYYY-MM-DD] [before:YYYY-MM-DD] [since:2h]"),
+            Err(e) => say!("Invalid search: {}", e),
+        },
+        Some(Builtin::Context) => match (arg, parts.next().map(str::parse::<usize>)) {
+            (Some(reference), None) => print_context(reference, 5, chat, member, node).await,
+            (Some(reference), Some(Ok(n))) => print_context(reference, n, chat, member, node).await,
+            _ => say!("Usage: /context <#n> [count]"),
+        },
+        Some(Builtin::Export) => match (arg.and_then(ExportFormat::parse), rest(line, 2)) {
+            (Some(format), path) => {
+                let path = match path {
+                    Some(path) => PathBuf::from(path),
+                    None => default_path(&chat.lock().await.room, format),
+                };
+                if let Err(e) = export(format, &path, chat, node).await {
+                    say!("Export failed: {}", e);
+                }
+            }
+            _ => say!("Usage: /export <json|text|html> [path]"),
+        },
+        Some(Builtin::Import) => match rest(line, 1) {
+            Some(path) => match import(Path::new(path), chat, node).await {
+                Ok(added) => print_notice(&format!("Imported {} new message(s), kept only on this node, see /history", added)),
+                Err(e) => say!("Import failed: {}", e),
+            },
+            None => say!("Usage: /import <file.json>"),
+        },
+        Some(Builtin::Retention) => match (arg, parts.next()) {
+            (None, _) => print_notice(&format!("Retention: {}", chat.lock().await.retention)),
+            (Some(rule), Some(value)) => retention(rule, value, chat, member, node, connections).await,
+            _ => say!("Usage: /retention [age|count|bytes|ttl <value|off>]"),
+        },
+        Some(Builtin::Format) => match arg {
+            Some("on") => {
+                node.formatting.set(true).await;
+                print_notice("Formatting on: **bold**, *italic*, `code`, [text](url)");
+            }
+            Some("off") => {
+                node.formatting.set(false).await;
+                print_notice("Formatting off");
+            }
+            _ => say!("Usage: /format <on|off>"),
+        },
+        Some(Builtin::Status) => match arg.and_then(Status::parse) {
+            Some(status) => {
+                let message = line.split_whitespace().skip(2).collect::<Vec<_>>().join(" ");
+                status_cmd(status, message, chat, member, node, connections).await
+            }
+            None => say!("Usage: /status <online|away|busy> [message]"),
+        },
+        Some(Builtin::Nick) => match arg {
+            Some(arg) => nick(arg, chat, member, node, connections).await,
+            None => say!("Usage: /nick <username>"),
+        },
+        Some(builtin @ (Builtin::Kick | Builtin::Ban)) => match arg {
+            Some(arg) => {
+                let action = if matches!(builtin, Builtin::Kick) { ModAction::Kick } else { ModAction::Ban };
+                moderate(action, arg, chat, member, node, connections).await
+            }
+            None => say!("Usage: {} <username|id>", command),
+        },
+        None => {
+            if !node.plugins.run_command(command, rest(line, 1).unwrap_or("")).await {
+                let mut known: Vec<String> = COMMANDS.iter().map(|c| c.to_string()).collect();
+                known.extend(node.plugins.usages().await);
+                say!("Unknown command {}. Commands: {}", command, known.join(", "));
+            }
+        }
+    }
+}
+
+// /invite [once] [durata]: senza opzioni il token vale finché l'host è raggiungibile.
+// Se sono già stati emessi inviti con segreto anche quelli senza opzioni ne hanno uno, riusabile
+async fn invite(line: &str, chat: &Arc<Mutex<Chat>>, member: &Arc<Member>, node: &Node) {
+    let mut one_time = false;
+    let mut ttl: Option<u64> = None;
+
+    for opt in line.split_whitespace().skip(1) {
+        if opt == "once" {
+            one_time = true;
+            continue;
+        }
+
+        match parse_duration(opt) {
+            Some(secs) => ttl = Some(secs),
+            None => {
+                say!("Usage: /invite [once] [ttl, e.g. 30m, 2h, 1d]");
+                return;
+            }
+        }
+    }
+
+    let expires = ttl.map(|t| get_timestamp() + t);
+    let secret = if one_time || expires.is_some() || node.invites.required() {
+        Some(node.invites.issue(expires, one_time).await)
+    } else {
+        None
+    };
+
+    let (room, owner) = {
+        let chat_lock = chat.lock().await;
+        (chat_lock.room.clone(), chat_lock.access.owner.as_deref().map(fingerprint))
+    };
+    let invite = Invite {
+        addrs: member.addrs.clone(),
+        room,
+        fingerprint: fingerprint(&member.pubkey),
+        owner,
+        expires,
+        secret,
+    };
+
+    say!("Invite for room {}:", sanitize_line(&invite.room));
+    say!("{}", invite.encode());
+    match (one_time, ttl) {
+        (true, Some(t)) => say!("Valid once, for {}s", t),
+        (true, None) => say!("Valid once"),
+        (false, Some(t)) => say!("Valid for {}s", t),
+        (false, None) => say!("Valid until you go offline"),
+    }
+    if invite.secret.is_some() {
+        say!("Only this node checks invites: other members still accept peers that connect to them directly");
+    }
+    say!("Join with: p2pchat --join <token>");
+}
+
+// Rimettere la stessa reazione la toglie
+async fn react(
+    reference: &str,
+    emoji: String,
+    chat: &Arc<Mutex<Chat>>,
+    member: &Arc<Member>,
+    node: &Node,
+    connections: &Connections,
+) {
+    let Some(id) = node.handles.resolve(reference).await else {
+        say!("No message #{}, see /history", reference.trim_start_matches('#'));
+        return;
+    };
+
+    let add = {
+        let mut chat_lock = chat.lock().await;
+        let Some(message) = chat_lock.all_messages.iter().find(|m| m.id == id) else {
+            say!("No message #{}, see /history", reference.trim_start_matches('#'));
+            return;
+        };
+
+        let add = !message.reactions.get(&emoji).is_some_and(|ids| ids.contains(&member.id));
+        chat_lock.react(&id, &member.id, &emoji, add);
+        add
+    };
+
+    {
+        let conns = connections.connections.lock().await;
+        for c in conns.iter() {
+            let _ = c.send(Packet::React {
+                message: id.clone(),
+                emoji: emoji.clone(),
+                add,
+            });
+        }
+    }
+
+    let handle = reference.trim_start_matches('#');
+    if add {
+        print_notice(&format!("You reacted {} to #{}", emoji, handle));
+    } else {
+        print_notice(&format!("Removed your {} from #{}", emoji, handle));
+    }
+}
+
+// Testo dopo i primi `skip` pezzi della riga, così com'è scritto
+fn rest(line: &str, skip: usize) -> Option<&str> {
+    let mut rest = line.trim_start();
+    for _ in 0..skip {
+        let end = rest.find(char::is_whitespace)?;
+        rest = rest[end..].trim_start();
+    }
+
+    if rest.is_empty() { None } else { Some(rest) }
+}
+
+// /edit e /delete agiscono sul messaggio indicato o sull'ultimo nostro non cancellato
+async fn amend(
+    reference: Option<&str>,
+    text: Option<&str>,
+    chat: &Arc<Mutex<Chat>>,
+    member: &Arc<Member>,
+    node: &Node,
+    connections: &Connections,
+) {
+    let id = match reference {
+        Some(reference) => match node.handles.resolve(reference).await {
+            Some(id) => Some(id),
+            None => {
+                say!("No message #{}, see /history", reference.trim_start_matches('#'));
+                return;
+            }
+        },
+        None => None,
+    };
+
+    let target = chat
+        .lock()
+        .await
+        .all_messages
+        .iter()
+        .rev()
+        .find(|m| {
+            m.sender_id.as_ref() == Some(&member.id)
+                && !m.amended.as_ref().is_some_and(|a| a.is_delete())
+                && id.as_ref().is_none_or(|id| *id == m.id)
+        })
+        .cloned();
+
+    let Some(target) = target else {
+        say!("You have no message to change");
+        return;
+    };
+
+    let action = match text {
+        Some(text) => {
+            let (text, key_id) = node.keyring.seal_edit(&target, text).await;
+            AmendAction::Edit { text, key_id }
+        }
+        None => AmendAction::Delete,
+    };
+
+    let mut amendment = Amendment {
+        message: target.id.clone(),
+        author: member.id.clone(),
+        action,
+        timestamp: get_timestamp(),
+        signature: String::new(),
+    };
+    amendment.signature = node.identity.sign(&amendment.payload());
+
+    let shown = {
+        let mut chat_lock = chat.lock().await;
+        if let Err(e) = chat_lock.amend(amendment.clone()) {
+            say!("Cannot change the message: {}", e);
+            return;
+        }
+
+        match chat_lock.all_messages.iter().find(|m| m.id == target.id) {
+            Some(message) => Some(view(message, &chat_lock, node, &member.id).await),
+            None => None,
+        }
+    };
+
+    {
+        let conns = connections.connections.lock().await;
+        for c in conns.iter() {
+            let _ = c.send(Packet::Amend(amendment.clone()));
+        }
+    }
+
+    if let Some(shown) = shown {
+        print_message(&shown);
+    }
+}
+
+// Ultimi messaggi, con accanto ai nostri quanti li hanno ricevuti e letti
+async fn print_history(count: usize, chat: &Arc<Mutex<Chat>>, member: &Arc<Member>, node: &Node) {
+    let chat_lock = chat.lock().await;
+    let start = chat_lock.all_messages.len().saturating_sub(count);
+
+    let views = thread_views(&chat_lock.all_messages[start..], &chat_lock, node, &member.id).await;
+    print_all_messages(views);
+}
+
+// Una regola alla volta, solo l'owner; "off" la toglie
+async fn retention(
+    rule: &str,
+    value: &str,
+    chat: &Arc<Mutex<Chat>>,
+    member: &Arc<Member>,
+    node: &Node,
+    connections: &Connections,
+) {
+    let mut retention = {
+        let chat_lock = chat.lock().await;
+        if chat_lock.access.owner.as_ref() != Some(&member.pubkey) {
+            say!("Only the room owner can change the retention");
+            return;
+        }
+        chat_lock.retention.clone()
+    };
+
+    let off = value == "off";
+    let duration = || parse_duration(value).filter(|secs| *secs > 0);
+    let valid = match rule {
+        "age" => {
+            retention.max_age = if off { None } else { duration() };
+            off || retention.max_age.is_some()
+        }
+        "count" => {
+            retention.max_count = if off { None } else { value.parse().ok().filter(|n| *n > 0) };
+            off || retention.max_count.is_some()
+        }
+        "bytes" => {
+            retention.max_bytes = if off { None } else { parse_size(value).filter(|n| *n > 0) };
+            off || retention.max_bytes.is_some()
+        }
+        "ttl" => {
+            retention.ttl = if off { None } else { duration() };
+            off || retention.ttl.is_some()
+        }
+        _ => false,
+    };
+
+    if !valid {
+        say!("Usage: /retention age 7d | count 500 | bytes 2M | ttl 30s, or <rule> off");
+        return;
+    }
+
+    set_retention(retention, chat, node, connections).await;
+}
+
+// from:nome, after:/before: con una data, since: con una durata come /invite; il resto sono parole
+fn parse_query<'a>(parts: impl Iterator<Item = &'a str>) -> Result<Query, String> {
+    let mut query = Query::default();
+
+    for part in parts {
+        match part.split_once(':') {
+            Some(("from", name)) if !name.is_empty() => query.from = Some(name.trim_start_matches('@').to_string()),
+            Some(("after", date)) => query.after = Some(parse_date(date).ok_or(format!("bad date {}", date))?),
+            // before: comprende tutto il giorno indicato
+            Some(("before", date)) => {
+                let day = parse_date(date).and_then(|d| d.checked_add(86400));
+                query.before = Some(day.ok_or(format!("bad date {}", date))?);
+            }
+            Some(("since", duration)) => {
+                let secs = parse_duration(duration).ok_or(format!("bad duration {}", duration))?;
+                query.after = Some(get_timestamp().saturating_sub(secs));
+            }
+            _ => query.words.extend(tokenize(part)),
+        }
+    }
+
+    Ok(query)
+}
+
+// Porta l'indice in pari con la storia: si decifrano solo i messaggi nuovi o modificati
+async fn refresh_index(chat: &Chat, node: &Node) {
+    let current: Vec<(String, Option<u64>)> = chat
+        .all_messages
+        .iter()
+        .map(|m| (m.id.clone(), m.amended.as_ref().map(|a| a.timestamp)))
+        .collect();
+
+    let stale: HashSet<String> = node.search.stale(&current).await.into_iter().collect();
+    for message in chat.all_messages.iter().filter(|m| stale.contains(&m.id)) {
+        let shown = node.keyring.reveal(message).await;
+
+        let deleted = message.amended.as_ref().is_some_and(|a| a.is_delete());
+        node.search
+            .insert(Indexed {
+                id: message.id.clone(),
+                sender_id: message.sender_id.clone(),
+                sender: message.sender.clone(),
+                timestamp: message.timestamp,
+                readable: shown.text != UNREADABLE,
+                text: if deleted { String::new() } else { shown.text },
+                version: message.amended.as_ref().map(|a| a.timestamp),
+            })
+            .await;
+    }
+
+    node.search
+        .retain(&current.iter().map(|(id, _)| id.as_str()).collect())
+        .await;
+}
+
+async fn search(query: &Query, chat: &Arc<Mutex<Chat>>, member: &Arc<Member>, node: &Node) {
+    let chat_lock = chat.lock().await;
+    refresh_index(&chat_lock, node).await;
+
+    let names: HashMap<String, String> = chat_lock
+        .members
+        .iter()
+        .map(|m| (m.id.clone(), chat_lock.display_name(Some(&m.id), &m.username).to_lowercase()))
+        .collect();
+    let ids = node.search.search(query, &names).await;
+    if ids.is_empty() {
+        say!("No messages found");
+        return;
+    }
+
+    // nell'ordine della storia: l'indice ordina per secondi e a pari secondo non sa chi viene prima
+    let ids: HashSet<String> = ids.into_iter().collect();
+    let mut views = vec![];
+    for message in chat_lock.all_messages.iter().filter(|m| ids.contains(&m.id)) {
+        let mut v = view(message, &chat_lock, node, &member.id).await;
+        v.time = Some(format_time(message.timestamp));
+        views.push(v);
+    }
+
+    say!("{} result(s), /context <#n> to see the conversation around one:", views.len());
+    print_all_messages(views);
+}
+
+// I count messaggi prima e dopo quello indicato, con i thread come in /history
+async fn print_context(reference: &str, count: usize, chat: &Arc<Mutex<Chat>>, member: &Arc<Member>, node: &Node) {
+    let Some(id) = node.handles.resolve(reference).await else {
+        say!("No message #{}, see /history", reference.trim_start_matches('#'));
+        return;
+    };
+
+    let chat_lock = chat.lock().await;
+    let Some(i) = chat_lock.all_messages.iter().position(|m| m.id == id) else {
+        say!("Message #{} is no longer in the history", reference.trim_start_matches('#'));
+        return;
+    };
+
+    let start = i.saturating_sub(count);
+    let end = (i + count + 1).min(chat_lock.all_messages.len());
+    let views = thread_views(&chat_lock.all_messages[start..end], &chat_lock, node, &member.id).await;
+    print_all_messages(views);
+}
+
+// Solo i messaggi in cui qualcuno ci ha chiamato, gli ultimi count, senza ricostruire i thread
+async fn print_mentions(count: usize, chat: &Arc<Mutex<Chat>>, member: &Arc<Member>, node: &Node) {
+    let chat_lock = chat.lock().await;
+
+    let mut views = vec![];
+    for message in chat_lock.all_messages.iter() {
+        let v = view(message, &chat_lock, node, &member.id).await;
+        if v.mentioned {
+            views.push(v);
+        }
+    }
+
+    if views.is_empty() {
+        say!("Nobody mentioned you yet");
+        return;
+    }
+
+    let start = views.len().saturating_sub(count);
+    print_all_messages(views.split_off(start));
+}
+
+async fn print_members(chat: &Arc<Mutex<Chat>>, member: &Arc<Member>) {
+    let chat_lock = chat.lock().await;
+    say!("Members of {}:", sanitize_line(&chat_lock.room));
+
+    for m in chat_lock.members.iter() {
+        let mut tags = vec![];
+        if m.id == member.id {
+            tags.push("you");
+        }
+        if chat_lock.access.owner.as_ref() == Some(&m.pubkey) {
+            tags.push("owner");
+        }
+        let tags = if tags.is_empty() {
+            String::new()
+        } else {
+            format!(" ({})", tags.join(", "))
+        };
+
+        let status = match &m.status_message {
+            Some(message) => format!("{}: {}", m.status, sanitize_line(message)),
+            None => m.status.to_string(),
+        };
+
+        say!(
+            "  {}{} - {}",
+            sanitize_line(&chat_lock.display_name(Some(&m.id), &m.username)),
+            tags,
+            status
+        );
+    }
+}
+
+async fn status_cmd(
+    status: Status,
+    message: String,
+    chat: &Arc<Mutex<Chat>>,
+    member: &Arc<Member>,
+    node: &Node,
+    connections: &Connections,
+) {
+    if message.chars().count() > MAX_STATUS_LEN {
+        say!("Status messages are limited to {} characters", MAX_STATUS_LEN);
+        return;
+    }
+
+    let message = if message.is_empty() { None } else { Some(message) };
+    node.activity.clear_auto_away().await; // scelto a mano: l'input successivo non lo deve cambiare
+    set_presence(Presence { status, message }, chat, member, connections).await;
+    print_notice(&format!("You are now {}", status));
+}
+
+async fn nick(username: &str, chat: &Arc<Mutex<Chat>>, member: &Arc<Member>, node: &Node, connections: &Connections) {
+    if let Err(reason) = validate_username(username) {
+        say!("Invalid username: {}", reason);
+        return;
+    }
+
+    let shown = {
+        let mut chat_lock = chat.lock().await;
+        chat_lock.rename(&member.id, username);
+        chat_lock.display_name(Some(&member.id), username)
+    };
+    node.plugins.set_username(username);
+
+    {
+        let conns = connections.connections.lock().await;
+        for c in conns.iter() {
+            let _ = c.send(Packet::Nick(username.to_string()));
+        }
+    }
+
+    print_notice(&format!("You are now known as {}", shown));
+}
+
+// Solo l'owner firma: gli altri membri controllano la firma con la chiave in RoomAccess.owner
+async fn moderate(
+    action: ModAction,
+    arg: &str,
+    chat: &Arc<Mutex<Chat>>,
+    member: &Arc<Member>,
+    node: &Node,
+    connections: &Connections,
+) {
+    let (room, target) = {
+        let chat_lock = chat.lock().await;
+
+        if chat_lock.access.owner.as_ref() != Some(&member.pubkey) {
+            say!("Only the room owner can kick or ban");
+            return;
+        }
+
+        let matches: Vec<Arc<Member>> = chat_lock
+            .members
+            .iter()
+            .filter(|m| {
+                m.id != member.id
+                    && (m.username == arg
+                        || m.id.starts_with(arg)
+                        || chat_lock.display_name(Some(&m.id), &m.username) == arg)
+            })
+            .cloned()
+            .collect();
+
+        // con un prefisso corto si rischia di colpire la persona sbagliata
+        if matches.len() > 1 {
+            let names: Vec<String> = matches
+                .iter()
+                .map(|m| format!("{} ({})", chat_lock.display_name(Some(&m.id), &m.username), m.id))
+                .collect();
+            say!("{} is ambiguous: {}", arg, names.join(", "));
+            return;
+        }
+
+        (chat_lock.room.clone(), matches.into_iter().next())
+    };
+
+    let Some(target) = target else {
+        say!("No member matches {}", arg);
+        return;
+    };
+
+    let mut moderation = Moderation {
+        action,
+        room,
+        target: target.id.clone(),
+        target_key: fingerprint(&target.pubkey),
+        timestamp: get_timestamp(),
+        signature: String::new(),
+    };
+    moderation.signature = node.identity.sign(&moderation.payload());
+
+    {
+        let conns = connections.connections.lock().await;
+        for c in conns.iter() {
+            let _ = c.send(Packet::Moderation(moderation.clone()));
+        }
+    }
+
+    apply_moderation(moderation, chat, member, node, connections, false).await;
+
+    // il ban cambia le regole della stanza
+    if action == ModAction::Ban {
+        share_rules(chat, node, connections).await;
+    }
+}
+
+// Durate nel formato 90, 90s, 30m, 2h, 1d
+pub fn parse_duration(s: &str) -> Option<u64> {
+    let (num, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, "s"),
+    };
+
+    let n: u64 = num.parse().ok()?;
+    let mult = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => return None,
+    };
+
+    n.checked_mul(mult)
+}
+
+async fn print_discovered(chat: &Arc<Mutex<Chat>>, discovered: &DiscoveredPeers) {
+    let peers = discovered.list().await;
+    if peers.is_empty() {
+        say!("No peers found on the LAN yet");
+        return;
+    }
+
+    let chat_lock = chat.lock().await;
+    say!("Peers on the LAN:");
+    for (i, p) in peers.iter().enumerate() {
+        let connected = if chat_lock.members.iter().any(|m| m.id == p.id) {
+            " (connected)"
+        } else {
+            ""
+        };
+
+        say!(
+            "  [{}] {} in room {}{}, seen {}s ago - {}",
+            i + 1,
+            sanitize_line(&p.username),
+            sanitize_line(&p.room),
+            connected,
+            p.last_seen.elapsed().as_secs(),
+            format_addrs(&p.addrs)
+        );
+    }
+}
+
+async fn join(
+    arg: &str,
+    chat: &Arc<Mutex<Chat>>,
+    member: &Arc<Member>,
+    node: &Node,
+    connections: &Connections,
+    discovered: &DiscoveredPeers,
+) {
+    let peers = discovered.list().await;
+    let Some(peer) = find_peer(&peers, arg) else {
+        say!("No discovered peer matches {}, try /discover", arg);
+        return;
+    };
+
+    say!("Joining {} in room {}...", sanitize_line(&peer.username), sanitize_line(&peer.room));
+
+    let chat = Arc::clone(chat);
+    let member = Arc::clone(member);
+    let node = node.clone();
+    let connections = connections.clone();
+    tokio::spawn(async move {
+        if let Err(e) = connection_main(peer.addrs.clone(), member, chat, node, connections).await {
+            say!("Error connecting to {}: {}", sanitize_line(&peer.username), e);
+        }
+    });
+}
+
+// Accetta l'indice mostrato da /discover, lo username o un prefisso dell'id
+fn find_peer(peers: &[DiscoveredPeer], arg: &str) -> Option<DiscoveredPeer> {
+    if let Ok(i) = arg.parse::<usize>() {
+        return peers.get(i.checked_sub(1)?).cloned();
+    }
+
+    peers
+        .iter()
+        .find(|p| p.username == arg || p.id.starts_with(arg))
+        .cloned()
+}
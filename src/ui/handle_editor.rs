@@ -0,0 +1,62 @@
+use std::sync::Arc;
+
+use rustyline::completion::Completer;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Context, Helper};
+use tokio::sync::Mutex;
+
+use crate::state::state_chat::Chat;
+
+// Helper del line editor: completamento delle menzioni e messaggi su più righe.
+// Con tab dopo una @ propone i nomi dei membri, come li mostra la chat (nome#abcd se ci sono doppioni)
+pub struct EditorHelper {
+    pub chat: Arc<Mutex<Chat>>,
+}
+
+impl Completer for EditorHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos].rfind(char::is_whitespace).map_or(0, |i| i + 1);
+        let Some(prefix) = line[start..pos].strip_prefix('@') else {
+            return Ok((pos, vec![]));
+        };
+
+        // readline gira fuori dal runtime: se la chat è occupata si rinuncia a completare
+        let Ok(chat) = self.chat.try_lock() else {
+            return Ok((pos, vec![]));
+        };
+
+        let prefix = prefix.to_lowercase();
+        let mut names: Vec<String> = chat
+            .members
+            .iter()
+            .map(|m| chat.display_name(Some(&m.id), &m.username))
+            .filter(|name| name.to_lowercase().starts_with(&prefix))
+            .map(|name| format!("@{} ", name))
+            .collect();
+        names.sort();
+
+        Ok((start, names))
+    }
+}
+
+impl Hinter for EditorHelper {
+    type Hint = String;
+}
+
+impl Highlighter for EditorHelper {}
+
+// Una riga che finisce con \ continua sotto invece di essere inviata
+impl Validator for EditorHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        if ctx.input().ends_with('\\') && !ctx.input().starts_with('/') {
+            return Ok(ValidationResult::Incomplete);
+        }
+        Ok(ValidationResult::Valid(None))
+    }
+}
+
+impl Helper for EditorHelper {}
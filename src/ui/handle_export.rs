@@ -0,0 +1,251 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::ValueEnum;
+use tokio::sync::Mutex;
+
+use crate::state::state_archive::{Archive, ArchivedMessage};
+use crate::state::state_chat::Chat;
+use crate::state::state_node::Node;
+use crate::state::state_search::format_time;
+use crate::ui::handle_format::{sanitize, sanitize_line};
+use crate::ui::handle_input::get_timestamp;
+use crate::ui::handle_output::say;
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum ExportFormat {
+    Json,
+    Text,
+    Html,
+}
+
+impl ExportFormat {
+    pub fn parse(s: &str) -> Option<Self> {
+        <Self as ValueEnum>::from_str(s, true).ok()
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "json",
+            ExportFormat::Text => "txt",
+            ExportFormat::Html => "html",
+        }
+    }
+}
+
+// Senza percorso si scrive <stanza>.<estensione> nella cartella corrente; il nome della stanza
+// arriva dai peer, quindi niente / o .. nel nome del file
+pub fn default_path(room: &str, format: ExportFormat) -> PathBuf {
+    let name: String = room
+        .chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    PathBuf::from(format!("{}.{}", name, format.extension()))
+}
+
+// Storia in chiaro; quello che non si riesce a decifrare resta fuori. Restituisce anche quanti se ne sono saltati
+pub async fn archive(chat: &Chat, node: &Node) -> (Archive, usize) {
+    let mut messages = vec![];
+    let mut skipped = 0;
+
+    for message in chat.all_messages.iter() {
+        match node.keyring.plaintext(message).await {
+            Some(text) => messages.push(ArchivedMessage::new(message, text)),
+            None => skipped += 1,
+        }
+    }
+
+    let archive = Archive {
+        room: chat.room.clone(),
+        exported_at: get_timestamp(),
+        messages,
+    };
+    (archive, skipped)
+}
+
+pub async fn export(format: ExportFormat, path: &Path, chat: &Arc<Mutex<Chat>>, node: &Node) -> Result<(), String> {
+    let (content, exported, skipped) = {
+        let chat_lock = chat.lock().await;
+        let (archive, skipped) = archive(&chat_lock, node).await;
+
+        let content = match format {
+            ExportFormat::Json => serde_json::to_string_pretty(&archive).expect("Failed to serialize"),
+            ExportFormat::Text => to_text(&archive, &chat_lock),
+            ExportFormat::Html => to_html(&archive, &chat_lock),
+        };
+        (content, archive.messages.len(), skipped)
+    };
+
+    tokio::fs::write(path, content)
+        .await
+        .map_err(|e| format!("cannot write {}: {}", path.display(), e))?;
+
+    say!("Exported {} message(s) to {}", exported, path.display());
+    if skipped > 0 {
+        say!("{} message(s) left out: no key to decrypt them", skipped);
+    }
+    Ok(())
+}
+
+// Unisce un archivio JSON alla storia locale: i messaggi già presenti (stesso id) si saltano,
+// quelli nuovi si cifrano con la chiave attuale e restano solo in questo nodo
+pub async fn import(path: &Path, chat: &Arc<Mutex<Chat>>, node: &Node) -> Result<usize, String> {
+    let json = tokio::fs::read(path)
+        .await
+        .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    let archive: Archive = serde_json::from_slice(&json).map_err(|e| format!("not a JSON export: {}", e))?;
+
+    let mut messages = vec![];
+    for archived in archive.messages.into_iter().filter(|m| !m.deleted) {
+        let mut message = archived.into_message();
+        node.keyring.seal(&mut message).await;
+        messages.push(message);
+    }
+
+    let mut chat_lock = chat.lock().await;
+    if archive.room != chat_lock.room {
+        return Err(format!("the export is from room {}, this is {}", archive.room, chat_lock.room));
+    }
+
+    // quello che la retention della stanza non terrebbe non si importa nemmeno
+    let added = chat_lock.merge_messages(messages);
+    chat_lock.prune(get_timestamp());
+
+    let kept: HashSet<&str> = chat_lock.all_messages.iter().map(|m| m.id.as_str()).collect();
+    Ok(added.iter().filter(|id| kept.contains(id.as_str())).count())
+}
+
+// Sottocomando export: ci si collega come al solito, si aspetta il Sync della stanza e si scrive il file
+pub async fn export_main(
+    format: ExportFormat,
+    output: Option<PathBuf>,
+    wait: Duration,
+    chat: Arc<Mutex<Chat>>,
+    node: Node,
+) -> Result<(), String> {
+    if tokio::time::timeout(wait, node.synced.notified()).await.is_err() {
+        return Err(format!("no history received within {}s", wait.as_secs()));
+    }
+
+    let path = match output {
+        Some(path) => path,
+        None => default_path(&chat.lock().await.room, format),
+    };
+    export(format, &path, &chat, &node).await
+}
+
+fn name(archived: &ArchivedMessage, chat: &Chat) -> String {
+    sanitize_line(&chat.display_name(archived.sender_id.as_deref(), &archived.sender))
+}
+
+fn to_text(archive: &Archive, chat: &Chat) -> String {
+    let mut out = format!(
+        "Room {} - exported {} UTC\n\n",
+        sanitize_line(&archive.room),
+        format_time(archive.exported_at)
+    );
+
+    for m in &archive.messages {
+        let prefix = format!("[{}] {}: ", format_time(m.timestamp), name(m, chat));
+        let text = if m.deleted { "(deleted)".to_string() } else { sanitize(&m.text) };
+
+        // le righe successive allineate sotto la prima, come nel terminale
+        let pad = " ".repeat(prefix.chars().count());
+        out.push_str(&prefix);
+        out.push_str(&text.replace('\n', &format!("\n{}", pad)));
+
+        if m.edited {
+            out.push_str(" (edited)");
+        }
+        if !m.reactions.is_empty() {
+            let reactions: Vec<String> = m.reactions.iter().map(|(e, ids)| format!("{} {}", e, ids.len())).collect();
+            out.push_str(&format!("  [{}]", reactions.join(" ")));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+// Un solo file, stile compreso, nessuna risorsa esterna da caricare
+fn to_html(archive: &Archive, chat: &Chat) -> String {
+    let room = escape_html(&sanitize_line(&archive.room));
+    let mut out = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{room}</title>
+<style>
+body {{ font-family: sans-serif; max-width: 48em; margin: 2em auto; color: #222; }}
+.msg {{ padding: .4em 0; border-bottom: 1px solid #eee; }}
+.meta {{ color: #888; font-size: .85em; }}
+.sender {{ font-weight: bold; }}
+.text {{ white-space: pre-wrap; }}
+.reply {{ display: block; color: #888; font-size: .85em; text-decoration: none; }}
+.deleted {{ color: #aaa; font-style: italic; }}
+.reactions {{ font-size: .85em; }}
+</style>
+</head>
+<body>
+<h1>{room}</h1>
+<p class="meta">Exported {exported} UTC, {count} message(s)</p>
+"#,
+        room = room,
+        exported = format_time(archive.exported_at),
+        count = archive.messages.len(),
+    );
+
+    for m in &archive.messages {
+        out.push_str(&format!("<div class=\"msg\" id=\"m-{}\">\n", escape_html(&m.id)));
+
+        if let Some(parent) = &m.reply_to {
+            let parent_name = archive
+                .messages
+                .iter()
+                .find(|p| &p.id == parent)
+                .map_or_else(|| "a message".to_string(), |p| name(p, chat));
+            out.push_str(&format!(
+                "<a class=\"reply\" href=\"#m-{}\">↳ reply to {}</a>\n",
+                escape_html(parent),
+                escape_html(&parent_name)
+            ));
+        }
+
+        out.push_str(&format!(
+            "<span class=\"sender\">{}</span> <span class=\"meta\">{}{}</span>\n",
+            escape_html(&name(m, chat)),
+            format_time(m.timestamp),
+            if m.edited { " (edited)" } else { "" }
+        ));
+
+        if m.deleted {
+            out.push_str("<div class=\"text deleted\">message deleted</div>\n");
+        } else {
+            out.push_str(&format!("<div class=\"text\">{}</div>\n", escape_html(&sanitize(&m.text))));
+        }
+
+        if !m.reactions.is_empty() {
+            let reactions: Vec<String> = m
+                .reactions
+                .iter()
+                .map(|(e, ids)| format!("{} {}", escape_html(e), ids.len()))
+                .collect();
+            out.push_str(&format!("<div class=\"reactions\">{}</div>\n", reactions.join(" · ")));
+        }
+
+        out.push_str("</div>\n");
+    }
+
+    out.push_str("</body>\n</html>\n");
+    out
+}
@@ -0,0 +1,216 @@
+const RESET: &str = "\x1b[0m";
+const MENTIONED: &str = "\x1b[0;33m";
+
+const BOLD: &str = "1";
+const ITALIC: &str = "3";
+const UNDERLINE: &str = "4";
+const CODE: &str = "7";
+const MENTION: &str = "1;36";
+
+// Il testo dei peer non deve mai arrivare al terminale con sequenze di controllo:
+// niente ESC (colori, spostamenti del cursore, titolo della finestra), \r, bidi override...
+// Restano solo gli a capo dei messaggi su più righe
+pub fn sanitize(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => skip_escape(&mut chars),
+            '\n' => out.push('\n'),
+            '\t' => out.push_str("    "),
+            '\u{202a}'..='\u{202e}' | '\u{2066}'..='\u{2069}' => {}
+            c if c.is_control() => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// Per nomi, stati, citazioni: tutto su una riga
+pub fn sanitize_line(text: &str) -> String {
+    sanitize(text).replace('\n', " ")
+}
+
+fn skip_escape(chars: &mut std::iter::Peekable<std::str::Chars>) {
+    match chars.next() {
+        // CSI: parametri fino al byte finale tra @ e ~
+        Some('[') => {
+            for c in chars.by_ref() {
+                if ('@'..='~').contains(&c) {
+                    break;
+                }
+            }
+        }
+        // OSC, DCS...: fino a BEL o ESC \
+        Some(']' | 'P' | '^' | '_') => {
+            while let Some(c) = chars.next() {
+                if c == '\x07' {
+                    break;
+                }
+                if c == '\x1b' {
+                    chars.next_if_eq(&'\\');
+                    break;
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+// Testo già ripulito -> stili del terminale. Le @menzioni si colorano sempre,
+// il resto della sintassi solo se l'utente ha scelto la formattazione
+pub fn render(text: &str, format: bool, mentioned: bool) -> String {
+    let base = if mentioned { MENTIONED } else { RESET };
+    let mut out = String::with_capacity(text.len());
+    let mut i = 0;
+
+    while let Some(c) = text[i..].chars().next() {
+        let rest = &text[i..];
+        let word_start = text[..i].chars().next_back().is_none_or(|p| p.is_whitespace() || "([{\"'".contains(p));
+
+        if format && let Some((len, styled)) = span(rest, word_start, base) {
+            out.push_str(&styled);
+            i += len;
+            continue;
+        }
+
+        if word_start && c == '@' {
+            let len = mention_len(rest);
+            if len > 1 {
+                out.push_str(&style(MENTION, &rest[..len], base));
+                i += len;
+                continue;
+            }
+        }
+
+        out.push(c);
+        i += c.len_utf8();
+    }
+    out
+}
+
+fn style(code: &str, text: &str, base: &str) -> String {
+    format!("\x1b[{}m{}{}", code, text, base)
+}
+
+// Un elemento di formattazione all'inizio di rest: byte consumati e testo con lo stile
+fn span(rest: &str, word_start: bool, base: &str) -> Option<(usize, String)> {
+    if let Some(inner) = rest.strip_prefix('`') {
+        let inner = &inner[..inner.find('`')?];
+        return valid(inner).then(|| (inner.len() + 2, style(CODE, inner, base)));
+    }
+
+    if let Some(inner) = rest.strip_prefix("**") {
+        let inner = &inner[..inner.find("**")?];
+        return tight(inner).then(|| (inner.len() + 4, style(BOLD, inner, base)));
+    }
+
+    // *corsivo* e _corsivo_ solo a parole intere, così nomi_con_underscore restano come sono
+    if word_start && let Some(marker) = rest.chars().next().filter(|c| *c == '*' || *c == '_') {
+        let inner = &rest[1..];
+        let inner = &inner[..inner.find(marker)?];
+        let after = rest[inner.len() + 2..].chars().next();
+        let at_word_end = after.is_none_or(|c| c.is_whitespace() || c.is_ascii_punctuation());
+        return (tight(inner) && at_word_end).then(|| (inner.len() + 2, style(ITALIC, inner, base)));
+    }
+
+    // [testo](url): l'indirizzo si mostra sempre, un link non deve poter nascondere dove porta
+    if let Some(inner) = rest.strip_prefix('[') {
+        let label_end = inner.find("](")?;
+        let label = &inner[..label_end];
+        let url = &inner[label_end + 2..];
+        let url = &url[..url.find(')')?];
+        if !valid(label) || url.is_empty() || url.contains(char::is_whitespace) {
+            return None;
+        }
+        let len = label.len() + url.len() + 4;
+        return Some((len, format!("{} <{}>", style(UNDERLINE, label, base), style(UNDERLINE, url, base))));
+    }
+
+    if word_start && (rest.starts_with("http://") || rest.starts_with("https://")) {
+        let url = rest.split(char::is_whitespace).next()?;
+        let url = url.trim_end_matches(['.', ',', ';', ':', '!', '?', ')', '"', '\'']);
+        return Some((url.len(), style(UNDERLINE, url, base)));
+    }
+
+    None
+}
+
+fn valid(inner: &str) -> bool {
+    !inner.is_empty() && !inner.contains('\n')
+}
+
+// **non è grassetto** se il contenuto comincia o finisce con uno spazio
+fn tight(inner: &str) -> bool {
+    valid(inner) && !inner.starts_with(char::is_whitespace) && !inner.ends_with(char::is_whitespace)
+}
+
+// @nome o @nome#abcd, senza la punteggiatura che lo segue: "@bob," "@bob."
+fn mention_len(rest: &str) -> usize {
+    let name = rest
+        .char_indices()
+        .skip(1)
+        .find(|(_, c)| !(c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | '#')))
+        .map_or(rest, |(i, _)| &rest[..i]);
+    name.trim_end_matches(['.', '#']).len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_strips_csi() {
+        assert_eq!(sanitize("a\x1b[31mred\x1b[0m b"), "ared b");
+        assert_eq!(sanitize("x\x1b[2J\x1b[1;1Hy"), "xy");
+    }
+
+    #[test]
+    fn sanitize_strips_osc() {
+        // titolo della finestra, chiuso da BEL o da ESC \
+        assert_eq!(sanitize("a\x1b]0;pwned\x07b"), "ab");
+        assert_eq!(sanitize("a\x1b]8;;http://evil\x1b\\link\x1b]8;;\x1b\\b"), "alinkb");
+    }
+
+    #[test]
+    fn sanitize_strips_c1_and_carriage_return() {
+        assert_eq!(sanitize("a\u{9b}31mb"), "a31mb");
+        assert_eq!(sanitize("a\u{90}b\u{9c}c"), "abc");
+        assert_eq!(sanitize("fake\rreal"), "fakereal");
+    }
+
+    #[test]
+    fn sanitize_strips_bidi_overrides() {
+        assert_eq!(sanitize("file\u{202e}gpj.exe"), "filegpj.exe");
+        assert_eq!(sanitize("a\u{2066}b\u{2069}c"), "abc");
+    }
+
+    #[test]
+    fn sanitize_keeps_newlines_and_text() {
+        assert_eq!(sanitize("ciao\nè più\tsotto"), "ciao\nè più    sotto");
+        assert_eq!(sanitize_line("one\ntwo"), "one two");
+    }
+
+    #[test]
+    fn render_mentions_and_styles() {
+        assert_eq!(render("hi @bob, ok", false, false), format!("hi \x1b[1;36m@bob{}, ok", RESET));
+        assert_eq!(render("**bold**", true, false), format!("\x1b[1mbold{}", RESET));
+        assert_eq!(render("**bold**", false, false), "**bold**");
+        assert_eq!(render("`c`", true, true), format!("\x1b[7mc{}", MENTIONED));
+    }
+
+    #[test]
+    fn render_keeps_underscores_in_words() {
+        assert_eq!(render("snake_case_name", true, false), "snake_case_name");
+        assert_eq!(render("_it_", true, false), format!("\x1b[3mit{}", RESET));
+    }
+
+    #[test]
+    fn render_shows_link_targets() {
+        assert_eq!(
+            render("[docs](https://x.y)", true, false),
+            format!("\x1b[4mdocs{} <\x1b[4mhttps://x.y{}>", RESET, RESET)
+        );
+    }
+}
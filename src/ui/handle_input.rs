@@ -1,11 +1,21 @@
-use rustyline::DefaultEditor;
 use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+use rustyline::{Editor, Event, EventHandler};
 
 use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, mpsc};
 
+use crate::handler::handle_presence::set_presence;
 use crate::state::state_chat::Connections;
+use crate::state::state_discovery::DiscoveredPeers;
+use crate::state::state_events::ChatEvent;
+use crate::state::state_node::Node;
+use crate::state::state_presence::{IDLE_CHECK, Presence, Status};
+use crate::ui::handle_command::handle_command;
+use crate::ui::handle_editor::EditorHelper;
+use crate::ui::handle_output::{print_message, print_notice, say, view};
+use crate::ui::handle_typing::{TypingHandler, emit_typing};
 use crate::{
     state::state_packets::Packet,
     state_chat::{Chat, Member, Message},
@@ -14,43 +24,134 @@ use crate::{
 pub async fn handle_input(
     chat: Arc<Mutex<Chat>>,
     member: Arc<Member>,
+    node: Node,
     connections: Connections,
+    discovered: DiscoveredPeers,
 ) {
-    let mut rl = DefaultEditor::new().expect("Failed to create editor");
-    println!("--- Chat started ---");
+    let mut rl: Editor<EditorHelper, DefaultHistory> = Editor::new().expect("Failed to create editor");
+    rl.set_helper(Some(EditorHelper {
+        chat: Arc::clone(&chat),
+    }));
+
+    let (typing_tx, typing_rx) = mpsc::unbounded_channel::<bool>();
+    rl.bind_sequence(Event::Any, EventHandler::Conditional(Box::new(TypingHandler { tx: typing_tx })));
+    tokio::spawn(emit_typing(typing_rx, connections.clone()));
+
+    say!("--- Chat started ---");
+
+    tokio::spawn(watch_idle(
+        Arc::clone(&chat),
+        Arc::clone(&member),
+        node.clone(),
+        connections.clone(),
+    ));
 
     loop {
         let readline = rl.readline(">> ");
 
         match readline {
             Ok(line) => {
-                let mut chat_lock = chat.lock().await;
-
-                let message: Message =
-                    Message::new((*member).username.clone(), line, get_timestamp());
-                chat_lock.add_message(message.clone());
+                if node.activity.touch().await {
+                    let message = chat.lock().await.me(&member).status_message;
+                    set_presence(Presence { status: Status::Online, message }, &chat, &member, &connections).await;
+                    print_notice("You are back online");
+                }
 
-                let packet: Packet = Packet::UserMessage(message);
+                // chi scrive ha visto quello che è arrivato nel frattempo
+                for (message, sender) in node.unread.take().await {
+                    connections.send_to(&sender, Packet::Ack { message, read: true }).await;
+                }
 
-                {
-                    let conns = connections.connections.lock().await;
-                    for c in conns.iter() {
-                        let _ = c.send(packet.clone());
-                    }
+                if line.starts_with('/') {
+                    handle_command(&line, &chat, &member, &node, &connections, &discovered).await;
+                    continue;
                 }
+
+                // righe unite con \ a fine riga: nel messaggio resta solo l'a capo
+                let text = line.replace("\\\n", "\n");
+                send_message(text, None, &chat, &member, &node, &connections).await;
             }
             Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => {
-                println!("Exiting chat...");
+                say!("Exiting chat...");
                 break;
             }
             Err(err) => {
-                println!("Error: {:?}", err);
+                say!("Error: {:?}", err);
                 break;
             }
         }
     }
 }
 
+// La riga digitata resta a schermo com'è; una risposta invece si ristampa col suo handle e la citazione.
+// Restituisce l'id del messaggio, per chi lo manda senza terminale
+pub async fn send_message(
+    text: String,
+    reply_to: Option<String>,
+    chat: &Arc<Mutex<Chat>>,
+    member: &Arc<Member>,
+    node: &Node,
+    connections: &Connections,
+) -> String {
+    let (me, retention) = {
+        let chat_lock = chat.lock().await;
+        (chat_lock.me(member), chat_lock.retention.clone())
+    };
+    let mut message: Message = Message::new(&me, text, get_timestamp());
+    message.reply_to = reply_to;
+    message.expires = retention.expires(message.timestamp);
+    node.keyring.seal(&mut message).await;
+
+    let mut chat_lock = chat.lock().await;
+    chat_lock.add_message(message.clone());
+    chat_lock.prune(message.timestamp);
+
+    let mut plain = node.keyring.reveal(&message).await;
+    chat_lock.label(&mut plain);
+    node.events.publish(ChatEvent::message(&plain));
+
+    if message.reply_to.is_some() {
+        print_message(&view(&message, &chat_lock, node, &member.id).await);
+    } else {
+        node.handles.assign(&message.id).await;
+    }
+
+    let id = message.id.clone();
+    let packet: Packet = Packet::UserMessage(message);
+
+    // un canale chiuso è una connessione finita: la si toglie e si avvisa
+    let failed = {
+        let mut conns = connections.connections.lock().await;
+        let before = conns.len();
+        conns.retain(|c| c.send(packet.clone()).is_ok());
+        before - conns.len()
+    };
+
+    if failed > 0 {
+        print_notice(&format!("Message not delivered to {} disconnected peer(s)", failed));
+    }
+    id
+}
+
+// Dopo AWAY_AFTER senza input si passa in away; solo da online, un busy messo a mano resta
+async fn watch_idle(chat: Arc<Mutex<Chat>>, member: Arc<Member>, node: Node, connections: Connections) {
+    loop {
+        tokio::time::sleep(IDLE_CHECK).await;
+
+        let me = chat.lock().await.me(&member);
+        if me.status != Status::Online || !node.activity.went_idle().await {
+            continue;
+        }
+
+        let presence = Presence {
+            status: Status::Away,
+            message: me.status_message,
+        };
+        set_presence(presence, &chat, &member, &connections).await;
+        print_notice("You are now away (idle)");
+    }
+}
+
 pub fn get_timestamp() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
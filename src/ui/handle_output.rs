@@ -1,18 +1,219 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::io::{self, Write};
-use crate::state::state_chat::Message;
+use std::sync::{Arc, OnceLock};
 
-pub fn print_all_messages(all_messages: Vec<std::sync::Arc<Message>>) {
-    print!("\r\x1b[2K"); // \r sposta cursore all'inizio riga (prima di >>) e \x1b[2K cancella tutta la riga
-    for message in all_messages {
-        println!("[{}]: {}", message.sender, message.text);
+use crate::state::state_chat::{Chat, Message};
+use crate::state::state_mentions::mentions_member;
+use crate::state::state_node::Node;
+use crate::ui::handle_format::{render, sanitize, sanitize_line};
+
+const QUOTE_LEN: usize = 40;
+const MAX_DEPTH: usize = 4;
+const CLEAR_LINE: &str = "\r\x1b[2K"; // \r sposta cursore all'inizio riga (prima di >>) e \x1b[2K cancella tutta la riga
+
+// Dove va quello che stampa il nodo: il terminale col prompt, righe semplici per il daemon,
+// che può aver perso il terminale da cui è partito, o stderr per gli script, che su stdout
+// vogliono solo JSON
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Output {
+    Terminal,
+    Log,
+    Stderr,
+}
+
+static OUTPUT: OnceLock<Output> = OnceLock::new();
+
+// Una volta sola, all'avvio, prima che il nodo stampi qualcosa
+pub fn set_output(output: Output) {
+    let _ = OUTPUT.set(output);
+}
+
+pub fn on_terminal() -> bool {
+    output() == Output::Terminal
+}
+
+fn output() -> Output {
+    OUTPUT.get().copied().unwrap_or(Output::Terminal)
+}
+
+// Come println!, ma un terminale chiuso (EIO) o una pipe chiusa non fanno cadere il nodo.
+// Fuori dal terminale non c'è prompt da cancellare
+pub fn write_line(args: fmt::Arguments) {
+    let line = args.to_string();
+    let _ = match output() {
+        Output::Terminal => writeln!(io::stdout(), "{}", line),
+        Output::Log => writeln!(io::stdout(), "{}", line.trim_start_matches(CLEAR_LINE)),
+        Output::Stderr => writeln!(io::stderr(), "{}", line.trim_start_matches(CLEAR_LINE)),
+    };
+}
+
+macro_rules! say {
+    ($($arg:tt)*) => {
+        $crate::ui::handle_output::write_line(format_args!($($arg)*))
+    };
+}
+pub(crate) use say;
+
+// Un messaggio pronto da stampare: testo in chiaro, nome da mostrare e posizione nel thread
+pub struct View {
+    pub handle: u32,
+    pub sender: String,
+    pub text: String,
+    pub quote: Option<String>, // a chi risponde, quando il messaggio padre non è appena sopra
+    pub depth: usize,
+    pub mentioned: bool, // qualcun altro ci ha chiamato con @nome
+    pub format: bool,
+    pub time: Option<String>, // data accanto al nome, nei risultati di /search
+}
+
+pub async fn view(message: &Message, chat: &Chat, node: &Node, my_id: &str) -> View {
+    let mut shown = node.keyring.reveal(message).await;
+    let mentioned = message.sender_id.as_deref() != Some(my_id)
+        && chat
+            .member(my_id)
+            .is_some_and(|me| mentions_member(&shown.text, &me.username, my_id));
+    chat.label(&mut shown);
+    chat.mark(&mut shown, my_id);
+
+    // reazioni compatte in coda: [👍 2 🎉 1]
+    if !message.reactions.is_empty() {
+        let reactions: Vec<String> = message
+            .reactions
+            .iter()
+            .map(|(emoji, ids)| format!("{} {}", emoji, ids.len()))
+            .collect();
+        shown.text = format!("{}  [{}]", shown.text, reactions.join(" "));
+    }
+
+    let quote = match &message.reply_to {
+        Some(parent) => Some(quote(parent, chat, node).await),
+        None => None,
+    };
+
+    View {
+        handle: node.handles.assign(&message.id).await,
+        sender: sanitize_line(&shown.sender),
+        text: sanitize(&shown.text),
+        quote,
+        depth: 0,
+        mentioned,
+        format: node.formatting.enabled().await,
+        time: None,
     }
-    print!(">> ");
-    io::stdout().flush().unwrap();
 }
 
-pub fn print_message(message: &Message) {
-    print!("\r\x1b[2K");
-    println!("[{}]: {}", message.sender, message.text);
-    print!(">> ");
-    io::stdout().flush().unwrap();
-}
\ No newline at end of file
+async fn quote(parent_id: &str, chat: &Chat, node: &Node) -> String {
+    let Some(parent) = chat.all_messages.iter().find(|m| m.id == parent_id) else {
+        return "↳ reply to a message that is not here".to_string();
+    };
+
+    let mut shown = node.keyring.reveal(parent).await;
+    chat.label(&mut shown);
+
+    let full = sanitize_line(&shown.text);
+    let mut text: String = full.chars().take(QUOTE_LEN).collect();
+    if full.chars().count() > QUOTE_LEN {
+        text.push('…');
+    }
+
+    format!(
+        "↳ #{} {}: {}",
+        node.handles.assign(&parent.id).await,
+        sanitize_line(&shown.sender),
+        text
+    )
+}
+
+// Storia in ordine di thread: ogni risposta subito sotto il suo messaggio, rientrata.
+// Se il padre non è tra i messaggi mostrati la risposta parte da sinistra con la citazione
+pub async fn thread_views(messages: &[Arc<Message>], chat: &Chat, node: &Node, my_id: &str) -> Vec<View> {
+    let ids: HashSet<&str> = messages.iter().map(|m| m.id.as_str()).collect();
+    let mut children: HashMap<&str, Vec<usize>> = HashMap::new();
+    let mut roots = vec![];
+
+    for (i, m) in messages.iter().enumerate() {
+        match m.reply_to.as_deref() {
+            Some(parent) if ids.contains(parent) && parent != m.id => children.entry(parent).or_default().push(i),
+            _ => roots.push(i),
+        }
+    }
+
+    let mut order = vec![];
+    let mut stack: Vec<(usize, usize)> = roots.into_iter().rev().map(|i| (i, 0)).collect();
+    while let Some((i, depth)) = stack.pop() {
+        order.push((i, depth));
+        if let Some(replies) = children.get(messages[i].id.as_str()) {
+            stack.extend(replies.iter().rev().map(|r| (*r, depth + 1)));
+        }
+    }
+
+    let mut views = Vec::with_capacity(order.len());
+    for (i, depth) in order {
+        let mut v = view(&messages[i], chat, node, my_id).await;
+        if depth > 0 {
+            v.quote = None;
+        }
+        v.depth = depth.min(MAX_DEPTH);
+        views.push(v);
+    }
+    views
+}
+
+// Le righe successive di un messaggio su più righe si allineano sotto la prima
+fn print_view(view: &View) {
+    let indent = "  ".repeat(view.depth);
+    if let Some(quote) = &view.quote {
+        say!("{}{}", indent, quote);
+    }
+
+    let prefix = match &view.time {
+        Some(time) => format!("#{} {} [{}]: ", view.handle, time, view.sender),
+        None => format!("#{} [{}]: ", view.handle, view.sender),
+    };
+    let (color, reset) = if view.mentioned { ("\x1b[33m", "\x1b[0m") } else { ("", "") };
+
+    let text = render(&view.text, view.format, view.mentioned);
+    for (i, line) in text.split('\n').enumerate() {
+        if i == 0 {
+            say!("{}{}{}{}{}", indent, color, prefix, line, reset);
+        } else {
+            say!("{}{}{}{}{}", indent, color, " ".repeat(prefix.chars().count()), line, reset);
+        }
+    }
+}
+
+pub fn print_all_messages(views: Vec<View>) {
+    clear_prompt();
+    for view in views {
+        print_view(&view);
+    }
+    restore_prompt();
+}
+
+pub fn print_message(view: &View) {
+    clear_prompt();
+    print_view(view);
+    restore_prompt();
+}
+
+// Avvisi di sistema (kick, ban...) stampati come i messaggi, senza rompere il prompt
+pub fn print_notice(text: &str) {
+    clear_prompt();
+    say!("* {}", sanitize_line(text));
+    restore_prompt();
+}
+
+fn clear_prompt() {
+    if on_terminal() {
+        let _ = write!(io::stdout(), "{}", CLEAR_LINE);
+    }
+}
+
+fn restore_prompt() {
+    if on_terminal() {
+        let mut stdout = io::stdout();
+        let _ = write!(stdout, ">> ");
+        let _ = stdout.flush();
+    }
+}
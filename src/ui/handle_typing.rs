@@ -0,0 +1,59 @@
+use std::time::Instant;
+
+use rustyline::{Cmd, ConditionalEventHandler, Event, EventContext, KeyCode, RepeatCount};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+use crate::state::state_chat::Connections;
+use crate::state::state_packets::Packet;
+use crate::state::state_typing::TYPING_INTERVAL;
+
+// Legato a Event::Any: rustyline lo chiama a ogni tasto, noi segnaliamo e lasciamo fare il comportamento normale
+pub struct TypingHandler {
+    pub tx: UnboundedSender<bool>, // false quando si preme invio
+}
+
+impl ConditionalEventHandler for TypingHandler {
+    fn handle(&self, evt: &Event, _: RepeatCount, _: bool, ctx: &EventContext) -> Option<Cmd> {
+        let key = evt.get(0)?;
+        if key.0 == KeyCode::Enter {
+            let _ = self.tx.send(false);
+            return None;
+        }
+
+        let typed = match key.0 {
+            KeyCode::Char(c) => !(ctx.line().is_empty() && c == '/'),
+            KeyCode::Backspace => !ctx.line().is_empty(),
+            _ => false,
+        };
+
+        // i comandi non sono messaggi, nessuno deve vederci scrivere
+        if typed && !ctx.line().starts_with('/') {
+            let _ = self.tx.send(true);
+        }
+
+        None
+    }
+}
+
+// Riduce i tasti a un Packet::Typing ogni TYPING_INTERVAL; dopo l'invio si riparte da capo,
+// perché chi riceve il messaggio smette di mostrarci mentre scriviamo
+pub async fn emit_typing(mut rx: UnboundedReceiver<bool>, connections: Connections) {
+    let mut last_sent: Option<Instant> = None;
+
+    while let Some(typed) = rx.recv().await {
+        if !typed {
+            last_sent = None;
+            continue;
+        }
+
+        if last_sent.is_some_and(|t| t.elapsed() < TYPING_INTERVAL) {
+            continue;
+        }
+        last_sent = Some(Instant::now());
+
+        let conns = connections.connections.lock().await;
+        for c in conns.iter() {
+            let _ = c.send(Packet::Typing);
+        }
+    }
+}
@@ -1,2 +1,7 @@
+pub mod handle_command;
+pub mod handle_editor;
+pub mod handle_export;
+pub mod handle_format;
 pub mod handle_input;
-pub mod handle_output;
\ No newline at end of file
+pub mod handle_output;
+pub mod handle_typing;